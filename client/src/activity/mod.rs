@@ -0,0 +1,89 @@
+//! Recent bot activity for the dashboard, kept in state as a bounded log.
+use crate::gcal::helpers::Action;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+// oldest entries are dropped beyond this
+pub const MAX_ACTIVITY: usize = 200;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Activity {
+    // rfc3339
+    pub at: String,
+    pub chat_id: i64,
+    pub user_id: u64,
+    pub user_name: String,
+    pub guest: bool,
+    pub text: String,
+    pub reply: String,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Outcome {
+    Answered,
+    Listed,
+    Scheduled {
+        event_id: Option<String>,
+        summary: Option<String>,
+    },
+    Incomplete,
+    Rejected,
+    Mismatch,
+    Undone,
+    // waiting for a confirm
+    Proposed {
+        summary: Option<String>,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+impl From<&Action> for Outcome {
+    fn from(action: &Action) -> Self {
+        match action {
+            Action::None => Outcome::Answered,
+            Action::Listed => Outcome::Listed,
+            Action::Scheduled(event) => Outcome::Scheduled {
+                event_id: event.id.clone(),
+                summary: event.summary.clone(),
+            },
+            Action::Incomplete => Outcome::Incomplete,
+            Action::Rejected => Outcome::Rejected,
+            Action::Mismatch => Outcome::Mismatch,
+            Action::Undone => Outcome::Undone,
+            Action::Proposed(proposal) => Outcome::Proposed {
+                summary: proposal.event.summary.clone(),
+            },
+        }
+    }
+}
+
+pub fn record(log: &mut VecDeque<Activity>, activity: Activity) {
+    log.push_back(activity);
+    while log.len() > MAX_ACTIVITY {
+        log.pop_front();
+    }
+}
+
+/// The latest message of every guest whose conversation hasn't ended in a
+/// booking or a rejection yet, newest first.
+pub fn pending_guest_requests(log: &VecDeque<Activity>) -> Vec<&Activity> {
+    let mut latest: HashMap<u64, &Activity> = HashMap::new();
+    for activity in log.iter().filter(|activity| activity.guest) {
+        latest.insert(activity.user_id, activity);
+    }
+    let mut pending: Vec<&Activity> = latest
+        .into_values()
+        .filter(|activity| {
+            !matches!(
+                activity.outcome,
+                Outcome::Scheduled { .. } | Outcome::Rejected
+            )
+        })
+        .collect();
+    pending.sort_by(|a, b| b.at.cmp(&a.at));
+    pending
+}
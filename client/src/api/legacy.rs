@@ -0,0 +1,93 @@
+//! The paths the UI used before routes moved under `API_PREFIX`, kept so scripts
+//! written against them still work. Each runs its `/api/v1` counterpart and
+//! answers in the old shape, `{"State": {"state": ...}}`, `{"Url": {"url": ...}}`
+//! or `{"Error": {"error": ...}}`:
+//!
+//! - `/status` is `GET /api/v1/status`
+//! - `/generate` is `POST /api/v1/google/connect`
+//! - `/disconnect` is `POST /api/v1/google/disconnect`
+//! - `/submit_config` is `POST /api/v1/config`
+//! - `/prompt` is `PUT /api/v1/schedule_rules`, with `new_prompt` for `schedule_rules`
+//!
+//! Like before, the method isn't checked. New clients should use `/api/v1`.
+use super::{
+    connect_google, disconnect_google, get_status, json_body, replace_schedule_rules, set_config,
+    ApiError, ApiRequest,
+};
+use crate::{backend::Backend, State};
+use kinode_process_lib::http::{IncomingHttpRequest, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+
+pub const PATHS: [&str; 5] = [
+    "/status",
+    "/generate",
+    "/disconnect",
+    "/submit_config",
+    "/prompt",
+];
+
+#[derive(Debug, Deserialize)]
+struct PromptRequest {
+    new_prompt: String,
+}
+
+/// Status and body of the old answer, if `incoming` is for one of the old paths.
+pub fn dispatch<B: Backend>(
+    backend: &B,
+    state: &mut State,
+    incoming: &IncomingHttpRequest,
+) -> Option<(StatusCode, Vec<u8>)> {
+    let path = incoming.path().ok()?;
+    let request = ApiRequest {
+        params: HashMap::new(),
+        query: HashMap::new(),
+    };
+    let (kind, result) = match path.as_str() {
+        "/status" => ("State", get_status(backend, state, &request)),
+        "/generate" => ("Url", connect_google(backend, state, &request)),
+        "/disconnect" => ("State", disconnect_google(backend, state, &request)),
+        "/submit_config" => ("State", set_config(backend, state, &request)),
+        "/prompt" => (
+            "State",
+            json_body::<PromptRequest>()
+                .and_then(|body| replace_schedule_rules(state, &body.new_prompt)),
+        ),
+        _ => return None,
+    };
+    Some(answer(kind, result))
+}
+
+/// The new handler's answer wrapped the old way, under `kind`.
+fn answer(kind: &str, result: Result<Vec<u8>, ApiError>) -> (StatusCode, Vec<u8>) {
+    let (status, body) = match result.and_then(|body| {
+        serde_json::from_slice::<serde_json::Value>(&body)
+            .map_err(|e| ApiError::from(anyhow::Error::from(e)))
+    }) {
+        // the new bodies are the old variant's one field, `{"url": ...}` already
+        Ok(body) if kind == "Url" => (StatusCode::OK, json!({ kind: body })),
+        Ok(body) => (StatusCode::OK, json!({ kind: { "state": body } })),
+        Err(e) => (e.status, json!({ "Error": { "error": e.message } })),
+    };
+    (status, serde_json::to_vec(&body).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_keep_their_old_shape() {
+        let (status, body) = answer("State", Ok(br#"{"timezone":"UTC"}"#.to_vec()));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, br#"{"State":{"state":{"timezone":"UTC"}}}"#);
+
+        let (_, body) = answer("Url", Ok(br#"{"url":"https://login"}"#.to_vec()));
+        assert_eq!(body, br#"{"Url":{"url":"https://login"}}"#);
+
+        let (status, body) = answer("State", Err(ApiError::bad_request("no")));
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, br#"{"Error":{"error":"no"}}"#);
+    }
+}
@@ -0,0 +1,1237 @@
+//! The HTTP API for the UI and scripts. Routes live under `API_PREFIX`, take and
+//! return JSON, and errors come back as `{"error": "..."}` with a matching status.
+//! `GET {API_PREFIX}/openapi.json` describes every route. The unversioned paths
+//! from before still answer, see `legacy`.
+use crate::{
+    activity,
+    attendees::Notify,
+    audit::{self, RETENTION_DAYS},
+    backend::{Backend, Kinode},
+    disconnect,
+    gcal::{
+        helpers::{
+            create_event, delete_event, get_event, get_events_from_primary_calendar,
+            is_valid_email, schedule_with_meeting, update_event, BookingOptions, Proposal,
+        },
+        Event, EventAttendees, EventCalendarDate, SendUpdates,
+    },
+    meetings::{self, Solution},
+    request_auth_url, save, set_rsvp_alerts, set_tokens,
+    stt::SttProvider,
+    tts, with_google_token, zoom_token, State, StatusView, Tokens,
+};
+use chrono::{DateTime, Duration, NaiveDate, SecondsFormat, Utc};
+use kinode_process_lib::{
+    get_blob,
+    http::{self, send_response, IncomingHttpRequest, Method, StatusCode},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use url::Url;
+
+mod legacy;
+
+pub const API_PREFIX: &str = "/api/v1";
+
+// schedule rules end up in every guest prompt, keep them reasonable.
+const MAX_RULES_LEN: usize = 4000;
+// events listed when the range is left out
+const DEFAULT_RANGE_DAYS: i64 = 7;
+const MAX_RANGE_DAYS: i64 = 366;
+const DEFAULT_ACTIVITY_LIMIT: usize = 50;
+const DEFAULT_AUDIT_LIMIT: usize = 100;
+const MAX_AUDIT_LIMIT: usize = 1000;
+// long voice notes on a slow local model still shouldn't hang the process
+const MAX_STT_TIMEOUT_SECS: u64 = 300;
+
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ErrorBody {
+    error: String,
+}
+
+fn json_body<T: DeserializeOwned>() -> Result<T, ApiError> {
+    let body = get_blob().map(|blob| blob.bytes).unwrap_or_default();
+    serde_json::from_slice(&body)
+        .map_err(|e| ApiError::bad_request(format!("invalid request body: {}", e)))
+}
+
+/// What a handler gets besides the body: `:name` segments of the route path and
+/// the query string.
+pub struct ApiRequest {
+    params: HashMap<String, String>,
+    query: HashMap<String, String>,
+}
+
+impl ApiRequest {
+    fn param(&self, name: &str) -> Result<&str, ApiError> {
+        self.params
+            .get(name)
+            .map(|value| value.as_str())
+            .ok_or_else(|| ApiError::bad_request(format!("missing {}", name)))
+    }
+
+    fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .get(name)
+            .map(|value| value.as_str())
+            .filter(|value| !value.is_empty())
+    }
+}
+
+type Handler<B> = fn(&B, &mut State, &ApiRequest) -> Result<Vec<u8>, ApiError>;
+
+struct Route<B> {
+    method: Method,
+    // relative to API_PREFIX, `:name` segments match anything
+    path: &'static str,
+    handler: Handler<B>,
+    summary: &'static str,
+    // schema names from `schemas()`
+    request: Option<&'static str>,
+    response: &'static str,
+}
+
+fn routes<B: Backend>() -> Vec<Route<B>> {
+    vec![
+        Route {
+            method: Method::GET,
+            path: "/status",
+            handler: get_status,
+            summary: "Connection state and settings, credentials only as configured or not",
+            request: None,
+            response: "Status",
+        },
+        Route {
+            method: Method::POST,
+            path: "/google/connect",
+            handler: connect_google,
+            summary: "Login url for a google account, from the given oauth node",
+            request: Some("ConnectRequest"),
+            response: "ConnectResponse",
+        },
+        Route {
+            method: Method::POST,
+            path: "/google/disconnect",
+            handler: disconnect_google,
+            summary: "Forget the google token and revoke it with the oauth node",
+            request: None,
+            response: "Status",
+        },
+        Route {
+            method: Method::POST,
+            path: "/config",
+            handler: set_config,
+            summary: "Set api keys, missing ones are left as they are",
+            request: Some("ConfigRequest"),
+            response: "Status",
+        },
+        Route {
+            method: Method::PUT,
+            path: "/schedule_rules",
+            handler: set_schedule_rules,
+            summary: "Replace the rules guests are scheduled by",
+            request: Some("ScheduleRulesRequest"),
+            response: "Status",
+        },
+        Route {
+            method: Method::PUT,
+            path: "/settings",
+            handler: set_settings,
+            summary: "Change bot behaviour, missing fields are left as they are",
+            request: Some("SettingsRequest"),
+            response: "Status",
+        },
+        Route {
+            method: Method::GET,
+            path: "/events",
+            handler: list_events,
+            summary: "Events between `from` and `to` (RFC 3339), the next week by default",
+            request: None,
+            response: "Events",
+        },
+        Route {
+            method: Method::POST,
+            path: "/events",
+            handler: add_event,
+            summary: "Create an event",
+            request: Some("CreateEventRequest"),
+            response: "Event",
+        },
+        Route {
+            method: Method::GET,
+            path: "/events/:id",
+            handler: show_event,
+            summary: "One event",
+            request: None,
+            response: "Event",
+        },
+        Route {
+            method: Method::PATCH,
+            path: "/events/:id",
+            handler: edit_event,
+            summary: "Change the given fields of an event",
+            request: Some("UpdateEventRequest"),
+            response: "Event",
+        },
+        Route {
+            method: Method::DELETE,
+            path: "/events/:id",
+            handler: remove_event,
+            summary:
+                "Delete an event, `send_updates` (all, externalOnly or none) says who google emails",
+            request: None,
+            response: "Deleted",
+        },
+        Route {
+            method: Method::GET,
+            path: "/guest_requests",
+            handler: list_guest_requests,
+            summary: "Latest message of each guest still waiting on a booking",
+            request: None,
+            response: "ActivityList",
+        },
+        Route {
+            method: Method::GET,
+            path: "/activity",
+            handler: list_activity,
+            summary: "What the bot was asked and did, newest first, up to `limit`",
+            request: None,
+            response: "ActivityList",
+        },
+        Route {
+            method: Method::GET,
+            path: "/audit",
+            handler: list_audit,
+            summary: "Audit log, newest first. Filters: `from`/`to` days (YYYY-MM-DD, \
+                      the last week by default), `user_id`, `limit`",
+            request: None,
+            response: "AuditList",
+        },
+        Route {
+            method: Method::GET,
+            path: "/openapi.json",
+            handler: get_openapi,
+            summary: "This description",
+            request: None,
+            response: "OpenApi",
+        },
+    ]
+}
+
+/// Binds every route path, behind node login. Kinode binds `:name` segments as
+/// wildcards, matching is redone in `dispatch`.
+pub fn bind() -> anyhow::Result<()> {
+    let mut paths: Vec<String> = routes::<Kinode>()
+        .iter()
+        .map(|route| format!("{}{}", API_PREFIX, route.path))
+        .chain(legacy::PATHS.iter().map(|path| path.to_string()))
+        .collect();
+    paths.sort();
+    paths.dedup();
+    for path in paths {
+        http::bind_http_path(path, true, false)?;
+    }
+    Ok(())
+}
+
+/// Routes the request and always answers it.
+pub fn handle(backend: &impl Backend, state: &mut State, incoming: &IncomingHttpRequest) {
+    let answer = legacy::dispatch(backend, state, incoming);
+    let (status, body) = answer.unwrap_or_else(|| match dispatch(backend, state, incoming) {
+        Ok(body) => (StatusCode::OK, body),
+        Err(e) => (
+            e.status,
+            serde_json::to_vec(&ErrorBody { error: e.message }).unwrap_or_default(),
+        ),
+    });
+    let headers = HashMap::from([("Content-Type".to_string(), "application/json".to_string())]);
+    send_response(status, Some(headers), body);
+}
+
+fn dispatch<B: Backend>(
+    backend: &B,
+    state: &mut State,
+    incoming: &IncomingHttpRequest,
+) -> Result<Vec<u8>, ApiError> {
+    let path = incoming.path()?;
+    let method = incoming.method()?;
+    let Some(path) = path.strip_prefix(API_PREFIX) else {
+        return Err(ApiError::not_found(format!("no such path: {}", path)));
+    };
+
+    let mut allowed = vec![];
+    for route in routes() {
+        let Some(params) = match_path(route.path, path) else {
+            continue;
+        };
+        if route.method != method {
+            allowed.push(route.method.to_string());
+            continue;
+        }
+        let request = ApiRequest {
+            params,
+            query: incoming.query_params().clone(),
+        };
+        return (route.handler)(backend, state, &request);
+    }
+
+    if allowed.is_empty() {
+        Err(ApiError::not_found(format!("no such path: {}", path)))
+    } else {
+        Err(ApiError::new(
+            StatusCode::METHOD_NOT_ALLOWED,
+            format!("{} not allowed, use {}", method, allowed.join(", ")),
+        ))
+    }
+}
+
+fn match_path(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
+    let pattern: Vec<&str> = pattern.trim_end_matches('/').split('/').collect();
+    let path: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+    if pattern.len() != path.len() {
+        return None;
+    }
+    let mut params = HashMap::new();
+    for (expected, actual) in pattern.iter().zip(path) {
+        match expected.strip_prefix(':') {
+            Some(name) if !actual.is_empty() => {
+                params.insert(name.to_string(), actual.to_string());
+            }
+            _ if expected == &actual => {}
+            _ => return None,
+        }
+    }
+    Some(params)
+}
+
+fn ok<T: Serialize>(value: &T) -> Result<Vec<u8>, ApiError> {
+    serde_json::to_vec(value).map_err(|e| ApiError::from(anyhow::Error::from(e)))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ConnectRequest {
+    // node running the oauth process, e.g. appattacc.os
+    target: String,
+    #[serde(default)]
+    scopes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ConnectResponse {
+    url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ScheduleRulesRequest {
+    schedule_rules: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SettingsRequest {
+    // preview bookings with Confirm/Edit/Cancel buttons before writing them
+    #[serde(default)]
+    confirm_mode: Option<bool>,
+    // answer voice messages with a voice note
+    #[serde(default)]
+    voice_replies: Option<bool>,
+    // openai-compatible speech endpoint, empty for openai's
+    #[serde(default)]
+    tts_url: Option<String>,
+    #[serde(default)]
+    tts_model: Option<String>,
+    #[serde(default)]
+    tts_voice: Option<String>,
+    // a whisper.cpp server to transcribe with, empty for openai
+    #[serde(default)]
+    whisper_url: Option<String>,
+    #[serde(default)]
+    stt_timeout_secs: Option<u64>,
+    // reply "I heard: ..." before acting on a voice message
+    #[serde(default)]
+    echo_transcripts: Option<bool>,
+    // meeting link events with attendees get
+    #[serde(default)]
+    meeting_default: Option<Solution>,
+    // empty for meet.jit.si
+    #[serde(default)]
+    jitsi_url: Option<String>,
+    // the link for the custom kind, empty to remove it
+    #[serde(default)]
+    meeting_link: Option<String>,
+    // message the owner when attendees accept or decline
+    #[serde(default)]
+    rsvp_alerts: Option<bool>,
+    // who google emails, per kind of change
+    #[serde(default)]
+    notify: Option<Notify>,
+    // keep http bodies in the audit log, secrets redacted
+    #[serde(default)]
+    audit_bodies: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CreateEventRequest {
+    summary: String,
+    #[serde(default)]
+    description: String,
+    // RFC 3339
+    start: String,
+    end: String,
+    // IANA name, the calendar's own when missing
+    #[serde(default)]
+    timezone: Option<String>,
+    #[serde(default)]
+    attendees: Vec<String>,
+    // add a meeting link, of the default kind unless `conference` says otherwise
+    #[serde(default)]
+    meeting: bool,
+    #[serde(default)]
+    conference: Option<Solution>,
+    // who google emails, the booking setting by default
+    #[serde(default)]
+    send_updates: Option<SendUpdates>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct UpdateEventRequest {
+    summary: Option<String>,
+    description: Option<String>,
+    start: Option<String>,
+    end: Option<String>,
+    timezone: Option<String>,
+    // replaces the attendee list
+    attendees: Option<Vec<String>>,
+    // who google emails, by default the invite setting when attendees change
+    // and no one otherwise
+    send_updates: Option<SendUpdates>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Deleted {
+    id: String,
+}
+
+fn get_status<B: Backend>(
+    _backend: &B,
+    state: &mut State,
+    _request: &ApiRequest,
+) -> Result<Vec<u8>, ApiError> {
+    ok(&StatusView::from(&*state))
+}
+
+fn connect_google<B: Backend>(
+    _backend: &B,
+    state: &mut State,
+    _request: &ApiRequest,
+) -> Result<Vec<u8>, ApiError> {
+    let body: ConnectRequest = json_body()?;
+    let target = body.target.trim();
+    if target.is_empty() || target.contains(char::is_whitespace) {
+        return Err(ApiError::bad_request("target must be a node name"));
+    }
+    if body.scopes.iter().any(|scope| scope.trim().is_empty()) {
+        return Err(ApiError::bad_request("scopes can't be empty"));
+    }
+    let url = request_auth_url(state, target.to_string(), body.scopes)?;
+    ok(&ConnectResponse { url })
+}
+
+fn disconnect_google<B: Backend>(
+    _backend: &B,
+    state: &mut State,
+    _request: &ApiRequest,
+) -> Result<Vec<u8>, ApiError> {
+    disconnect(state)?;
+    ok(&StatusView::from(&*state))
+}
+
+fn set_config<B: Backend>(
+    _backend: &B,
+    state: &mut State,
+    _request: &ApiRequest,
+) -> Result<Vec<u8>, ApiError> {
+    let tokens: Tokens = json_body()?;
+    let all = [&tokens.telegram, &tokens.openai, &tokens.groq];
+    if all
+        .iter()
+        .any(|token| token.as_ref().is_some_and(|t| t.trim().is_empty()))
+    {
+        return Err(ApiError::bad_request(
+            "keys can't be empty, leave them out instead",
+        ));
+    }
+    set_tokens(state, tokens)?;
+    ok(&StatusView::from(&*state))
+}
+
+fn set_schedule_rules<B: Backend>(
+    _backend: &B,
+    state: &mut State,
+    _request: &ApiRequest,
+) -> Result<Vec<u8>, ApiError> {
+    let body: ScheduleRulesRequest = json_body()?;
+    replace_schedule_rules(state, &body.schedule_rules)
+}
+
+fn replace_schedule_rules(state: &mut State, rules: &str) -> Result<Vec<u8>, ApiError> {
+    let rules = rules.trim();
+    if rules.is_empty() {
+        return Err(ApiError::bad_request("schedule_rules can't be empty"));
+    }
+    if rules.len() > MAX_RULES_LEN {
+        return Err(ApiError::bad_request(format!(
+            "schedule_rules longer than {} characters",
+            MAX_RULES_LEN
+        )));
+    }
+    state.schedule_rules = rules.to_string();
+    save(state);
+    ok(&StatusView::from(&*state))
+}
+
+fn set_settings<B: Backend>(
+    _backend: &B,
+    state: &mut State,
+    _request: &ApiRequest,
+) -> Result<Vec<u8>, ApiError> {
+    let body: SettingsRequest = json_body()?;
+    // all of it is checked before anything changes, a bad field leaves every
+    // setting as it was.
+    let tts_url = settings_url("tts_url", body.tts_url.as_deref())?;
+    let whisper_url = settings_url("whisper_url", body.whisper_url.as_deref())?;
+    let jitsi_url = settings_url("jitsi_url", body.jitsi_url.as_deref())?;
+    let meeting_link = settings_url("meeting_link", body.meeting_link.as_deref())?;
+    if let Some(timeout) = body.stt_timeout_secs {
+        if !(1..=MAX_STT_TIMEOUT_SECS).contains(&timeout) {
+            return Err(ApiError::bad_request(format!(
+                "stt_timeout_secs must be between 1 and {}",
+                MAX_STT_TIMEOUT_SECS
+            )));
+        }
+    }
+    let custom_url = match meeting_link {
+        Some(url) => (!url.is_empty()).then(|| url.to_string()),
+        None => state.meetings.custom_url.clone(),
+    };
+    let meeting_default = body.meeting_default.unwrap_or(state.meetings.default);
+    if meeting_default == Solution::Custom && custom_url.is_none() {
+        return Err(ApiError::bad_request(
+            "meeting_default custom needs a meeting_link",
+        ));
+    }
+
+    if let Some(confirm_mode) = body.confirm_mode {
+        state.confirm_mode = confirm_mode;
+        if !confirm_mode {
+            state.previews.clear();
+        }
+    }
+    if let Some(voice_replies) = body.voice_replies {
+        state.tts.enabled = voice_replies;
+    }
+    if let Some(url) = tts_url {
+        state.tts.url = match url {
+            "" => tts::OPENAI_URL.to_string(),
+            url => url.to_string(),
+        };
+    }
+    if let Some(model) = body.tts_model.filter(|model| !model.is_empty()) {
+        state.tts.model = model;
+    }
+    if let Some(voice) = body.tts_voice.filter(|voice| !voice.is_empty()) {
+        state.tts.voice = voice;
+    }
+    if let Some(url) = whisper_url {
+        state.stt.provider = match url {
+            "" => SttProvider::Openai,
+            url => SttProvider::Whisper {
+                url: url.to_string(),
+            },
+        };
+    }
+    if let Some(timeout) = body.stt_timeout_secs {
+        state.stt.timeout_secs = timeout;
+    }
+    if let Some(echo) = body.echo_transcripts {
+        state.stt.echo = echo;
+    }
+    state.meetings.default = meeting_default;
+    if let Some(url) = jitsi_url {
+        state.meetings.jitsi_url = match url {
+            "" => meetings::DEFAULT_JITSI_URL.to_string(),
+            url => url.to_string(),
+        };
+    }
+    state.meetings.custom_url = custom_url;
+    if let Some(notify) = body.notify {
+        state.attendees.notify = notify;
+    }
+    if let Some(on) = body.rsvp_alerts {
+        set_rsvp_alerts(state, on);
+    }
+    if let Some(bodies) = body.audit_bodies {
+        state.audit_bodies = bodies;
+    }
+    save(state);
+    ok(&StatusView::from(&*state))
+}
+
+/// A url setting, trimmed, `""` putting it back to its default.
+fn settings_url<'a>(field: &str, url: Option<&'a str>) -> Result<Option<&'a str>, ApiError> {
+    let Some(url) = url.map(str::trim) else {
+        return Ok(None);
+    };
+    if !url.is_empty() && Url::parse(url).is_err() {
+        return Err(ApiError::bad_request(format!("invalid {}: {}", field, url)));
+    }
+    Ok(Some(url))
+}
+
+fn parse_time(field: &str, value: &str) -> Result<DateTime<Utc>, ApiError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| ApiError::bad_request(format!("{} must be an RFC 3339 time", field)))
+}
+
+fn parse_attendees(emails: &[String]) -> Result<Vec<EventAttendees>, ApiError> {
+    emails
+        .iter()
+        .map(|email| {
+            let email = email.trim();
+            if !is_valid_email(email) {
+                return Err(ApiError::bad_request(format!(
+                    "{} is not an email address",
+                    email
+                )));
+            }
+            Ok(EventAttendees {
+                email: email.to_string(),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+fn check_timezone(timezone: &Option<String>) -> Result<(), ApiError> {
+    match timezone {
+        Some(tz) if tz.parse::<chrono_tz::Tz>().is_err() => {
+            Err(ApiError::bad_request(format!("unknown timezone {}", tz)))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn list_events<B: Backend>(
+    backend: &B,
+    state: &mut State,
+    request: &ApiRequest,
+) -> Result<Vec<u8>, ApiError> {
+    let from = match request.query("from") {
+        Some(from) => parse_time("from", from)?,
+        None => Utc::now(),
+    };
+    let to = match request.query("to") {
+        Some(to) => parse_time("to", to)?,
+        None => from + Duration::days(DEFAULT_RANGE_DAYS),
+    };
+    if to <= from {
+        return Err(ApiError::bad_request("to must be after from"));
+    }
+    if to - from > Duration::days(MAX_RANGE_DAYS) {
+        return Err(ApiError::bad_request(format!(
+            "range can't be longer than {} days",
+            MAX_RANGE_DAYS
+        )));
+    }
+    // Z instead of an offset, a `+` doesn't survive the query string.
+    let from = from.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let to = to.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let events = with_google_token(state, |token| {
+        get_events_from_primary_calendar(backend, token, &from, &to)
+    })?;
+    ok(&events)
+}
+
+fn add_event<B: Backend>(
+    backend: &B,
+    state: &mut State,
+    _request: &ApiRequest,
+) -> Result<Vec<u8>, ApiError> {
+    let body: CreateEventRequest = json_body()?;
+    let summary = body.summary.trim();
+    if summary.is_empty() {
+        return Err(ApiError::bad_request("summary can't be empty"));
+    }
+    if parse_time("end", &body.end)? <= parse_time("start", &body.start)? {
+        return Err(ApiError::bad_request("end must be after start"));
+    }
+    check_timezone(&body.timezone)?;
+    let attendees = parse_attendees(&body.attendees)?;
+
+    let event = create_event(
+        summary,
+        &body.description,
+        &body.start,
+        &body.end,
+        body.timezone,
+        attendees,
+    )?;
+    let proposal = Proposal {
+        event,
+        meeting: body.meeting || body.conference.is_some(),
+        solution: body.conference.unwrap_or(state.meetings.default),
+        answer: String::new(),
+    };
+    let (oauth_node, owner) = (state.oauth_node.clone(), state.user_id);
+    let zoom = || zoom_token(backend, oauth_node.as_deref(), owner);
+    let options = BookingOptions {
+        confirm: false,
+        solution: body.conference,
+        meetings: state.meetings.clone(),
+        zoom_token: &zoom,
+        send_updates: body.send_updates.unwrap_or(state.attendees.notify.book),
+    };
+    let event = with_google_token(state, |token| {
+        schedule_with_meeting(backend, token, &proposal, &options)
+    })?;
+    ok(&event)
+}
+
+fn show_event<B: Backend>(
+    backend: &B,
+    state: &mut State,
+    request: &ApiRequest,
+) -> Result<Vec<u8>, ApiError> {
+    let id = request.param("id")?;
+    let event = with_google_token(state, |token| get_event(backend, token, id))?;
+    ok(&event)
+}
+
+fn edit_event<B: Backend>(
+    backend: &B,
+    state: &mut State,
+    request: &ApiRequest,
+) -> Result<Vec<u8>, ApiError> {
+    let id = request.param("id")?;
+    let body: UpdateEventRequest = json_body()?;
+    patch_event(backend, state, id, body)
+}
+
+fn patch_event<B: Backend>(
+    backend: &B,
+    state: &mut State,
+    id: &str,
+    body: UpdateEventRequest,
+) -> Result<Vec<u8>, ApiError> {
+    if let Some(summary) = &body.summary {
+        if summary.trim().is_empty() {
+            return Err(ApiError::bad_request("summary can't be empty"));
+        }
+    }
+    let start = body
+        .start
+        .as_deref()
+        .map(|t| parse_time("start", t))
+        .transpose()?;
+    let end = body
+        .end
+        .as_deref()
+        .map(|t| parse_time("end", t))
+        .transpose()?;
+    check_timezone(&body.timezone)?;
+    // a lone start or end has to fit the other bound the event already has
+    let bounds = match (start, end) {
+        (Some(start), Some(end)) => Some((start, end)),
+        (None, None) => None,
+        _ => {
+            let event = with_google_token(state, |token| get_event(backend, token, id))?;
+            let current = |field: &str, date: &Option<EventCalendarDate>| {
+                date.as_ref()
+                    .and_then(|date| date.date_time.as_deref())
+                    .ok_or_else(|| {
+                        ApiError::bad_request(format!(
+                            "the event has no {} time, it's all day, change start and end together",
+                            field
+                        ))
+                    })
+                    .and_then(|time| parse_time(field, time))
+            };
+            Some((
+                start.map_or_else(|| current("start", &event.start), Ok)?,
+                end.map_or_else(|| current("end", &event.end), Ok)?,
+            ))
+        }
+    };
+    if bounds.is_some_and(|(start, end)| end <= start) {
+        return Err(ApiError::bad_request("end must be after start"));
+    }
+
+    let date = |time: &Option<String>| {
+        time.as_ref().map(|time| EventCalendarDate {
+            date: None,
+            date_time: Some(time.clone()),
+            time_zone: body.timezone.clone(),
+        })
+    };
+    let patch = Event {
+        summary: body.summary.as_ref().map(|s| s.trim().to_string()),
+        description: body.description.clone(),
+        start: date(&body.start),
+        end: date(&body.end),
+        attendees: body.attendees.as_deref().map(parse_attendees).transpose()?,
+        ..Default::default()
+    };
+    let send_updates = match (body.send_updates, &patch.attendees) {
+        (Some(send_updates), _) => send_updates,
+        (None, Some(_)) => state.attendees.notify.invite,
+        (None, None) => SendUpdates::None,
+    };
+    let event = with_google_token(state, |token| {
+        update_event(backend, token, id, &patch, send_updates)
+    })?;
+    ok(&event)
+}
+
+fn remove_event<B: Backend>(
+    backend: &B,
+    state: &mut State,
+    request: &ApiRequest,
+) -> Result<Vec<u8>, ApiError> {
+    let id = request.param("id")?;
+    let send_updates = match request.query("send_updates") {
+        Some(value) => parse_send_updates(value)?,
+        None => state.attendees.notify.cancel,
+    };
+    with_google_token(state, |token| {
+        delete_event(backend, token, id, send_updates)
+    })?;
+    ok(&Deleted { id: id.to_string() })
+}
+
+/// A `send_updates` query value, spelled like in JSON bodies.
+fn parse_send_updates(value: &str) -> Result<SendUpdates, ApiError> {
+    serde_json::from_value(json!(value))
+        .map_err(|_| ApiError::bad_request("send_updates must be all, externalOnly or none"))
+}
+
+fn list_guest_requests<B: Backend>(
+    _backend: &B,
+    state: &mut State,
+    _request: &ApiRequest,
+) -> Result<Vec<u8>, ApiError> {
+    ok(&activity::pending_guest_requests(&state.activity))
+}
+
+fn list_activity<B: Backend>(
+    _backend: &B,
+    state: &mut State,
+    request: &ApiRequest,
+) -> Result<Vec<u8>, ApiError> {
+    let limit = match request.query("limit") {
+        Some(limit) => limit
+            .parse::<usize>()
+            .map_err(|_| ApiError::bad_request("limit must be a number"))?,
+        None => DEFAULT_ACTIVITY_LIMIT,
+    };
+    let recent: Vec<_> = state.activity.iter().rev().take(limit).collect();
+    ok(&recent)
+}
+
+fn parse_day(field: &str, value: &str) -> Result<NaiveDate, ApiError> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| ApiError::bad_request(format!("{} must be a YYYY-MM-DD day", field)))
+}
+
+fn list_audit<B: Backend>(
+    _backend: &B,
+    _state: &mut State,
+    request: &ApiRequest,
+) -> Result<Vec<u8>, ApiError> {
+    let today = Utc::now().date_naive();
+    let to = match request.query("to") {
+        Some(to) => parse_day("to", to)?,
+        None => today,
+    };
+    let from = match request.query("from") {
+        Some(from) => parse_day("from", from)?,
+        None => to - Duration::days(6),
+    };
+    if to < from {
+        return Err(ApiError::bad_request("to can't be before from"));
+    }
+    // nothing older than this is kept anyway
+    let from = from.max(today - Duration::days(RETENTION_DAYS - 1));
+    let user_id = request
+        .query("user_id")
+        .map(|id| id.parse::<u64>())
+        .transpose()
+        .map_err(|_| ApiError::bad_request("user_id must be a number"))?;
+    let limit = match request.query("limit") {
+        Some(limit) => limit
+            .parse::<usize>()
+            .map_err(|_| ApiError::bad_request("limit must be a number"))?,
+        None => DEFAULT_AUDIT_LIMIT,
+    };
+
+    let entries = audit::query(&audit::Query {
+        from,
+        to,
+        user_id,
+        limit: limit.min(MAX_AUDIT_LIMIT),
+    })?;
+    ok(&entries)
+}
+
+fn get_openapi<B: Backend>(
+    _backend: &B,
+    _state: &mut State,
+    _request: &ApiRequest,
+) -> Result<Vec<u8>, ApiError> {
+    let mut paths = serde_json::Map::new();
+    for route in routes::<B>() {
+        let mut params = vec![];
+        let segments: Vec<String> = route
+            .path
+            .split('/')
+            .map(|segment| match segment.strip_prefix(':') {
+                Some(name) => {
+                    params.push(json!({
+                        "name": name,
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "string" }
+                    }));
+                    format!("{{{}}}", name)
+                }
+                None => segment.to_string(),
+            })
+            .collect();
+        let path = format!("{}{}", API_PREFIX, segments.join("/"));
+        let mut operation = json!({
+            "summary": route.summary,
+            "parameters": params,
+            "responses": {
+                "200": {
+                    "description": "ok",
+                    "content": { "application/json": { "schema": schema_ref(route.response) } }
+                },
+                "default": {
+                    "description": "error",
+                    "content": { "application/json": { "schema": schema_ref("Error") } }
+                }
+            }
+        });
+        if let Some(request) = route.request {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": schema_ref(request) } }
+            });
+        }
+        let entry = paths.entry(path).or_insert_with(|| json!({}));
+        entry[route.method.as_str().to_lowercase()] = operation;
+    }
+
+    ok(&json!({
+        "openapi": "3.0.3",
+        "info": { "title": "kcal client", "version": API_PREFIX.trim_start_matches("/api/") },
+        "paths": paths,
+        "components": { "schemas": schemas() },
+    }))
+}
+
+fn schema_ref(name: &str) -> serde_json::Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn schemas() -> serde_json::Value {
+    json!({
+        "Error": {
+            "type": "object",
+            "properties": { "error": { "type": "string" } }
+        },
+        "Status": {
+            "type": "object",
+            "properties": {
+                "google_configured": { "type": "boolean" },
+                "telegram_configured": { "type": "boolean" },
+                "openai_configured": { "type": "boolean" },
+                "groq_configured": { "type": "boolean" },
+                "timezone": { "type": "string", "nullable": true },
+                "schedule_rules": { "type": "string" },
+                "google_scopes": { "type": "array", "items": { "type": "string" } },
+                "confirm_mode": { "type": "boolean" },
+                "voice_replies": { "type": "boolean" },
+                "tts_url": { "type": "string" },
+                "tts_voice": { "type": "string" },
+                "stt_provider": {
+                    "type": "object",
+                    "properties": {
+                        "kind": { "type": "string", "enum": ["openai", "whisper"] },
+                        "url": { "type": "string" }
+                    }
+                },
+                "stt_timeout_secs": { "type": "integer" },
+                "echo_transcripts": { "type": "boolean" },
+                "meeting_default": { "type": "string", "enum": ["meet", "jitsi", "zoom", "custom"] },
+                "jitsi_url": { "type": "string" },
+                "meeting_link": { "type": "string", "nullable": true },
+                "rsvp_alerts": { "type": "boolean" },
+                "notify": { "$ref": "#/components/schemas/Notify" },
+                "audit_bodies": { "type": "boolean" }
+            }
+        },
+        "ConnectRequest": {
+            "type": "object",
+            "required": ["target"],
+            "properties": {
+                "target": { "type": "string", "description": "node running the oauth process" },
+                "scopes": { "type": "array", "items": { "type": "string" } }
+            }
+        },
+        "ConnectResponse": {
+            "type": "object",
+            "properties": { "url": { "type": "string" } }
+        },
+        "ConfigRequest": {
+            "type": "object",
+            "properties": {
+                "telegram": { "type": "string", "nullable": true },
+                "openai": { "type": "string", "nullable": true },
+                "groq": { "type": "string", "nullable": true }
+            }
+        },
+        "ScheduleRulesRequest": {
+            "type": "object",
+            "required": ["schedule_rules"],
+            "properties": { "schedule_rules": { "type": "string" } }
+        },
+        "SettingsRequest": {
+            "type": "object",
+            "properties": {
+                "confirm_mode": { "type": "boolean", "description": "ask before booking" },
+                "voice_replies": { "type": "boolean", "description": "answer voice messages with a voice note" },
+                "tts_url": { "type": "string", "description": "openai-compatible speech endpoint, empty for openai" },
+                "tts_model": { "type": "string" },
+                "tts_voice": { "type": "string" },
+                "whisper_url": { "type": "string", "description": "whisper.cpp server, empty for openai" },
+                "stt_timeout_secs": { "type": "integer", "minimum": 1, "maximum": 300 },
+                "echo_transcripts": { "type": "boolean", "description": "say what was heard before acting" },
+                "meeting_default": { "type": "string", "enum": ["meet", "jitsi", "zoom", "custom"] },
+                "jitsi_url": { "type": "string", "description": "jitsi server, empty for meet.jit.si" },
+                "meeting_link": { "type": "string", "description": "link for the custom kind, empty to remove it" },
+                "rsvp_alerts": { "type": "boolean", "description": "message the owner when attendees accept or decline" },
+                "notify": { "$ref": "#/components/schemas/Notify" },
+                "audit_bodies": { "type": "boolean", "description": "keep http bodies in the audit log, secrets redacted" }
+            }
+        },
+        "SendUpdates": { "type": "string", "enum": ["all", "externalOnly", "none"] },
+        "Notify": {
+            "type": "object",
+            "description": "who google emails, per kind of change",
+            "required": ["book", "invite", "remove", "cancel"],
+            "properties": {
+                "book": { "$ref": "#/components/schemas/SendUpdates" },
+                "invite": { "$ref": "#/components/schemas/SendUpdates" },
+                "remove": { "$ref": "#/components/schemas/SendUpdates" },
+                "cancel": { "$ref": "#/components/schemas/SendUpdates" }
+            }
+        },
+        "CreateEventRequest": {
+            "type": "object",
+            "required": ["summary", "start", "end"],
+            "properties": {
+                "summary": { "type": "string" },
+                "description": { "type": "string" },
+                "start": { "type": "string", "format": "date-time" },
+                "end": { "type": "string", "format": "date-time" },
+                "timezone": { "type": "string", "nullable": true },
+                "attendees": { "type": "array", "items": { "type": "string", "format": "email" } },
+                "meeting": { "type": "boolean" },
+                "conference": { "type": "string", "enum": ["meet", "jitsi", "zoom", "custom"], "description": "implies meeting" },
+                "send_updates": { "$ref": "#/components/schemas/SendUpdates" }
+            }
+        },
+        "UpdateEventRequest": {
+            "type": "object",
+            "properties": {
+                "summary": { "type": "string" },
+                "description": { "type": "string" },
+                "start": { "type": "string", "format": "date-time" },
+                "end": { "type": "string", "format": "date-time" },
+                "timezone": { "type": "string" },
+                "attendees": { "type": "array", "items": { "type": "string", "format": "email" } },
+                "send_updates": { "$ref": "#/components/schemas/SendUpdates" }
+            }
+        },
+        "Event": {
+            "type": "object",
+            "description": "google calendar event resource"
+        },
+        "Events": {
+            "type": "object",
+            "description": "google calendar events list, events in `items`",
+            "properties": { "items": { "type": "array", "items": schema_ref("Event") } }
+        },
+        "Deleted": {
+            "type": "object",
+            "properties": { "id": { "type": "string" } }
+        },
+        "Activity": {
+            "type": "object",
+            "properties": {
+                "at": { "type": "string", "format": "date-time" },
+                "chat_id": { "type": "integer" },
+                "user_id": { "type": "integer" },
+                "user_name": { "type": "string" },
+                "guest": { "type": "boolean" },
+                "text": { "type": "string" },
+                "reply": { "type": "string" },
+                "outcome": {
+                    "type": "object",
+                    "properties": {
+                        "kind": {
+                            "type": "string",
+                            "enum": ["answered", "listed", "scheduled", "incomplete", "rejected", "mismatch", "undone", "proposed", "failed"]
+                        }
+                    }
+                }
+            }
+        },
+        "ActivityList": { "type": "array", "items": schema_ref("Activity") },
+        "HttpCall": {
+            "type": "object",
+            "properties": {
+                "method": { "type": "string" },
+                "url": { "type": "string" },
+                "request": { "type": "string", "nullable": true, "description": "only with audit_bodies" },
+                "status": { "type": "integer", "nullable": true },
+                "response": { "type": "string", "nullable": true, "description": "only with audit_bodies" },
+                "error": { "type": "string", "nullable": true }
+            }
+        },
+        "AuditEntry": {
+            "type": "object",
+            "properties": {
+                "at": { "type": "string", "format": "date-time" },
+                "chat_id": { "type": "integer" },
+                "user_id": { "type": "integer" },
+                "user_name": { "type": "string" },
+                "text": { "type": "string" },
+                "prompt": { "type": "string", "enum": ["default", "schedule", "command"] },
+                "llm_response": { "type": "string", "nullable": true },
+                "command": { "type": "string", "nullable": true },
+                "http_calls": { "type": "array", "items": schema_ref("HttpCall") },
+                "reply": { "type": "string", "nullable": true },
+                "error": { "type": "string", "nullable": true }
+            }
+        },
+        "AuditList": { "type": "array", "items": schema_ref("AuditEntry") },
+        "OpenApi": { "type": "object" }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{fake_calendar::TOKEN, mock::MockBackend};
+
+    #[test]
+    fn a_lone_start_or_end_is_checked_against_the_event() {
+        let backend = MockBackend::default();
+        let mut state = State {
+            google_token: Some(TOKEN.to_string()),
+            ..Default::default()
+        };
+        let id = backend.calendar.insert(json!({
+            "summary": "Standup",
+            "start": { "dateTime": "2024-06-06T08:00:00Z" },
+            "end": { "dateTime": "2024-06-06T08:15:00Z" },
+        }));
+        let mut patch = |start: Option<&str>, end: Option<&str>| {
+            let body = UpdateEventRequest {
+                start: start.map(str::to_string),
+                end: end.map(str::to_string),
+                ..Default::default()
+            };
+            patch_event(&backend, &mut state, &id, body)
+        };
+
+        let Err(e) = patch(Some("2024-06-06T09:00:00Z"), None) else {
+            panic!("moved the start past the end");
+        };
+        assert_eq!(e.message, "end must be after start");
+        let Err(e) = patch(None, Some("2024-06-06T07:00:00Z")) else {
+            panic!("moved the end before the start");
+        };
+        assert_eq!(e.message, "end must be after start");
+        assert_eq!(
+            backend.calendar.event(&id).unwrap()["start"]["dateTime"],
+            "2024-06-06T08:00:00Z"
+        );
+
+        patch(None, Some("2024-06-06T08:30:00Z")).unwrap();
+        assert_eq!(
+            backend.calendar.event(&id).unwrap()["end"]["dateTime"],
+            "2024-06-06T08:30:00Z"
+        );
+    }
+
+    #[test]
+    fn every_documented_send_updates_value_is_accepted() {
+        let documented = schemas()["SendUpdates"]["enum"].clone();
+        let documented: Vec<String> = serde_json::from_value(documented).unwrap();
+        assert_eq!(documented.len(), 3);
+        for value in &documented {
+            let create: CreateEventRequest = serde_json::from_value(json!({
+                "summary": "Sync",
+                "start": "2024-06-06T13:00:00Z",
+                "end": "2024-06-06T14:00:00Z",
+                "send_updates": value,
+            }))
+            .unwrap();
+            let update: UpdateEventRequest =
+                serde_json::from_value(json!({ "send_updates": value })).unwrap();
+            let query = parse_send_updates(value).unwrap();
+            assert_eq!(create.send_updates, Some(query));
+            assert_eq!(update.send_updates, Some(query));
+            assert_eq!(&query.to_string(), value);
+        }
+
+        let Err(e) = parse_send_updates("external") else {
+            panic!("accepted an undocumented value");
+        };
+        assert!(documented
+            .iter()
+            .all(|value| e.message.contains(value.as_str())));
+    }
+}
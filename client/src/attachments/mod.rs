@@ -0,0 +1,232 @@
+//! Photos and documents sent to the bot, turned into text the scheduling prompts
+//! can work with. `.ics` invitations are parsed here; screenshots, flyers and PDF
+//! tickets are read by a vision model.
+use crate::backend::{Http, Telegram};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{NaiveDate, NaiveDateTime};
+use frankenstein::Message as TgMessage;
+use kinode_process_lib::http;
+use std::{collections::HashMap, str::FromStr};
+use url::Url;
+
+const OPENAI_CHAT_URL: &str = "https://api.openai.com/v1/chat/completions";
+const VISION_MODEL: &str = "gpt-4o-mini";
+// bigger files are refused before downloading, telegram's own limit is 20MB
+const MAX_FILE_BYTES: u64 = 10 * 1024 * 1024;
+
+const VISION_PROMPT: &str = "Read this image or document and write down the event it is about, one field per line: title, date, start time, end time, timezone, location, organizer, attendee emails. Leave out what isn't there. If there is no event in it, answer only NONE.";
+
+/// A file someone sent and how to read it.
+enum Attachment {
+    Ics { file_id: String },
+    Image { file_id: String, mime: String },
+    Pdf { file_id: String, name: String },
+    Text { file_id: String },
+}
+
+/// The event details in the message's photo or document, if it has one the bot
+/// can read. Errors are for files it should have been able to read.
+pub fn read(
+    backend: &(impl Http + Telegram),
+    openai_key: Option<&str>,
+    msg: &TgMessage,
+) -> anyhow::Result<Option<String>> {
+    let Some(attachment) = attachment(msg)? else {
+        return Ok(None);
+    };
+    let text = match attachment {
+        Attachment::Ics { file_id } => {
+            let bytes = backend.get_file(&file_id)?;
+            let events = parse_ics(&String::from_utf8_lossy(&bytes));
+            if events.is_empty() {
+                return Err(anyhow::anyhow!("no events in that calendar file"));
+            }
+            format!("Calendar invitation:\n{}", events.join("\n\n"))
+        }
+        Attachment::Text { file_id } => {
+            let bytes = backend.get_file(&file_id)?;
+            format!("Text file:\n{}", String::from_utf8_lossy(&bytes))
+        }
+        Attachment::Image { file_id, mime } => {
+            let bytes = backend.get_file(&file_id)?;
+            let part = serde_json::json!({
+                "type": "image_url",
+                "image_url": { "url": data_url(&mime, &bytes) },
+            });
+            format!("From an image:\n{}", vision(backend, openai_key, part)?)
+        }
+        Attachment::Pdf { file_id, name } => {
+            let bytes = backend.get_file(&file_id)?;
+            let part = serde_json::json!({
+                "type": "file",
+                "file": { "filename": name, "file_data": data_url("application/pdf", &bytes) },
+            });
+            format!("From a PDF:\n{}", vision(backend, openai_key, part)?)
+        }
+    };
+    Ok(Some(text))
+}
+
+fn attachment(msg: &TgMessage) -> anyhow::Result<Option<Attachment>> {
+    // telegram sends a photo in several sizes, the last is the largest
+    if let Some(photo) = msg.photo.as_ref().and_then(|sizes| sizes.last()) {
+        check_size(photo.file_size)?;
+        return Ok(Some(Attachment::Image {
+            file_id: photo.file_id.clone(),
+            mime: "image/jpeg".to_string(),
+        }));
+    }
+    let Some(document) = &msg.document else {
+        return Ok(None);
+    };
+    let name = document.file_name.clone().unwrap_or_default();
+    let mime = document.mime_type.clone().unwrap_or_default();
+    let extension = name.rsplit_once('.').map(|(_, ext)| ext.to_lowercase());
+    let file_id = document.file_id.clone();
+    let attachment = match (mime.as_str(), extension.as_deref()) {
+        ("text/calendar", _) | (_, Some("ics")) => Attachment::Ics { file_id },
+        ("application/pdf", _) | (_, Some("pdf")) => Attachment::Pdf { file_id, name },
+        (mime, _) if mime.starts_with("image/") => Attachment::Image {
+            file_id,
+            mime: mime.to_string(),
+        },
+        ("text/plain", _) | (_, Some("txt" | "eml")) => Attachment::Text { file_id },
+        _ => return Ok(None),
+    };
+    check_size(document.file_size)?;
+    Ok(Some(attachment))
+}
+
+fn check_size(size: Option<u64>) -> anyhow::Result<()> {
+    match size {
+        Some(size) if size > MAX_FILE_BYTES => Err(anyhow::anyhow!(
+            "that file is too big, up to {}MB please",
+            MAX_FILE_BYTES / 1024 / 1024
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn data_url(mime: &str, bytes: &[u8]) -> String {
+    format!("data:{};base64,{}", mime, STANDARD.encode(bytes))
+}
+
+/// Asks the vision model what event `part`, an image or file content part, is about.
+fn vision(
+    http: &impl Http,
+    openai_key: Option<&str>,
+    part: serde_json::Value,
+) -> anyhow::Result<String> {
+    let Some(key) = openai_key else {
+        return Err(anyhow::anyhow!("reading images needs an openai key"));
+    };
+    let headers = HashMap::from([
+        ("Authorization".to_string(), format!("Bearer {}", key)),
+        ("Content-Type".to_string(), "application/json".to_string()),
+    ]);
+    let body = serde_json::json!({
+        "model": VISION_MODEL,
+        "messages": [{
+            "role": "user",
+            "content": [{ "type": "text", "text": VISION_PROMPT }, part],
+        }],
+    });
+    let res = http.request(
+        http::Method::POST,
+        Url::from_str(OPENAI_CHAT_URL)?,
+        headers,
+        60,
+        serde_json::to_vec(&body)?,
+    )?;
+    if !res.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "reading the file failed: {}",
+            String::from_utf8_lossy(res.body())
+        ));
+    }
+    let json: serde_json::Value = serde_json::from_slice(res.body())?;
+    let Some(answer) = json["choices"][0]["message"]["content"].as_str() else {
+        return Err(anyhow::anyhow!("no answer reading the file"));
+    };
+    let answer = answer.trim();
+    if answer == "NONE" {
+        return Err(anyhow::anyhow!("couldn't find an event in that"));
+    }
+    Ok(answer.to_string())
+}
+
+/// The VEVENTs of an iCalendar file, each as readable lines.
+fn parse_ics(ics: &str) -> Vec<String> {
+    // long lines are folded: continuation lines start with a space or tab
+    let mut lines: Vec<String> = vec![];
+    for line in ics.lines() {
+        match line.strip_prefix([' ', '\t']) {
+            Some(rest) if !lines.is_empty() => lines.last_mut().unwrap().push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut events = vec![];
+    let mut event: Option<Vec<String>> = None;
+    for line in &lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (name, params) = name.split_once(';').unwrap_or((name, ""));
+        let value = unescape(value);
+        match (name.to_uppercase().as_str(), event.as_mut()) {
+            ("BEGIN", None) if value == "VEVENT" => event = Some(vec![]),
+            ("END", Some(fields)) if value == "VEVENT" => {
+                events.push(fields.join("\n"));
+                event = None;
+            }
+            ("SUMMARY", Some(fields)) => fields.push(format!("Title: {}", value)),
+            ("DTSTART", Some(fields)) => {
+                fields.push(format!("Start: {}", ics_time(&value, params)))
+            }
+            ("DTEND", Some(fields)) => fields.push(format!("End: {}", ics_time(&value, params))),
+            ("LOCATION", Some(fields)) => fields.push(format!("Location: {}", value)),
+            ("DESCRIPTION", Some(fields)) => fields.push(format!("Description: {}", value)),
+            ("ORGANIZER", Some(fields)) => fields.push(format!("Organizer: {}", mailto(&value))),
+            ("ATTENDEE", Some(fields)) => fields.push(format!("Attendee: {}", mailto(&value))),
+            _ => {}
+        }
+    }
+    events
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("\\n", "\n")
+        .replace("\\N", "\n")
+        .replace("\\,", ",")
+        .replace("\\;", ";")
+        .replace("\\\\", "\\")
+}
+
+fn mailto(value: &str) -> &str {
+    value
+        .strip_prefix("mailto:")
+        .or_else(|| value.strip_prefix("MAILTO:"))
+        .unwrap_or(value)
+}
+
+/// `20241021T100000Z`, `20241021T100000` with a TZID, or a `20241021` date.
+fn ics_time(value: &str, params: &str) -> String {
+    let tzid = params
+        .split(';')
+        .find_map(|param| param.strip_prefix("TZID="))
+        .map(|tz| tz.trim_matches('"'));
+    if let Ok(time) = NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S") {
+        let zone = match (value.ends_with('Z'), tzid) {
+            (true, _) => "UTC",
+            (false, Some(tz)) => tz,
+            (false, None) => "local time",
+        };
+        return format!("{} ({})", time.format("%Y-%m-%d %H:%M"), zone);
+    }
+    match NaiveDate::parse_from_str(value, "%Y%m%d") {
+        Ok(date) => format!("{} (all day)", date.format("%Y-%m-%d")),
+        Err(_) => value.to_string(),
+    }
+}
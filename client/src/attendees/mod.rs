@@ -0,0 +1,444 @@
+//! Attendees of events already on the calendar: inviting and removing people,
+//! who answered what, and telling the owner when someone accepts or declines.
+//! Answers are noticed by polling for events changed since the last look.
+use crate::{
+    backend::Http,
+    dates,
+    gcal::{
+        helpers::get_changed_events, Event, EventAttendees, EventResponseStatus, EventStatus,
+        SendUpdates,
+    },
+    timezones,
+};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const POLL_INTERVAL_MS: u64 = 10 * 60 * 1000;
+// how far ahead an event is looked for when the request names no day
+const LOOKAHEAD_DAYS: i64 = 14;
+// words that say nothing about which event is meant
+const FILLER_WORDS: &[&str] = &[
+    "the", "a", "my", "our", "to", "from", "for", "of", "on", "at", "with", "meeting", "event",
+    "call",
+];
+
+/// Who google emails when the bot changes an event, per kind of change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notify {
+    pub book: SendUpdates,
+    pub invite: SendUpdates,
+    pub remove: SendUpdates,
+    pub cancel: SendUpdates,
+}
+
+impl Default for Notify {
+    // booking and cancelling stay quiet as they were before this was configurable
+    fn default() -> Self {
+        Notify {
+            book: SendUpdates::None,
+            invite: SendUpdates::All,
+            remove: SendUpdates::All,
+            cancel: SendUpdates::None,
+        }
+    }
+}
+
+impl Notify {
+    pub fn get_mut(&mut self, action: &str) -> Option<&mut SendUpdates> {
+        match action {
+            "book" => Some(&mut self.book),
+            "invite" => Some(&mut self.invite),
+            "remove" => Some(&mut self.remove),
+            "cancel" => Some(&mut self.cancel),
+            _ => None,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "booking {}, inviting {}, removing {}, cancelling {}",
+            name(self.book),
+            name(self.invite),
+            name(self.remove),
+            name(self.cancel)
+        )
+    }
+}
+
+pub fn parse_send_updates(word: &str) -> Option<SendUpdates> {
+    match word {
+        "all" => Some(SendUpdates::All),
+        "external" => Some(SendUpdates::ExternalOnly),
+        "none" => Some(SendUpdates::None),
+        _ => None,
+    }
+}
+
+fn name(send_updates: SendUpdates) -> &'static str {
+    match send_updates {
+        SendUpdates::All => "emails everyone",
+        SendUpdates::ExternalOnly => "emails outside guests",
+        SendUpdates::None => "emails no one",
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AttendeeSettings {
+    // message the owner when attendees of their events answer
+    pub alerts: bool,
+    pub notify: Notify,
+}
+
+/// What the last poll saw of an upcoming event's attendees.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tracked {
+    pub summary: Option<String>,
+    // rfc3339, forgotten once it's over
+    pub end: Option<String>,
+    // email -> answer
+    pub responses: HashMap<String, EventResponseStatus>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Rsvps {
+    // rfc3339, the next poll asks for events changed since
+    pub checked_at: Option<String>,
+    pub events: HashMap<String, Tracked>,
+    // only the latest poll timer armed keeps polling, timers can't be cancelled
+    pub timer: u64,
+}
+
+/// Where to look for the event a request names, and the words left to match its
+/// title against once the dates are taken out.
+pub fn search(query: &str, now: DateTime<Tz>) -> (DateTime<Utc>, DateTime<Utc>, Vec<String>) {
+    // "friday's meeting" is about friday
+    let mut text = query
+        .to_lowercase()
+        .replace("'s", "")
+        .replace("\u{2019}s", "");
+    let ranges = dates::resolve(&text, now);
+    for range in &ranges {
+        text = text.replace(&range.phrase, " ");
+    }
+    let words = title_words(&text);
+    let now = now.with_timezone(&Utc);
+    let from = ranges.iter().map(|range| range.start).min();
+    let to = ranges.iter().map(|range| range.end).max();
+    match (from, to) {
+        (Some(from), Some(to)) => (from, to, words),
+        _ => (now, now + Duration::days(LOOKAHEAD_DAYS), words),
+    }
+}
+
+/// The words of `text` worth matching an event title against.
+pub fn title_words(text: &str) -> Vec<String> {
+    words(&text.to_lowercase())
+        .into_iter()
+        .filter(|word| !FILLER_WORDS.contains(&word.as_str()))
+        .collect()
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The event `words` point to among `events`: the one whose title shares the most
+/// words with them, or the only one there is. The error is for the user.
+pub fn pick<'a>(events: &'a [Event], words: &[String], tz: Tz) -> Result<&'a Event, String> {
+    let events: Vec<&Event> = events
+        .iter()
+        .filter(|event| !matches!(event.status, Some(EventStatus::Cancelled)))
+        .collect();
+    let score = |event: &Event| {
+        let title = words_of(event);
+        words.iter().filter(|word| title.contains(word)).count()
+    };
+    let best = events.iter().map(|event| score(event)).max().unwrap_or(0);
+    if best == 0 && !words.is_empty() {
+        return Err("I couldn't find that event.".to_string());
+    }
+    let matching: Vec<&Event> = events
+        .iter()
+        .copied()
+        .filter(|event| score(event) == best)
+        .collect();
+    match matching[..] {
+        [] => Err("I couldn't find that event.".to_string()),
+        [event] => Ok(event),
+        _ => {
+            let mut lines = vec!["Which one?".to_string()];
+            lines.extend(
+                matching
+                    .iter()
+                    .map(|event| format!("- {}", title(event, tz))),
+            );
+            Err(lines.join("\n"))
+        }
+    }
+}
+
+fn words_of(event: &Event) -> Vec<String> {
+    words(&event.summary.as_deref().unwrap_or_default().to_lowercase())
+}
+
+/// "Standup, Fri 24 Oct 10:00 CEST"
+pub fn title(event: &Event, tz: Tz) -> String {
+    let summary = event.summary.as_deref().unwrap_or("untitled event");
+    let start = event
+        .start
+        .as_ref()
+        .and_then(|start| start.date_time.as_deref())
+        .and_then(|start| timezones::format_local(start, tz));
+    match start {
+        Some(start) => format!("{}, {}", summary, start),
+        None => summary.to_string(),
+    }
+}
+
+fn who(attendee: &EventAttendees) -> String {
+    match &attendee.display_name {
+        Some(name) => format!("{} ({})", name, attendee.email),
+        None => attendee.email.clone(),
+    }
+}
+
+/// The people invited besides the owner.
+fn guests(event: &Event) -> impl Iterator<Item = &EventAttendees> {
+    event.attendees.iter().flatten().filter(|attendee| {
+        attendee.appears_as_self != Some(true) && attendee.resource != Some(true)
+    })
+}
+
+/// Who accepted, declined, maybe or hasn't answered yet.
+pub fn responses(event: &Event, tz: Tz) -> String {
+    let mut lines = vec![title(event, tz)];
+    if guests(event).next().is_none() {
+        lines.push("Nobody else is invited.".to_string());
+        return lines.join("\n");
+    }
+    for (status, label) in [
+        (EventResponseStatus::Accepted, "Accepted"),
+        (EventResponseStatus::Tentative, "Maybe"),
+        (EventResponseStatus::Declined, "Declined"),
+        (EventResponseStatus::NeedsAction, "No answer yet"),
+    ] {
+        let people: Vec<String> = guests(event)
+            .filter(|attendee| attendee.response_status == status)
+            .map(who)
+            .collect();
+        if !people.is_empty() {
+            lines.push(format!("{}: {}", label, people.join(", ")));
+        }
+    }
+    if guests(event).all(|attendee| attendee.response_status != EventResponseStatus::NeedsAction) {
+        lines.push("Everyone has answered.".to_string());
+    }
+    lines.join("\n")
+}
+
+/// The attendee list with `emails` added, and who of them is new.
+pub fn with_invited(event: &Event, emails: &[String]) -> (Vec<EventAttendees>, Vec<String>) {
+    let mut attendees = event.attendees.clone().unwrap_or_default();
+    let mut added = vec![];
+    for email in emails {
+        if attendees
+            .iter()
+            .any(|attendee| attendee.email.eq_ignore_ascii_case(email))
+        {
+            continue;
+        }
+        attendees.push(EventAttendees {
+            email: email.clone(),
+            ..Default::default()
+        });
+        added.push(email.clone());
+    }
+    (attendees, added)
+}
+
+/// The attendee list without the people `names` point to, by email or by name,
+/// and who was taken off.
+pub fn without(event: &Event, names: &[String]) -> (Vec<EventAttendees>, Vec<String>) {
+    let matches = |attendee: &EventAttendees| {
+        names.iter().any(|name| {
+            let name = name.to_lowercase();
+            attendee.email.to_lowercase() == name
+                || attendee.email.to_lowercase().split('@').next() == Some(name.as_str())
+                || attendee
+                    .display_name
+                    .as_deref()
+                    .is_some_and(|display| words(&display.to_lowercase()).contains(&name))
+        })
+    };
+    let (removed, kept): (Vec<EventAttendees>, Vec<EventAttendees>) = event
+        .attendees
+        .clone()
+        .unwrap_or_default()
+        .into_iter()
+        .partition(|attendee| attendee.appears_as_self != Some(true) && matches(attendee));
+    (kept, removed.iter().map(who).collect())
+}
+
+/// Whether `event` was made after `since`, an rfc3339 time.
+fn created_since(event: &Event, since: &str) -> bool {
+    let parse = |time: &str| DateTime::parse_from_rfc3339(time).ok();
+    match (event.created.as_deref().and_then(parse), parse(since)) {
+        (Some(created), Some(since)) => created >= since,
+        _ => false,
+    }
+}
+
+/// Looks at the owner's upcoming events changed since the last poll, returning what
+/// the poll saw and a line for every attendee who answered since. The first poll
+/// only starts the clock, and an event made before it that is seen for the first
+/// time only has its answers noted, they may be long given.
+pub fn poll(
+    http: &impl Http,
+    token: &str,
+    rsvps: &Rsvps,
+    tz: Tz,
+) -> anyhow::Result<(Rsvps, Vec<String>)> {
+    let mut rsvps = rsvps.clone();
+    let now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    let Some(since) = rsvps.checked_at.clone() else {
+        rsvps.checked_at = Some(now);
+        return Ok((rsvps, vec![]));
+    };
+
+    let mut alerts = vec![];
+    for event in get_changed_events(http, token, &since, &now)? {
+        let Some(id) = event.id.clone() else {
+            continue;
+        };
+        let organizer = event
+            .organizer
+            .as_ref()
+            .is_some_and(|organizer| organizer.appears_as_self == Some(true));
+        if matches!(event.status, Some(EventStatus::Cancelled)) || !organizer {
+            rsvps.events.remove(&id);
+            continue;
+        }
+        let seeding = !rsvps.events.contains_key(&id) && !created_since(&event, &since);
+        let tracked = rsvps.events.entry(id).or_insert_with(|| Tracked {
+            summary: None,
+            end: None,
+            responses: HashMap::new(),
+        });
+        tracked.summary = event.summary.clone();
+        tracked.end = event.end.as_ref().and_then(|end| end.date_time.clone());
+        for attendee in guests(&event) {
+            let before = tracked
+                .responses
+                .insert(attendee.email.clone(), attendee.response_status)
+                .unwrap_or_default();
+            if seeding || attendee.response_status == before {
+                continue;
+            }
+            let answer = match attendee.response_status {
+                EventResponseStatus::Accepted => "accepted",
+                EventResponseStatus::Declined => "declined",
+                EventResponseStatus::Tentative => "said maybe to",
+                EventResponseStatus::NeedsAction => continue,
+            };
+            alerts.push(format!(
+                "{} {} {}.",
+                who(attendee),
+                answer,
+                title(&event, tz)
+            ));
+        }
+    }
+
+    // over, nobody answers those anymore
+    rsvps.events.retain(|_, tracked| {
+        tracked
+            .end
+            .as_deref()
+            .and_then(|end| DateTime::parse_from_rfc3339(end).ok())
+            .is_none_or(|end| end.with_timezone(&Utc) > Utc::now())
+    });
+    rsvps.checked_at = Some(now);
+    Ok((rsvps, alerts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{fake_calendar::TOKEN, mock::MockBackend};
+    use serde_json::json;
+
+    // well before any of the events are changed
+    const SINCE: &str = "2024-01-01T00:00:00Z";
+
+    fn lunch(backend: &MockBackend, created: &str, ana: &str) -> String {
+        backend.calendar.insert(json!({
+            "summary": "Lunch",
+            "created": created,
+            "start": { "dateTime": "2099-06-06T12:00:00Z" },
+            "end": { "dateTime": "2099-06-06T13:00:00Z" },
+            "organizer": { "email": "owner@example.com", "self": true },
+            "attendees": [
+                { "email": "owner@example.com", "self": true, "responseStatus": "accepted" },
+                { "email": "ana@example.com", "displayName": "Ana", "responseStatus": ana },
+            ],
+        }))
+    }
+
+    fn watching() -> Rsvps {
+        Rsvps {
+            checked_at: Some(SINCE.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn old_answers_are_not_news() {
+        let backend = MockBackend::default();
+        let id = lunch(&backend, "2023-12-01T00:00:00Z", "accepted");
+
+        // first seen when something else about it changed
+        let (rsvps, alerts) = poll(&backend, TOKEN, &watching(), Tz::UTC).unwrap();
+        assert!(alerts.is_empty(), "{:?}", alerts);
+        assert_eq!(
+            rsvps.events[&id].responses["ana@example.com"],
+            EventResponseStatus::Accepted
+        );
+
+        backend.calendar.patch(
+            &id,
+            json!({ "attendees": [
+                { "email": "owner@example.com", "self": true, "responseStatus": "accepted" },
+                { "email": "ana@example.com", "displayName": "Ana", "responseStatus": "declined" },
+            ]}),
+        );
+        let (_, alerts) = poll(&backend, TOKEN, &rsvps, Tz::UTC).unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].starts_with("Ana (ana@example.com) declined Lunch"));
+    }
+
+    #[test]
+    fn answers_to_new_events_are_news() {
+        let backend = MockBackend::default();
+        lunch(&backend, "2024-06-01T00:00:00Z", "tentative");
+
+        let (_, alerts) = poll(&backend, TOKEN, &watching(), Tz::UTC).unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].starts_with("Ana (ana@example.com) said maybe to Lunch"));
+    }
+
+    #[test]
+    fn the_first_poll_starts_the_clock() {
+        let backend = MockBackend::default();
+        lunch(&backend, "2024-06-01T00:00:00Z", "accepted");
+
+        let (rsvps, alerts) = poll(&backend, TOKEN, &Rsvps::default(), Tz::UTC).unwrap();
+        assert!(alerts.is_empty());
+        assert!(rsvps.checked_at.is_some());
+        assert!(rsvps.events.is_empty());
+    }
+}
@@ -0,0 +1,352 @@
+//! Append-only record of what the bot did with each message: the prompt it used,
+//! what the LLM answered, and every HTTP call that followed.
+//!
+//! Entries are JSON lines in the `audit` vfs drive, one file per UTC day, so
+//! filtering by date only opens the days asked for and retention is deleting
+//! old files.
+use crate::{
+    backend::{Http, HttpResponse, Wraps},
+    gcal::helpers::{llm_command, Reply},
+    tg::display_name,
+};
+use chrono::{Duration, NaiveDate, Utc};
+use kinode_process_lib::{
+    http,
+    vfs::{self, FileType},
+    Address,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+use url::Url;
+
+// days of files kept, today included
+pub const RETENTION_DAYS: i64 = 30;
+// llm answers and http bodies are cut to this many characters
+const MAX_FIELD_LEN: usize = 4000;
+// json fields blanked out of recorded bodies, zoom's start_url logs in as the host
+const SECRET_FIELDS: [&str; 8] = [
+    "start_url",
+    "access_token",
+    "refresh_token",
+    "id_token",
+    "client_secret",
+    "password",
+    "encrypted_password",
+    "h323_password",
+];
+const REDACTED: &str = "[redacted]";
+
+static DRIVE: OnceLock<String> = OnceLock::new();
+// the day old files were last pruned on
+static PRUNED: Mutex<Option<NaiveDate>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Prompt {
+    // `get_default_prompt`, the owner
+    Default,
+    // `get_schedule_prompt`, a guest
+    Schedule,
+    // a command handled without the LLM
+    Command,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    // rfc3339
+    pub at: String,
+    pub chat_id: i64,
+    pub user_id: u64,
+    pub user_name: String,
+    pub text: String,
+    pub prompt: Prompt,
+    pub llm_response: Option<String>,
+    // the line before ENDMARKER, if the LLM gave one
+    pub command: Option<String>,
+    // `google_calls` in entries written before zoom calls were recorded too
+    #[serde(default, alias = "google_calls")]
+    pub http_calls: Vec<HttpCall>,
+    pub reply: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpCall {
+    pub method: String,
+    pub url: String,
+    // bodies only with `State::audit_bodies`, secrets redacted
+    #[serde(default)]
+    pub request: Option<String>,
+    // None if the request never got an answer
+    pub status: Option<u16>,
+    #[serde(default)]
+    pub response: Option<String>,
+    pub error: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(chat_id: i64, user: &frankenstein::User, text: &str, prompt: Prompt) -> Self {
+        AuditEntry {
+            at: Utc::now().to_rfc3339(),
+            chat_id,
+            user_id: user.id,
+            user_name: display_name(user),
+            text: text.to_string(),
+            prompt,
+            llm_response: None,
+            command: None,
+            http_calls: vec![],
+            reply: None,
+            error: None,
+        }
+    }
+
+    pub fn set_llm_response(&mut self, response: &str) {
+        self.llm_response = Some(truncate(response));
+        self.command = llm_command(response).map(str::to_string);
+    }
+
+    pub fn finish(&mut self, calls: Vec<HttpCall>, reply: &anyhow::Result<Reply>) {
+        self.http_calls = calls;
+        match reply {
+            Ok(reply) => self.reply = Some(reply.text.clone()),
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+}
+
+fn truncate(text: &str) -> String {
+    match text.char_indices().nth(MAX_FIELD_LEN) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// `body` as it goes in the log: json with its `SECRET_FIELDS` blanked, anything
+/// else only by size since there's no telling what's in it.
+fn redact(body: &[u8]) -> Option<String> {
+    if body.is_empty() {
+        return None;
+    }
+    let Ok(mut json) = serde_json::from_slice::<serde_json::Value>(body) else {
+        return Some(format!("[{} bytes]", body.len()));
+    };
+    redact_value(&mut json);
+    Some(truncate(&json.to_string()))
+}
+
+fn redact_value(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, value) in fields.iter_mut() {
+                if SECRET_FIELDS.contains(&key.as_str()) {
+                    *value = REDACTED.into();
+                } else {
+                    redact_value(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact_value),
+        _ => {}
+    }
+}
+
+/// Opens the audit drive. Until this runs, entries are dropped.
+pub fn init(our: &Address) -> anyhow::Result<()> {
+    let drive = vfs::create_drive(our.package_id(), "audit", None)?;
+    let _ = DRIVE.set(drive);
+    prune(Utc::now().date_naive())
+}
+
+fn drive() -> anyhow::Result<&'static str> {
+    DRIVE
+        .get()
+        .map(|drive| drive.as_str())
+        .ok_or_else(|| anyhow::anyhow!("audit drive not opened"))
+}
+
+fn day_path(drive: &str, day: NaiveDate) -> String {
+    format!("{}/{}.jsonl", drive, day.format("%Y-%m-%d"))
+}
+
+pub fn append(entry: &AuditEntry) -> anyhow::Result<()> {
+    let today = Utc::now().date_naive();
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    let mut file = vfs::open_file(&day_path(drive()?, today), true, None)?;
+    file.append(&line)?;
+
+    let pruned = *PRUNED.lock().unwrap();
+    if pruned != Some(today) {
+        prune(today)?;
+    }
+    Ok(())
+}
+
+/// Deletes the day files that fell out of `RETENTION_DAYS`.
+fn prune(today: NaiveDate) -> anyhow::Result<()> {
+    let oldest = today - Duration::days(RETENTION_DAYS - 1);
+    let dir = vfs::open_dir(drive()?, false, None)?;
+    for entry in dir.read()? {
+        if entry.file_type != FileType::File {
+            continue;
+        }
+        let Some(day) = file_day(&entry.path) else {
+            continue;
+        };
+        if day < oldest {
+            vfs::remove_file(&entry.path, None)?;
+        }
+    }
+    *PRUNED.lock().unwrap() = Some(today);
+    Ok(())
+}
+
+fn file_day(path: &str) -> Option<NaiveDate> {
+    let name = path.rsplit('/').next()?.strip_suffix(".jsonl")?;
+    NaiveDate::parse_from_str(name, "%Y-%m-%d").ok()
+}
+
+pub struct Query {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub user_id: Option<u64>,
+    pub limit: usize,
+}
+
+/// Entries between the two days, inclusive, newest first.
+pub fn query(query: &Query) -> anyhow::Result<Vec<AuditEntry>> {
+    let drive = drive()?;
+    let mut entries = vec![];
+    let mut day = query.to;
+    while day >= query.from && entries.len() < query.limit {
+        let mut daily = read_day(drive, day)?;
+        daily.retain(|entry| query.user_id.is_none() || query.user_id == Some(entry.user_id));
+        daily.reverse();
+        entries.extend(daily.into_iter().take(query.limit - entries.len()));
+        day -= Duration::days(1);
+    }
+    Ok(entries)
+}
+
+fn read_day(drive: &str, day: NaiveDate) -> anyhow::Result<Vec<AuditEntry>> {
+    let Ok(file) = vfs::open_file(&day_path(drive, day), false, None) else {
+        return Ok(vec![]);
+    };
+    let content = file.read_to_string()?;
+    // a line cut short by a crash mid-write is skipped, not fatal
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Wraps a backend to note down every HTTP call made through it: method, url,
+/// status and error, and with `bodies` what was sent and answered.
+pub struct Recorder<'a, B> {
+    inner: &'a B,
+    bodies: bool,
+    calls: RefCell<Vec<HttpCall>>,
+}
+
+impl<'a, B> Recorder<'a, B> {
+    pub fn new(inner: &'a B, bodies: bool) -> Self {
+        Recorder {
+            inner,
+            bodies,
+            calls: RefCell::new(vec![]),
+        }
+    }
+
+    pub fn take_calls(&self) -> Vec<HttpCall> {
+        self.calls.take()
+    }
+}
+
+impl<B> Wraps for Recorder<'_, B> {
+    type Inner = B;
+
+    fn inner(&self) -> &B {
+        self.inner
+    }
+}
+
+impl<B: Http> Http for Recorder<'_, B> {
+    fn request(
+        &self,
+        method: http::Method,
+        url: Url,
+        headers: HashMap<String, String>,
+        timeout: u64,
+        body: Vec<u8>,
+    ) -> anyhow::Result<HttpResponse> {
+        // headers hold the bearer token, they stay out of the log.
+        let mut call = HttpCall {
+            method: method.to_string(),
+            url: url.to_string(),
+            request: self.bodies.then(|| redact(&body)).flatten(),
+            status: None,
+            response: None,
+            error: None,
+        };
+        let result = self.inner.request(method, url, headers, timeout, body);
+        match &result {
+            Ok(res) => {
+                call.status = Some(res.status().as_u16());
+                call.response = self.bodies.then(|| redact(res.body())).flatten();
+            }
+            Err(e) => call.error = Some(e.to_string()),
+        }
+        self.calls.borrow_mut().push(call);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    fn create_meeting(recorder: &Recorder<MockBackend>) -> HttpCall {
+        let url = Url::parse("https://api.zoom.us/v2/users/me/meetings").unwrap();
+        let body = br#"{"topic":"Sync","settings":{"password":"123456"}}"#.to_vec();
+        recorder
+            .request(http::Method::POST, url, HashMap::new(), 5, body)
+            .unwrap();
+        recorder.take_calls().pop().unwrap()
+    }
+
+    #[test]
+    fn bodies_are_left_out_unless_asked_for() {
+        let backend = MockBackend::default();
+        let call = create_meeting(&Recorder::new(&backend, false));
+        assert_eq!(call.method, "POST");
+        assert_eq!(call.status, Some(201));
+        assert_eq!((call.request, call.response), (None, None));
+    }
+
+    #[test]
+    fn recorded_bodies_have_their_secrets_redacted() {
+        let backend = MockBackend::default();
+        let call = create_meeting(&Recorder::new(&backend, true));
+        let request = call.request.unwrap();
+        assert!(
+            request.contains("Sync") && !request.contains("123456"),
+            "{}",
+            request
+        );
+        let response = call.response.unwrap();
+        assert!(response.contains("https://zoom.us/j/1"), "{}", response);
+        assert!(!response.contains("host-token"), "{}", response);
+        assert!(
+            response.contains(r#""start_url":"[redacted]""#),
+            "{}",
+            response
+        );
+        assert_eq!(redact(b"not json"), Some("[8 bytes]".to_string()));
+    }
+}
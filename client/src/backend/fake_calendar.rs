@@ -0,0 +1,241 @@
+//! An in-memory stand-in for the Google Calendar API, answering the requests the
+//! client makes the way Google would: events are inserted, listed, patched and
+//! deleted, Meet links are made when asked for, and wrong tokens get a 401.
+use super::HttpResponse;
+use chrono::{DateTime, SecondsFormat, Utc};
+use kinode_process_lib::http::{Method, StatusCode};
+use serde_json::{json, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use url::Url;
+
+pub const TOKEN: &str = "fake-google-token";
+const PREFIX: &str = "/calendar/v3";
+
+pub struct FakeCalendar {
+    // as google returns them, in insertion order
+    events: RefCell<Vec<Value>>,
+    next_id: Cell<u32>,
+    pub time_zone: String,
+    // allowedConferenceSolutionTypes of the primary calendar
+    pub conference_types: Vec<String>,
+    // the access token google accepts
+    pub token: RefCell<String>,
+    // method and url of every request, in order
+    pub requests: RefCell<Vec<(Method, Url)>>,
+    // when set, event inserts fail with this status
+    pub fail_inserts: Cell<Option<StatusCode>>,
+}
+
+impl Default for FakeCalendar {
+    fn default() -> Self {
+        FakeCalendar {
+            events: RefCell::new(vec![]),
+            next_id: Cell::new(1),
+            time_zone: "Europe/Berlin".to_string(),
+            conference_types: vec!["hangoutsMeet".to_string()],
+            token: RefCell::new(TOKEN.to_string()),
+            requests: RefCell::new(vec![]),
+            fail_inserts: Cell::new(None),
+        }
+    }
+}
+
+impl FakeCalendar {
+    /// Adds an event as if it had been made elsewhere, returning its id.
+    pub fn insert(&self, mut event: Value) -> String {
+        let id = event["id"]
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| self.new_id());
+        event["id"] = id.clone().into();
+        event["status"] = event.get("status").cloned().unwrap_or("confirmed".into());
+        event["updated"] = now().into();
+        if event.get("created").is_none() {
+            event["created"] = event["updated"].clone();
+        }
+        self.events.borrow_mut().push(event);
+        id
+    }
+
+    /// Changes an event as if it had been edited elsewhere.
+    pub fn patch(&self, id: &str, patch: Value) {
+        self.one(Method::PATCH, id, patch);
+    }
+
+    pub fn events(&self) -> Vec<Value> {
+        self.events.borrow().clone()
+    }
+
+    pub fn event(&self, id: &str) -> Option<Value> {
+        self.events
+            .borrow()
+            .iter()
+            .find(|event| event["id"] == id)
+            .cloned()
+    }
+
+    /// How many requests went to urls with this path, after the `/calendar/v3` prefix.
+    pub fn calls(&self, method: Method, path: &str) -> usize {
+        self.requests
+            .borrow()
+            .iter()
+            .filter(|(m, url)| *m == method && url.path() == format!("{}{}", PREFIX, path))
+            .count()
+    }
+
+    fn new_id(&self) -> String {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        format!("event{}", id)
+    }
+
+    pub fn handle(
+        &self,
+        method: Method,
+        url: Url,
+        headers: &HashMap<String, String>,
+        body: &[u8],
+    ) -> HttpResponse {
+        self.requests
+            .borrow_mut()
+            .push((method.clone(), url.clone()));
+        let bearer = format!("Bearer {}", self.token.borrow());
+        if headers.get("Authorization") != Some(&bearer) {
+            return error(StatusCode::UNAUTHORIZED, "invalid credentials");
+        }
+        let query: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        let body: Value = serde_json::from_slice(body).unwrap_or(Value::Null);
+        let path = url.path().strip_prefix(PREFIX).unwrap_or_default();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+        match (method, &segments[..]) {
+            (Method::GET, ["users", "me", "settings", "timezone"]) => {
+                ok(json!({ "value": self.time_zone }))
+            }
+            (Method::GET, ["calendars", "primary"]) => ok(json!({
+                "kind": "calendar#calendar",
+                "id": "owner@example.com",
+                "summary": "owner@example.com",
+                "timeZone": self.time_zone,
+                "conferenceProperties": {
+                    "allowedConferenceSolutionTypes": self.conference_types,
+                },
+            })),
+            (Method::GET, ["calendars", "primary", "events"]) => ok(self.list(&query)),
+            (Method::POST, ["calendars", "primary", "events"]) => {
+                if let Some(status) = self.fail_inserts.get() {
+                    return error(status, "insert failed");
+                }
+                let mut event = body;
+                if query.get("conferenceDataVersion").map(String::as_str) == Some("1")
+                    && event["conferenceData"]["createRequest"].is_object()
+                {
+                    let link = format!("https://meet.google.com/fake-{}", self.next_id.get());
+                    let data = &mut event["conferenceData"];
+                    data["createRequest"]["status"] = json!({ "statusCode": "success" });
+                    data["conferenceSolution"] = json!({
+                        "key": { "type": "hangoutsMeet" },
+                        "name": "Google Meet",
+                        "iconUri": "https://fonts.gstatic.com/s/i/productlogos/meet_2020q4/v6/web-512dp/logo_meet_2020q4_color_2x_web_512dp.png",
+                    });
+                    data["entryPoints"] = json!([{ "entryPointType": "video", "uri": link }]);
+                    event["hangoutLink"] = link.into();
+                }
+                let id = self.insert(event);
+                ok(self.event(&id).unwrap_or_default())
+            }
+            (method, ["calendars", "primary", "events", id]) => self.one(method, id, body),
+            (Method::POST, ["freeBusy"]) => ok(self.free_busy(&body)),
+            _ => error(StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+
+    fn one(&self, method: Method, id: &str, patch: Value) -> HttpResponse {
+        let mut events = self.events.borrow_mut();
+        let Some(index) = events.iter().position(|event| event["id"] == id) else {
+            return error(StatusCode::NOT_FOUND, "not found");
+        };
+        match method {
+            Method::GET => ok(events[index].clone()),
+            Method::PATCH => {
+                let event = &mut events[index];
+                if let (Some(event), Some(patch)) = (event.as_object_mut(), patch.as_object()) {
+                    for (key, value) in patch {
+                        event.insert(key.clone(), value.clone());
+                    }
+                    event.insert("updated".to_string(), now().into());
+                }
+                ok(event.clone())
+            }
+            Method::DELETE => {
+                events.remove(index);
+                HttpResponse::new(StatusCode::NO_CONTENT, vec![])
+            }
+            _ => error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed"),
+        }
+    }
+
+    fn list(&self, query: &HashMap<String, String>) -> Value {
+        let bound = |name: &str| query.get(name).and_then(|time| parse(time));
+        let (time_min, time_max, updated_min) =
+            (bound("timeMin"), bound("timeMax"), bound("updatedMin"));
+        let mut items: Vec<Value> = self
+            .events
+            .borrow()
+            .iter()
+            .filter(|event| {
+                let start = parse(event["start"]["dateTime"].as_str().unwrap_or_default());
+                let end = parse(event["end"]["dateTime"].as_str().unwrap_or_default());
+                let updated = parse(event["updated"].as_str().unwrap_or_default());
+                time_min.is_none_or(|min| end.is_some_and(|end| end > min))
+                    && time_max.is_none_or(|max| start.is_some_and(|start| start < max))
+                    && updated_min.is_none_or(|min| updated.is_some_and(|updated| updated >= min))
+            })
+            .cloned()
+            .collect();
+        items.sort_by_key(|event| parse(event["start"]["dateTime"].as_str().unwrap_or_default()));
+        json!({ "kind": "calendar#events", "defaultReminders": [], "items": items })
+    }
+
+    fn free_busy(&self, body: &Value) -> Value {
+        let busy: Vec<Value> = self
+            .events
+            .borrow()
+            .iter()
+            .map(|event| json!({ "start": event["start"]["dateTime"], "end": event["end"]["dateTime"] }))
+            .collect();
+        let mut calendars = serde_json::Map::new();
+        for item in body["items"].as_array().into_iter().flatten() {
+            let id = item["id"].as_str().unwrap_or_default().to_string();
+            let calendar = match id.as_str() {
+                "primary" => json!({ "busy": busy }),
+                _ => json!({ "errors": [{ "domain": "global", "reason": "notFound" }] }),
+            };
+            calendars.insert(id, calendar);
+        }
+        json!({ "calendars": calendars })
+    }
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn parse(time: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+fn ok(body: Value) -> HttpResponse {
+    HttpResponse::new(
+        StatusCode::OK,
+        serde_json::to_vec(&body).unwrap_or_default(),
+    )
+}
+
+fn error(status: StatusCode, message: &str) -> HttpResponse {
+    let body = json!({ "error": { "code": status.as_u16(), "message": message } });
+    HttpResponse::new(status, serde_json::to_vec(&body).unwrap_or_default())
+}
@@ -0,0 +1,147 @@
+//! A backend for tests: the LLM and transcription give canned answers, the bot
+//! records what it would have sent, and HTTP goes to a `FakeCalendar` or, for
+//! Zoom, to a counter of meetings made.
+use super::{fake_calendar::FakeCalendar, Http, HttpResponse, Llm, Stt, Telegram};
+use crate::stt::SttOptions;
+use frankenstein::{InlineKeyboardMarkup, Message as TgMessage};
+use kinode_process_lib::http::{Method, StatusCode};
+use serde_json::json;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use url::Url;
+
+#[derive(Default)]
+pub struct MockBackend {
+    pub calendar: FakeCalendar,
+    // handed out in order, one per prompt
+    answers: RefCell<VecDeque<String>>,
+    pub prompts: RefCell<Vec<String>>,
+    // chat id and text of everything the bot sent or edited
+    pub sent: RefCell<Vec<(i64, String)>>,
+    pub transcript: Option<String>,
+    // zoom meetings made, and the ids of those deleted again
+    pub zoom_meetings: Cell<u32>,
+    pub zoom_deleted: RefCell<Vec<u64>>,
+}
+
+impl MockBackend {
+    /// A backend whose LLM answers `answers`, in order.
+    pub fn answering(answers: &[&str]) -> Self {
+        MockBackend {
+            answers: RefCell::new(answers.iter().map(|answer| answer.to_string()).collect()),
+            ..Default::default()
+        }
+    }
+
+    fn message(&self, chat_id: i64, text: &str) -> anyhow::Result<TgMessage> {
+        self.sent.borrow_mut().push((chat_id, text.to_string()));
+        let id = self.sent.borrow().len();
+        Ok(serde_json::from_value(json!({
+            "message_id": id,
+            "date": 0,
+            "chat": { "id": chat_id, "type": "private" },
+            "text": text,
+        }))?)
+    }
+}
+
+impl Http for MockBackend {
+    fn request(
+        &self,
+        method: Method,
+        url: Url,
+        headers: HashMap<String, String>,
+        _timeout: u64,
+        body: Vec<u8>,
+    ) -> anyhow::Result<HttpResponse> {
+        match url.host_str() {
+            Some("www.googleapis.com") => Ok(self.calendar.handle(method, url, &headers, &body)),
+            Some("api.zoom.us") if method == Method::DELETE => {
+                let id = url.path().rsplit('/').next().unwrap_or_default();
+                self.zoom_deleted.borrow_mut().push(id.parse()?);
+                Ok(HttpResponse::new(StatusCode::NO_CONTENT, vec![]))
+            }
+            Some("api.zoom.us") => {
+                let n = self.zoom_meetings.get() + 1;
+                self.zoom_meetings.set(n);
+                let meeting = json!({
+                    "id": n,
+                    "join_url": format!("https://zoom.us/j/{}", n),
+                    "start_url": format!("https://zoom.us/s/{}?zak=host-token", n),
+                });
+                Ok(HttpResponse::new(
+                    StatusCode::CREATED,
+                    serde_json::to_vec(&meeting)?,
+                ))
+            }
+            _ => Ok(HttpResponse::new(StatusCode::NOT_FOUND, vec![])),
+        }
+    }
+}
+
+impl Llm for MockBackend {
+    fn answer(&self, prompt: &str) -> anyhow::Result<String> {
+        self.prompts.borrow_mut().push(prompt.to_string());
+        self.answers
+            .borrow_mut()
+            .pop_front()
+            .ok_or_else(|| anyhow::anyhow!("no canned answer left for: {}", prompt))
+    }
+}
+
+impl Telegram for MockBackend {
+    fn send_message(&self, chat_id: i64, text: &str) -> anyhow::Result<TgMessage> {
+        self.message(chat_id, text)
+    }
+
+    fn send_keyboard(
+        &self,
+        chat_id: i64,
+        text: &str,
+        _keyboard: InlineKeyboardMarkup,
+    ) -> anyhow::Result<TgMessage> {
+        self.message(chat_id, text)
+    }
+
+    fn send_html(
+        &self,
+        chat_id: i64,
+        html: &str,
+        _keyboard: Option<InlineKeyboardMarkup>,
+    ) -> anyhow::Result<TgMessage> {
+        self.message(chat_id, html)
+    }
+
+    fn send_voice(
+        &self,
+        chat_id: i64,
+        _audio: Vec<u8>,
+        caption: Option<&str>,
+    ) -> anyhow::Result<TgMessage> {
+        self.message(chat_id, caption.unwrap_or("(voice)"))
+    }
+
+    fn edit_message(&self, chat_id: i64, _message_id: i32, text: &str) -> anyhow::Result<()> {
+        self.message(chat_id, text).map(|_| ())
+    }
+
+    fn answer_callback(&self, _query_id: &str, _text: Option<&str>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn bot_username(&self) -> anyhow::Result<String> {
+        Ok("kcal_test_bot".to_string())
+    }
+
+    fn get_file(&self, file_id: &str) -> anyhow::Result<Vec<u8>> {
+        Err(anyhow::anyhow!("no file {}", file_id))
+    }
+}
+
+impl Stt for MockBackend {
+    fn transcribe(&self, _audio: Vec<u8>, _options: &SttOptions) -> anyhow::Result<String> {
+        self.transcript
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("nothing to transcribe"))
+    }
+}
@@ -0,0 +1,246 @@
+//! External calls the client makes, behind traits so the message handling logic
+//! doesn't depend on a running node. `Kinode` is the real implementation, tests
+//! use `mock::MockBackend` with a `fake_calendar::FakeCalendar` behind it.
+use crate::{
+    groq,
+    stt::{self, SttOptions},
+    tg,
+};
+use frankenstein::{InlineKeyboardMarkup, Message as TgMessage};
+use kinode_process_lib::http;
+use std::collections::HashMap;
+use url::Url;
+
+#[cfg(test)]
+pub mod fake_calendar;
+#[cfg(test)]
+pub mod mock;
+
+pub struct HttpResponse {
+    status: http::StatusCode,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn new(status: http::StatusCode, body: Vec<u8>) -> Self {
+        HttpResponse { status, body }
+    }
+
+    pub fn status(&self) -> http::StatusCode {
+        self.status
+    }
+
+    pub fn body(&self) -> &Vec<u8> {
+        &self.body
+    }
+}
+
+/// Outgoing HTTP, mostly the Google Calendar API.
+pub trait Http {
+    fn request(
+        &self,
+        method: http::Method,
+        url: Url,
+        headers: HashMap<String, String>,
+        timeout: u64,
+        body: Vec<u8>,
+    ) -> anyhow::Result<HttpResponse>;
+}
+
+/// Chat completions, through the openai process.
+pub trait Llm {
+    fn answer(&self, prompt: &str) -> anyhow::Result<String>;
+}
+
+/// The telegram bot, through the tg process or the bot api where it falls short.
+pub trait Telegram {
+    fn send_message(&self, chat_id: i64, text: &str) -> anyhow::Result<TgMessage>;
+    fn send_keyboard(
+        &self,
+        chat_id: i64,
+        text: &str,
+        keyboard: InlineKeyboardMarkup,
+    ) -> anyhow::Result<TgMessage>;
+    /// `html` in the HTML parse mode, see `tg::format`.
+    fn send_html(
+        &self,
+        chat_id: i64,
+        html: &str,
+        keyboard: Option<InlineKeyboardMarkup>,
+    ) -> anyhow::Result<TgMessage>;
+    fn send_voice(
+        &self,
+        chat_id: i64,
+        audio: Vec<u8>,
+        caption: Option<&str>,
+    ) -> anyhow::Result<TgMessage>;
+    fn edit_message(&self, chat_id: i64, message_id: i32, text: &str) -> anyhow::Result<()>;
+    fn answer_callback(&self, query_id: &str, text: Option<&str>) -> anyhow::Result<()>;
+    fn bot_username(&self) -> anyhow::Result<String>;
+    fn get_file(&self, file_id: &str) -> anyhow::Result<Vec<u8>>;
+}
+
+/// Transcription of voice messages, by the provider in `options`.
+pub trait Stt {
+    fn transcribe(&self, audio: Vec<u8>, options: &SttOptions) -> anyhow::Result<String>;
+}
+
+/// Everything a telegram message might need.
+pub trait Backend: Http + Llm + Telegram + Stt {}
+
+impl<T: Http + Llm + Telegram + Stt> Backend for T {}
+
+/// A backend that changes some of another one's calls, like `audit::Recorder`
+/// does with HTTP. It implements those itself, the LLM, bot and transcription
+/// are handed to `inner` here so a new trait method only needs forwarding once.
+pub trait Wraps {
+    type Inner;
+    fn inner(&self) -> &Self::Inner;
+}
+
+impl<W: Wraps> Llm for W
+where
+    W::Inner: Llm,
+{
+    fn answer(&self, prompt: &str) -> anyhow::Result<String> {
+        self.inner().answer(prompt)
+    }
+}
+
+impl<W: Wraps> Telegram for W
+where
+    W::Inner: Telegram,
+{
+    fn send_message(&self, chat_id: i64, text: &str) -> anyhow::Result<TgMessage> {
+        self.inner().send_message(chat_id, text)
+    }
+
+    fn send_keyboard(
+        &self,
+        chat_id: i64,
+        text: &str,
+        keyboard: InlineKeyboardMarkup,
+    ) -> anyhow::Result<TgMessage> {
+        self.inner().send_keyboard(chat_id, text, keyboard)
+    }
+
+    fn send_html(
+        &self,
+        chat_id: i64,
+        html: &str,
+        keyboard: Option<InlineKeyboardMarkup>,
+    ) -> anyhow::Result<TgMessage> {
+        self.inner().send_html(chat_id, html, keyboard)
+    }
+
+    fn send_voice(
+        &self,
+        chat_id: i64,
+        audio: Vec<u8>,
+        caption: Option<&str>,
+    ) -> anyhow::Result<TgMessage> {
+        self.inner().send_voice(chat_id, audio, caption)
+    }
+
+    fn edit_message(&self, chat_id: i64, message_id: i32, text: &str) -> anyhow::Result<()> {
+        self.inner().edit_message(chat_id, message_id, text)
+    }
+
+    fn answer_callback(&self, query_id: &str, text: Option<&str>) -> anyhow::Result<()> {
+        self.inner().answer_callback(query_id, text)
+    }
+
+    fn bot_username(&self) -> anyhow::Result<String> {
+        self.inner().bot_username()
+    }
+
+    fn get_file(&self, file_id: &str) -> anyhow::Result<Vec<u8>> {
+        self.inner().get_file(file_id)
+    }
+}
+
+impl<W: Wraps> Stt for W
+where
+    W::Inner: Stt,
+{
+    fn transcribe(&self, audio: Vec<u8>, options: &SttOptions) -> anyhow::Result<String> {
+        self.inner().transcribe(audio, options)
+    }
+}
+
+pub struct Kinode;
+
+impl Http for Kinode {
+    fn request(
+        &self,
+        method: http::Method,
+        url: Url,
+        headers: HashMap<String, String>,
+        timeout: u64,
+        body: Vec<u8>,
+    ) -> anyhow::Result<HttpResponse> {
+        let res = http::send_request_await_response(method, url, Some(headers), timeout, body)?;
+        Ok(HttpResponse::new(res.status(), res.body().clone()))
+    }
+}
+
+impl Llm for Kinode {
+    fn answer(&self, prompt: &str) -> anyhow::Result<String> {
+        groq::get_groq_answer(prompt)
+    }
+}
+
+impl Telegram for Kinode {
+    fn send_message(&self, chat_id: i64, text: &str) -> anyhow::Result<TgMessage> {
+        tg::send_bot_message(text, chat_id)
+    }
+
+    fn send_keyboard(
+        &self,
+        chat_id: i64,
+        text: &str,
+        keyboard: InlineKeyboardMarkup,
+    ) -> anyhow::Result<TgMessage> {
+        tg::send_bot_keyboard(text, chat_id, keyboard)
+    }
+
+    fn send_html(
+        &self,
+        chat_id: i64,
+        html: &str,
+        keyboard: Option<InlineKeyboardMarkup>,
+    ) -> anyhow::Result<TgMessage> {
+        tg::send_bot_html(html, chat_id, keyboard)
+    }
+
+    fn send_voice(
+        &self,
+        chat_id: i64,
+        audio: Vec<u8>,
+        caption: Option<&str>,
+    ) -> anyhow::Result<TgMessage> {
+        tg::send_bot_voice(chat_id, audio, caption)
+    }
+
+    fn edit_message(&self, chat_id: i64, message_id: i32, text: &str) -> anyhow::Result<()> {
+        tg::edit_bot_message(text, chat_id, message_id)
+    }
+
+    fn answer_callback(&self, query_id: &str, text: Option<&str>) -> anyhow::Result<()> {
+        tg::answer_callback_query(query_id, text)
+    }
+
+    fn bot_username(&self) -> anyhow::Result<String> {
+        tg::bot_username()
+    }
+
+    fn get_file(&self, file_id: &str) -> anyhow::Result<Vec<u8>> {
+        tg::get_file(file_id)
+    }
+}
+
+impl Stt for Kinode {
+    fn transcribe(&self, audio: Vec<u8>, options: &SttOptions) -> anyhow::Result<String> {
+        stt::transcribe(self, audio, options)
+    }
+}
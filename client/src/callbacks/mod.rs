@@ -0,0 +1,60 @@
+//! Inline keyboard buttons and what pressing one means. A button's callback data
+//! starts with what it belongs to, so every press telegram sends back can be
+//! routed from one place.
+use crate::confirm::Choice;
+use frankenstein::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+// telegram rejects callback data longer than this, in bytes
+const MAX_DATA_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Callback {
+    // a button under a confirm mode preview, with the preview's id
+    Preview(Choice, u32),
+    // a free slot from `/free`, unix secs
+    Slot { start: i64, end: i64 },
+    // the owner approving the calendar a group member asked to `/link`
+    Link(u64),
+}
+
+impl Callback {
+    fn data(&self) -> String {
+        match self {
+            Callback::Preview(choice, id) => format!("preview:{}:{}", choice.as_str(), id),
+            Callback::Slot { start, end } => format!("slot:{}:{}", start, end),
+            Callback::Link(user_id) => format!("link:{}", user_id),
+        }
+    }
+
+    pub fn parse(data: &str) -> Option<Self> {
+        let mut parts = data.split(':');
+        let callback = match parts.next()? {
+            "preview" => {
+                let choice = Choice::parse(parts.next()?)?;
+                Callback::Preview(choice, parts.next()?.parse().ok()?)
+            }
+            "slot" => Callback::Slot {
+                start: parts.next()?.parse().ok()?,
+                end: parts.next()?.parse().ok()?,
+            },
+            "link" => Callback::Link(parts.next()?.parse().ok()?),
+            _ => return None,
+        };
+        parts.next().is_none().then_some(callback)
+    }
+
+    pub fn button(&self, label: &str) -> InlineKeyboardButton {
+        let data = self.data();
+        debug_assert!(data.len() <= MAX_DATA_LEN);
+        InlineKeyboardButton::builder()
+            .text(label)
+            .callback_data(data)
+            .build()
+    }
+}
+
+pub fn keyboard(rows: Vec<Vec<InlineKeyboardButton>>) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::builder()
+        .inline_keyboard(rows)
+        .build()
+}
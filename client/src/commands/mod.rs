@@ -0,0 +1,904 @@
+//! Slash commands, answered in Rust without asking the LLM. Text that isn't one of
+//! these, unknown commands included, still goes to the LLM.
+use crate::{
+    attendees,
+    backend::Backend,
+    callbacks::{self, Callback},
+    disconnect,
+    gcal::{
+        helpers::{get_busy, get_events_from_primary_calendar, update_event, Action, Reply},
+        Event, EventAttendees, SendUpdates,
+    },
+    groups::{self, Group, Member},
+    meetings::Solution,
+    save, set_rsvp_alerts,
+    stt::SttProvider,
+    tg::{display_name, format},
+    timezones,
+    undo::Inverse,
+    undo_last, with_google_token, State,
+};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, SecondsFormat, TimeZone, Utc};
+use chrono_tz::Tz;
+use frankenstein::{Chat, User};
+use url::Url;
+
+// /free only offers weekdays between these hours, owner time
+const WORK_START_HOUR: u32 = 9;
+const WORK_END_HOUR: u32 = 18;
+const MIN_FREE_MINUTES: i64 = 30;
+const MAX_FREE_SLOTS: usize = 15;
+// the first few slots also get a button that asks for a meeting of this length
+const MAX_SLOT_BUTTONS: usize = 6;
+const SLOT_MEETING_MINUTES: i64 = 30;
+// how far /next looks ahead
+const NEXT_DAYS: i64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Today,
+    Tomorrow,
+    Week,
+    Next,
+    Free,
+    Invite,
+    Uninvite,
+    Rsvp,
+    Cancel,
+    Undo,
+    Settings,
+    Timezone,
+    Language,
+    Link,
+    Unlink,
+    Disconnect,
+    Help,
+}
+
+struct CommandInfo {
+    command: Command,
+    name: &'static str,
+    description: &'static str,
+    owner_only: bool,
+}
+
+const COMMANDS: &[CommandInfo] = &[
+    CommandInfo {
+        command: Command::Today,
+        name: "today",
+        description: "Today's events",
+        owner_only: true,
+    },
+    CommandInfo {
+        command: Command::Tomorrow,
+        name: "tomorrow",
+        description: "Tomorrow's events",
+        owner_only: true,
+    },
+    CommandInfo {
+        command: Command::Week,
+        name: "week",
+        description: "Events in the next 7 days",
+        owner_only: true,
+    },
+    CommandInfo {
+        command: Command::Next,
+        name: "next",
+        description: "The next event",
+        owner_only: true,
+    },
+    CommandInfo {
+        command: Command::Free,
+        name: "free",
+        description: "Free slots, /free today, tomorrow or week",
+        owner_only: false,
+    },
+    CommandInfo {
+        command: Command::Invite,
+        name: "invite",
+        description: "Invite people, e.g. /invite ana@example.com to friday standup",
+        owner_only: true,
+    },
+    CommandInfo {
+        command: Command::Uninvite,
+        name: "uninvite",
+        description: "Take people off an event, e.g. /uninvite ana from friday standup",
+        owner_only: true,
+    },
+    CommandInfo {
+        command: Command::Rsvp,
+        name: "rsvp",
+        description: "Who answered, e.g. /rsvp friday standup",
+        owner_only: true,
+    },
+    CommandInfo {
+        command: Command::Cancel,
+        name: "cancel",
+        description: "Drop the booking waiting for confirmation",
+        owner_only: false,
+    },
+    CommandInfo {
+        command: Command::Undo,
+        name: "undo",
+        description: "Revert the last calendar change the bot made for you",
+        owner_only: false,
+    },
+    CommandInfo {
+        command: Command::Settings,
+        name: "settings",
+        description: "Show settings, /settings confirm, voice, heard or rsvp on|off",
+        owner_only: true,
+    },
+    CommandInfo {
+        command: Command::Timezone,
+        name: "timezone",
+        description: "Show or set your timezone, e.g. /timezone Europe/Berlin",
+        owner_only: false,
+    },
+    CommandInfo {
+        command: Command::Language,
+        name: "language",
+        description: "Language of your voice messages, e.g. /language de, or auto",
+        owner_only: false,
+    },
+    CommandInfo {
+        command: Command::Link,
+        name: "link",
+        description: "In a group, add your calendar to /free once the owner approves, e.g. /link you@example.com",
+        owner_only: false,
+    },
+    CommandInfo {
+        command: Command::Unlink,
+        name: "unlink",
+        description: "In a group, take your calendar out of /free",
+        owner_only: false,
+    },
+    CommandInfo {
+        command: Command::Disconnect,
+        name: "disconnect",
+        description: "Disconnect the Google account",
+        owner_only: true,
+    },
+    CommandInfo {
+        command: Command::Help,
+        name: "help",
+        description: "What the bot can do",
+        owner_only: false,
+    },
+];
+
+fn info(command: Command) -> &'static CommandInfo {
+    COMMANDS
+        .iter()
+        .find(|info| info.command == command)
+        .expect("every command is listed")
+}
+
+/// Name and description of every command, for Telegram's command menu.
+pub fn menu() -> Vec<(&'static str, &'static str)> {
+    COMMANDS
+        .iter()
+        .map(|info| (info.name, info.description))
+        .collect()
+}
+
+/// The command and its arguments, if the text is a known command.
+pub fn parse(text: &str) -> Option<(Command, String)> {
+    let rest = text.trim().strip_prefix('/')?;
+    let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    // in groups commands come as /today@botname
+    let name = name.split('@').next().unwrap_or(name).to_lowercase();
+    let command = match name.as_str() {
+        // sent by telegram when someone first opens the bot
+        "start" => Command::Help,
+        name => COMMANDS.iter().find(|info| info.name == name)?.command,
+    };
+    Some((command, args.trim().to_string()))
+}
+
+pub fn run(
+    backend: &impl Backend,
+    state: &mut State,
+    chat: &Chat,
+    user: &User,
+    command: Command,
+    args: &str,
+) -> anyhow::Result<Reply> {
+    let owner = state.user_id == Some(user.id);
+    if info(command).owner_only && !owner {
+        return Ok(reply("Only the calendar owner can use that."));
+    }
+    let chat_id = chat.id;
+    let tz = timezones::parse_tz(&state.timezone);
+    let now = Utc::now();
+
+    match command {
+        Command::Today | Command::Tomorrow => {
+            let days = if command == Command::Today { 0 } else { 1 };
+            let day = now.with_timezone(&tz).date_naive() + Duration::days(days);
+            let (Some(from), Some(to)) = (midnight(day, tz), midnight(day + Duration::days(1), tz))
+            else {
+                return Err(anyhow::anyhow!("no midnight on {} in {}", day, tz));
+            };
+            let events = list_events(backend, state, from, to)?;
+            Ok(Reply::html(format_events(&events, tz), Action::Listed))
+        }
+        Command::Week => {
+            let events = list_events(backend, state, now, now + Duration::days(7))?;
+            Ok(Reply::html(format_events(&events, tz), Action::Listed))
+        }
+        Command::Next => {
+            let events = list_events(backend, state, now, now + Duration::days(NEXT_DAYS))?;
+            if events.is_empty() {
+                return Ok(reply(&format!("Nothing in the next {} days.", NEXT_DAYS)));
+            }
+            let text = format!("Next: {}", format_events(&events[..1], tz));
+            Ok(Reply::html(text, Action::Listed))
+        }
+        Command::Free => free(backend, state, chat_id, user, args, tz),
+        Command::Invite => invite(backend, state, args, tz),
+        Command::Uninvite => uninvite(backend, state, args, tz),
+        Command::Rsvp => {
+            let event = match find_event(backend, state, args, tz)? {
+                Ok(event) => event,
+                Err(text) => return Ok(reply(&text)),
+            };
+            Ok(Reply::new(attendees::responses(&event, tz), Action::Listed))
+        }
+        Command::Cancel => {
+            let theirs = state
+                .previews
+                .get(&chat_id)
+                .is_some_and(|preview| preview.user_id == user.id);
+            let Some(preview) = theirs.then(|| state.previews.remove(&chat_id)).flatten() else {
+                return Ok(reply("Nothing to cancel."));
+            };
+            save(state);
+            if let Some(message_id) = preview.message_id {
+                let _ = backend.edit_message(chat_id, message_id, "Cancelled, nothing was booked.");
+            }
+            Ok(reply("Cancelled, nothing was booked."))
+        }
+        Command::Undo => undo_last(backend, state, user.id),
+        Command::Settings => settings(state, args),
+        Command::Timezone => timezone(state, user, owner, args),
+        Command::Language => language(state, user, args),
+        Command::Link | Command::Unlink if !groups::is_group(chat) => {
+            Ok(reply("Linking is for group chats, add me to one first."))
+        }
+        Command::Link => link(state, chat, user, owner, args),
+        Command::Unlink => {
+            let removed = state.groups.get_mut(&chat_id).and_then(|group| {
+                let pending = group.pending.remove(&user.id);
+                group.members.remove(&user.id).or(pending)
+            });
+            save(state);
+            Ok(reply(match removed {
+                Some(_) => "Your calendar is no longer part of this group's /free.",
+                None => "Your calendar wasn't linked here.",
+            }))
+        }
+        Command::Disconnect => Ok(reply(&match disconnect(state) {
+            Ok(()) => "Google account disconnected.".to_string(),
+            Err(e) => format!(
+                "Google account disconnected here, but revoking failed: {}",
+                e
+            ),
+        })),
+        Command::Help => Ok(reply(&help(owner))),
+    }
+}
+
+fn reply(text: &str) -> Reply {
+    Reply::new(text, Action::None)
+}
+
+fn midnight(day: NaiveDate, tz: Tz) -> Option<DateTime<Utc>> {
+    local_time(day, 0, tz)
+}
+
+fn local_time(day: NaiveDate, hour: u32, tz: Tz) -> Option<DateTime<Utc>> {
+    tz.from_local_datetime(&day.and_hms_opt(hour, 0, 0)?)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+fn list_events(
+    backend: &impl Backend,
+    state: &mut State,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> anyhow::Result<Vec<Event>> {
+    let from = from.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let to = to.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let events = with_google_token(state, |token| {
+        get_events_from_primary_calendar(backend, token, &from, &to)
+    })?;
+    Ok(events.items)
+}
+
+/// A line per event under a line per day, times in `tz`.
+/// Telegram HTML, by day, with meeting links.
+fn format_events(events: &[Event], tz: Tz) -> String {
+    if events.is_empty() {
+        return "No events.".to_string();
+    }
+    let mut lines = vec![];
+    let mut last_day = None;
+    for event in events {
+        let start = event.start.as_ref();
+        let end = event.end.as_ref();
+        let local = |time: Option<&String>| {
+            DateTime::parse_from_rfc3339(time?)
+                .ok()
+                .map(|time| time.with_timezone(&tz))
+        };
+        let mut title = format::escape_html(event.summary.as_deref().unwrap_or("(no title)"));
+        if let Some(meet) = &event.hangout_link {
+            title = format!("{} {}", title, format::link("Meet", meet));
+        } else if let Some(location) = event
+            .location
+            .as_deref()
+            .filter(|location| location.starts_with("https://"))
+        {
+            // where zoom, jitsi and custom links go
+            title = format!("{} {}", title, format::link("Join", location));
+        }
+        let (day, line) = match (
+            local(start.and_then(|s| s.date_time.as_ref())),
+            local(end.and_then(|e| e.date_time.as_ref())),
+        ) {
+            (Some(start), Some(end)) => (
+                start.date_naive(),
+                format!(
+                    "{}-{} {}",
+                    start.format("%H:%M"),
+                    end.format("%H:%M"),
+                    title
+                ),
+            ),
+            _ => {
+                let Some(day) = start
+                    .and_then(|s| s.date.as_deref())
+                    .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+                else {
+                    continue;
+                };
+                (day, format!("all day {}", title))
+            }
+        };
+        if last_day != Some(day) {
+            lines.push(format::bold(&day.format("%a %d %b").to_string()));
+            last_day = Some(day);
+        }
+        lines.push(format!("  {}", line));
+    }
+    lines.join("\n")
+}
+
+fn free(
+    backend: &impl Backend,
+    state: &mut State,
+    chat_id: i64,
+    user: &User,
+    args: &str,
+    owner_tz: Tz,
+) -> anyhow::Result<Reply> {
+    let now = Utc::now();
+    let today = now.with_timezone(&owner_tz).date_naive();
+    let (from, to) = match args.to_lowercase().as_str() {
+        "today" => (now, midnight(today + Duration::days(1), owner_tz)),
+        "tomorrow" => (
+            midnight(today + Duration::days(1), owner_tz).unwrap_or(now),
+            midnight(today + Duration::days(2), owner_tz),
+        ),
+        "" | "week" => (now, Some(now + Duration::days(7))),
+        _ => return Ok(reply("Use /free today, /free tomorrow or /free week.")),
+    };
+    let to = to.unwrap_or(now + Duration::days(1));
+
+    // in a group, the linked members have to be free too
+    let group = state.groups.get(&chat_id).cloned().unwrap_or_default();
+    let mut calendars = vec!["primary".to_string()];
+    calendars.extend(group.calendars());
+    let free_busy = with_google_token(state, |token| {
+        get_busy(backend, token, &from, &to, &calendars)
+    })?;
+    let slots = free_slots(&free_busy.busy, from, to, owner_tz);
+    let unknown = match free_busy.unknown.is_empty() {
+        true => String::new(),
+        false => format!(
+            "\nCouldn't see the calendar of {}.",
+            free_busy.unknown.join(", ")
+        ),
+    };
+    if slots.is_empty() {
+        return Ok(reply(&format!("No free time then.{}", unknown)));
+    }
+
+    // guests see their own time when we know it
+    let tz = state
+        .guest_timezones
+        .get(&user.id)
+        .filter(|_| state.user_id != Some(user.id))
+        .and_then(|tz| tz.parse().ok())
+        .unwrap_or(owner_tz);
+    let mut lines = vec![match group.members.len() {
+        0 => format!("Free ({}):", tz),
+        n => format!("Free for you and {} linked member(s) ({}):", n, tz),
+    }];
+    for (start, end) in slots.iter().take(MAX_FREE_SLOTS) {
+        let start = start.with_timezone(&tz);
+        let end = end.with_timezone(&tz);
+        lines.push(format!(
+            "{} {}-{}",
+            start.format("%a %d %b"),
+            start.format("%H:%M"),
+            end.format("%H:%M")
+        ));
+    }
+    if slots.len() > MAX_FREE_SLOTS {
+        lines.push(format!("and {} more", slots.len() - MAX_FREE_SLOTS));
+    }
+    lines.push(format!("Tap a time to book it.{}", unknown));
+
+    let buttons = slots
+        .iter()
+        .take(MAX_SLOT_BUTTONS)
+        .map(|(start, end)| {
+            let end = (*start + Duration::minutes(SLOT_MEETING_MINUTES)).min(*end);
+            let label = start.with_timezone(&tz).format("%a %H:%M").to_string();
+            let slot = Callback::Slot {
+                start: start.timestamp(),
+                end: end.timestamp(),
+            };
+            vec![slot.button(&label)]
+        })
+        .collect();
+    Ok(Reply::new(lines.join("\n"), Action::Listed).with_keyboard(callbacks::keyboard(buttons)))
+}
+
+/// The event a command's words point to, or what to tell the user when they don't.
+fn find_event(
+    backend: &impl Backend,
+    state: &mut State,
+    query: &str,
+    tz: Tz,
+) -> anyhow::Result<Result<Event, String>> {
+    let (from, to, words) = attendees::search(query, Utc::now().with_timezone(&tz));
+    let events = list_events(backend, state, from, to)?;
+    Ok(attendees::pick(&events, &words, tz).cloned())
+}
+
+/// Who google emails about an attendee change: no one when the command ends with
+/// "quietly", otherwise the setting for that kind of change.
+fn quietly(args: &str, setting: SendUpdates) -> (&str, SendUpdates) {
+    let trimmed = args.trim_end();
+    for word in ["quietly", "silently"] {
+        if let Some(rest) = trimmed.strip_suffix(word) {
+            return (rest.trim_end(), SendUpdates::None);
+        }
+    }
+    (args, setting)
+}
+
+/// Replaces the attendees of `event`, for /invite and /uninvite.
+fn set_attendees(
+    backend: &impl Backend,
+    state: &mut State,
+    event: &Event,
+    list: Vec<EventAttendees>,
+    send_updates: SendUpdates,
+) -> anyhow::Result<Option<Inverse>> {
+    let Some(id) = event.id.clone() else {
+        return Err(anyhow::anyhow!("the event has no id"));
+    };
+    let patch = Event {
+        attendees: Some(list),
+        ..Default::default()
+    };
+    with_google_token(state, |token| {
+        update_event(backend, token, &id, &patch, send_updates)
+    })?;
+    Ok(Some(Inverse::edited(event, send_updates)))
+}
+
+fn invite(backend: &impl Backend, state: &mut State, args: &str, tz: Tz) -> anyhow::Result<Reply> {
+    let (args, send_updates) = quietly(args, state.attendees.notify.invite);
+    let usage =
+        "Use /invite ana@example.com to friday standup, add \"quietly\" to not email anyone.";
+    let Some((people, query)) = args.split_once(" to ") else {
+        return Ok(reply(usage));
+    };
+    let emails: Vec<String> = people
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|word| word.contains('@'))
+        .map(str::to_string)
+        .collect();
+    if emails.is_empty() {
+        return Ok(reply(usage));
+    }
+    let event = match find_event(backend, state, query, tz)? {
+        Ok(event) => event,
+        Err(text) => return Ok(reply(&text)),
+    };
+    let (list, added) = attendees::with_invited(&event, &emails);
+    if added.is_empty() {
+        return Ok(reply(&format!(
+            "They're already invited to {}.",
+            attendees::title(&event, tz)
+        )));
+    }
+    let undo = set_attendees(backend, state, &event, list, send_updates)?;
+    let text = format!(
+        "Invited {} to {}.",
+        added.join(", "),
+        attendees::title(&event, tz)
+    );
+    Ok(Reply::new(text, Action::None).undoable(undo))
+}
+
+fn uninvite(
+    backend: &impl Backend,
+    state: &mut State,
+    args: &str,
+    tz: Tz,
+) -> anyhow::Result<Reply> {
+    let (args, send_updates) = quietly(args, state.attendees.notify.remove);
+    let Some((people, query)) = args.split_once(" from ") else {
+        return Ok(reply(
+            "Use /uninvite ana from friday standup, add \"quietly\" to not email anyone.",
+        ));
+    };
+    let names: Vec<String> = people
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|word| !word.is_empty() && *word != "and")
+        .map(str::to_string)
+        .collect();
+    let event = match find_event(backend, state, query, tz)? {
+        Ok(event) => event,
+        Err(text) => return Ok(reply(&text)),
+    };
+    let (list, removed) = attendees::without(&event, &names);
+    if removed.is_empty() {
+        return Ok(reply(&format!(
+            "Nobody like that is invited to {}.",
+            attendees::title(&event, tz)
+        )));
+    }
+    let undo = set_attendees(backend, state, &event, list, send_updates)?;
+    let text = format!(
+        "Took {} off {}.",
+        removed.join(", "),
+        attendees::title(&event, tz)
+    );
+    Ok(Reply::new(text, Action::None).undoable(undo))
+}
+
+/// Gaps between `busy` periods in working hours on weekdays, `tz` being the owner's.
+fn free_slots(
+    busy: &[(DateTime<Utc>, DateTime<Utc>)],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    tz: Tz,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let min = Duration::minutes(MIN_FREE_MINUTES);
+    let mut slots = vec![];
+    let mut day = from.with_timezone(&tz).date_naive();
+    let last = to.with_timezone(&tz).date_naive();
+    while day <= last {
+        let open = local_time(day, WORK_START_HOUR, tz);
+        let close = local_time(day, WORK_END_HOUR, tz);
+        if let (true, Some(open), Some(close)) =
+            (day.weekday().number_from_monday() <= 5, open, close)
+        {
+            let mut cursor = open.max(from);
+            let close = close.min(to);
+            for (start, end) in busy {
+                if *end <= cursor || *start >= close {
+                    continue;
+                }
+                if *start - cursor >= min {
+                    slots.push((cursor, *start));
+                }
+                cursor = cursor.max(*end);
+            }
+            if close - cursor >= min {
+                slots.push((cursor, close));
+            }
+        }
+        day += Duration::days(1);
+    }
+    slots
+}
+
+/// The hint passed to transcription. Whisper detects the language by itself but
+/// guesses wrong on short clips.
+fn language(state: &mut State, user: &User, args: &str) -> anyhow::Result<Reply> {
+    let args = args.to_lowercase();
+    match args.as_str() {
+        "" => Ok(reply(&match state.stt_languages.get(&user.id) {
+            Some(language) => format!("Your voice messages are read as {}.", language),
+            None => "Your voice messages' language is detected, set one with e.g. /language de."
+                .to_string(),
+        })),
+        "auto" => {
+            state.stt_languages.remove(&user.id);
+            save(state);
+            Ok(reply("Your voice messages' language will be detected."))
+        }
+        code if code.len() == 2 && code.chars().all(|c| c.is_ascii_lowercase()) => {
+            state.stt_languages.insert(user.id, code.to_string());
+            save(state);
+            Ok(reply(&format!(
+                "Your voice messages will be read as {}.",
+                code
+            )))
+        }
+        _ => Ok(reply(
+            "Use a two letter language code like /language en, or /language auto.",
+        )),
+    }
+}
+
+/// `/free` reads linked calendars with the owner's token, so only the owner links
+/// one straight away. Anyone else's waits for the owner to press Approve.
+fn link(
+    state: &mut State,
+    chat: &Chat,
+    user: &User,
+    owner: bool,
+    args: &str,
+) -> anyhow::Result<Reply> {
+    let group = state.groups.entry(chat.id).or_default();
+    group.title = chat.title.clone();
+    if args.is_empty() {
+        return Ok(reply(&linked(group)));
+    }
+    if !args.contains('@') || args.contains(char::is_whitespace) {
+        return Ok(reply(
+            "That doesn't look like a calendar, use /link you@example.com",
+        ));
+    }
+    let member = Member {
+        name: display_name(user),
+        calendar: args.to_string(),
+    };
+    if !owner {
+        group.members.remove(&user.id);
+        group.pending.insert(user.id, member);
+        save(state);
+        let text = format!(
+            "The calendar owner has to approve linking {} first, their account is the one that reads it.",
+            args
+        );
+        let approve = Callback::Link(user.id).button("Approve");
+        return Ok(reply(&text).with_keyboard(callbacks::keyboard(vec![vec![approve]])));
+    }
+    group.pending.remove(&user.id);
+    group.members.insert(user.id, member);
+    let text = format!(
+        "Linked {}. The calendar owner's Google account has to be able to see its free/busy.",
+        args
+    );
+    save(state);
+    Ok(reply(&text))
+}
+
+fn linked(group: &Group) -> String {
+    if group.members.is_empty() && group.pending.is_empty() {
+        return "Nobody linked a calendar here yet, use /link you@example.com".to_string();
+    }
+    let mut members: Vec<_> = group
+        .members
+        .values()
+        .map(|member| format!("{}: {}", member.name, member.calendar))
+        .chain(group.pending.values().map(|member| {
+            format!(
+                "{}: {} (waiting for approval)",
+                member.name, member.calendar
+            )
+        }))
+        .collect();
+    members.sort();
+    format!("Linked calendars:\n{}", members.join("\n"))
+}
+
+fn settings(state: &mut State, args: &str) -> anyhow::Result<Reply> {
+    // links are case sensitive, everything else isn't
+    if let Some(url) = args.trim().strip_prefix("link") {
+        return meeting_link(state, url.trim());
+    }
+    let args: Vec<String> = args.split_whitespace().map(str::to_lowercase).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => {}
+        ["meeting", name] => {
+            let Some(solution) = Solution::parse(name) else {
+                return Ok(reply("Use /settings meeting meet|jitsi|zoom|custom."));
+            };
+            if solution == Solution::Custom && state.meetings.custom_url.is_none() {
+                return Ok(reply("Set the link first with /settings link <url>."));
+            }
+            state.meetings.default = solution;
+            save(state);
+        }
+        ["confirm", "on"] => {
+            state.confirm_mode = true;
+            save(state);
+        }
+        ["confirm", "off"] => {
+            state.confirm_mode = false;
+            state.previews.clear();
+            save(state);
+        }
+        ["voice", on @ ("on" | "off")] => {
+            state.tts.enabled = on == "on";
+            save(state);
+        }
+        ["heard", on @ ("on" | "off")] => {
+            state.stt.echo = on == "on";
+            save(state);
+        }
+        ["rsvp", on @ ("on" | "off")] => {
+            set_rsvp_alerts(state, on == "on");
+            save(state);
+        }
+        ["notify", action, who] => {
+            let (Some(send_updates), Some(setting)) = (
+                attendees::parse_send_updates(who),
+                state.attendees.notify.get_mut(action),
+            ) else {
+                return Ok(reply(
+                    "Use /settings notify book|invite|remove|cancel all|external|none.",
+                ));
+            };
+            *setting = send_updates;
+            save(state);
+        }
+        _ => {
+            return Ok(reply(
+                "Use /settings confirm|voice|heard|rsvp on|off, /settings meeting meet|jitsi|zoom|custom, /settings notify book|invite|remove|cancel all|external|none or /settings link <url>.",
+            ))
+        }
+    }
+
+    let scopes = if state.google_scopes.is_empty() {
+        "none".to_string()
+    } else {
+        state.google_scopes.join(", ")
+    };
+    Ok(reply(&format!(
+        "Timezone: {}\nConfirm before booking: {}\nVoice replies to voice messages: {}\nTranscription: {}, saying what was heard {}\nMeeting links: {}{}\nMessages when guests answer: {}\nUpdate emails: {}\nGoogle: {} (scopes: {})\n\nChange with /settings confirm|voice|heard|rsvp on|off, /settings meeting meet|jitsi|zoom|custom, /settings notify book|invite|remove|cancel all|external|none, /settings link <url> and /timezone <zone>.",
+        state.timezone.as_deref().unwrap_or("not set"),
+        if state.confirm_mode { "on" } else { "off" },
+        if state.tts.enabled { "on" } else { "off" },
+        match &state.stt.provider {
+            SttProvider::Openai => "openai".to_string(),
+            SttProvider::Whisper { url } => format!("whisper at {}", url),
+        },
+        if state.stt.echo { "on" } else { "off" },
+        state.meetings.default.label(),
+        match (state.meetings.default, &state.meetings.custom_url) {
+            (Solution::Jitsi, _) => format!(" at {}", state.meetings.jitsi_url),
+            (Solution::Custom, Some(url)) => format!(" ({})", url),
+            _ => String::new(),
+        },
+        if state.attendees.alerts { "on" } else { "off" },
+        state.attendees.notify.describe(),
+        if state.google_token.is_some() { "connected" } else { "not connected" },
+        scopes,
+    )))
+}
+
+/// Sets the link custom meetings use, or removes it when `url` is empty.
+fn meeting_link(state: &mut State, url: &str) -> anyhow::Result<Reply> {
+    if url.is_empty() {
+        if state.meetings.default == Solution::Custom {
+            state.meetings.default = Solution::Meet;
+        }
+        state.meetings.custom_url = None;
+        save(state);
+        return Ok(reply("Meeting link removed."));
+    }
+    if Url::parse(url).is_err() {
+        return Ok(reply(&format!("{} isn't a link.", url)));
+    }
+    state.meetings.custom_url = Some(url.to_string());
+    save(state);
+    Ok(reply(&format!(
+        "Meeting link set to {}. Use it for every meeting with /settings meeting custom, or ask for \"my room\" in a request.",
+        url
+    )))
+}
+
+fn timezone(state: &mut State, user: &User, owner: bool, args: &str) -> anyhow::Result<Reply> {
+    if args.is_empty() {
+        let current = if owner {
+            state.timezone.clone()
+        } else {
+            state.guest_timezones.get(&user.id).cloned()
+        };
+        return Ok(reply(&match current {
+            Some(tz) => format!("Your timezone is {}.", tz),
+            None => {
+                "No timezone set, use e.g. /timezone Europe/Berlin or /timezone Tokyo.".to_string()
+            }
+        }));
+    }
+
+    let Some(tz) = args
+        .parse::<Tz>()
+        .ok()
+        .or_else(|| timezones::by_name(args))
+        .or_else(|| timezones::find_in_text(args).map(|mention| mention.tz()))
+    else {
+        return Ok(reply(&format!(
+            "I don't know the timezone {}, try a name like Europe/Berlin or a city.",
+            args
+        )));
+    };
+    if owner {
+        state.timezone = Some(tz.name().to_string());
+    } else {
+        state.guest_timezones.insert(user.id, tz.name().to_string());
+    }
+    save(state);
+    Ok(reply(&format!("Timezone set to {}.", tz.name())))
+}
+
+fn help(owner: bool) -> String {
+    let mut lines = vec!["Commands:".to_string()];
+    for info in COMMANDS.iter().filter(|info| owner || !info.owner_only) {
+        lines.push(format!("/{} - {}", info.name, info.description));
+    }
+    lines.push(String::new());
+    lines.push(if owner {
+        "Anything else is read as a request, like \"what's on Friday?\" or \"lunch with Ana tomorrow at 1\".".to_string()
+    } else {
+        "Anything else is read as a request, like \"can we meet Thursday at 3pm?\".".to_string()
+    });
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use serde_json::json;
+
+    const OWNER: u64 = 1;
+    const GROUP: i64 = -100;
+
+    fn user(id: u64) -> User {
+        serde_json::from_value(json!({ "id": id, "is_bot": false, "first_name": "Ana" })).unwrap()
+    }
+
+    fn group_chat() -> Chat {
+        serde_json::from_value(json!({ "id": GROUP, "type": "group", "title": "Team" })).unwrap()
+    }
+
+    #[test]
+    fn members_links_wait_for_the_owner() {
+        let backend = MockBackend::default();
+        let mut state = State {
+            user_id: Some(OWNER),
+            ..Default::default()
+        };
+        let chat = group_chat();
+        let link = |state: &mut State, id: u64, calendar: &str| {
+            run(&backend, state, &chat, &user(id), Command::Link, calendar).unwrap()
+        };
+
+        let reply = link(&mut state, 7, "boss@example.com");
+        assert!(reply.text.contains("has to approve"), "{}", reply.text);
+        assert!(reply.keyboard.is_some());
+        assert!(state.groups[&GROUP].calendars().is_empty());
+
+        let reply = link(&mut state, OWNER, "owner@example.com");
+        assert!(reply.text.starts_with("Linked owner@example.com."));
+        assert_eq!(state.groups[&GROUP].calendars(), vec!["owner@example.com"]);
+
+        run(&backend, &mut state, &chat, &user(7), Command::Unlink, "").unwrap();
+        assert!(state.groups[&GROUP].pending.is_empty());
+    }
+}
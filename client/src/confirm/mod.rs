@@ -0,0 +1,103 @@
+//! Confirm mode: instead of booking right away, the bot shows what it would book
+//! with Confirm/Edit/Cancel buttons and only writes once confirmed. Each chat has
+//! at most one pending preview, a newer request replaces it.
+use crate::callbacks::{self, Callback};
+use crate::gcal::helpers::Proposal;
+use chrono::Utc;
+use frankenstein::InlineKeyboardMarkup;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// unanswered previews are dropped after this
+pub const PREVIEW_TTL_SECS: u64 = 600;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Preview {
+    // tells this preview's buttons from older ones in the same chat
+    pub id: u32,
+    pub user_id: u64,
+    pub guest: bool,
+    pub proposal: Proposal,
+    // the request, amended when the user edits it
+    pub text: String,
+    pub created_at: u64,
+    // the user's message with the request
+    #[serde(default)]
+    pub request_message_id: Option<i32>,
+    // the bot's message with the buttons
+    pub message_id: Option<i32>,
+    // Edit was pressed, the user's next message says what to change
+    #[serde(default)]
+    pub editing: bool,
+}
+
+impl Preview {
+    pub fn new(
+        user_id: u64,
+        guest: bool,
+        proposal: Proposal,
+        text: &str,
+        request_message_id: i32,
+    ) -> Self {
+        Preview {
+            id: rand::random(),
+            user_id,
+            guest,
+            proposal,
+            text: text.to_string(),
+            created_at: now_secs(),
+            request_message_id: Some(request_message_id),
+            message_id: None,
+            editing: false,
+        }
+    }
+
+    fn expired(&self, now: u64) -> bool {
+        now.saturating_sub(self.created_at) > PREVIEW_TTL_SECS
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Choice {
+    Confirm,
+    Edit,
+    Cancel,
+}
+
+impl Choice {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Choice::Confirm => "confirm",
+            Choice::Edit => "edit",
+            Choice::Cancel => "cancel",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "confirm" => Some(Choice::Confirm),
+            "edit" => Some(Choice::Edit),
+            "cancel" => Some(Choice::Cancel),
+            _ => None,
+        }
+    }
+}
+
+fn now_secs() -> u64 {
+    Utc::now().timestamp() as u64
+}
+
+pub fn keyboard(preview_id: u32) -> InlineKeyboardMarkup {
+    let button = |label: &str, choice: Choice| Callback::Preview(choice, preview_id).button(label);
+    callbacks::keyboard(vec![vec![
+        button("Confirm", Choice::Confirm),
+        button("Edit", Choice::Edit),
+        button("Cancel", Choice::Cancel),
+    ]])
+}
+
+/// Drops previews nobody answered in time.
+pub fn sweep(previews: &mut HashMap<i64, Preview>) {
+    let now = now_secs();
+    previews.retain(|_, preview| !preview.expired(now));
+}
@@ -0,0 +1,122 @@
+//! What a message means beyond its own text: who a forwarded message came from,
+//! and what a reply answers. Bot messages that confirmed a booking are tracked,
+//! so replying "cancel this" to one can find the event.
+use crate::gcal::Event;
+use frankenstein::{Message as TgMessage, MessageOrigin};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+// per chat, replies to older confirmations read like any other reply
+const MAX_BOOKINGS: usize = 50;
+// of a replied-to message, quoted to the LLM
+const MAX_QUOTE_LEN: usize = 500;
+
+const CANCEL_WORDS: &[&str] = &["cancel", "delete", "call off", "call it off"];
+
+/// A bot message that confirmed a booking.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Booking {
+    pub message_id: i32,
+    pub event_id: String,
+    pub summary: Option<String>,
+    // who asked for it
+    pub user_id: u64,
+}
+
+pub fn track(
+    bookings: &mut HashMap<i64, VecDeque<Booking>>,
+    chat_id: i64,
+    message_id: i32,
+    user_id: u64,
+    event: &Event,
+) {
+    let Some(event_id) = event.id.clone() else {
+        return;
+    };
+    let chat = bookings.entry(chat_id).or_default();
+    chat.push_back(Booking {
+        message_id,
+        event_id,
+        summary: event.summary.clone(),
+        user_id,
+    });
+    if chat.len() > MAX_BOOKINGS {
+        chat.pop_front();
+    }
+}
+
+/// The booking a message replies to, if it replies to a confirmation.
+pub fn replied_booking<'a>(
+    bookings: &'a HashMap<i64, VecDeque<Booking>>,
+    msg: &TgMessage,
+) -> Option<&'a Booking> {
+    let replied = msg.reply_to_message.as_ref()?;
+    bookings
+        .get(&msg.chat.id)?
+        .iter()
+        .find(|booking| booking.message_id == replied.message_id)
+}
+
+pub fn forget(bookings: &mut HashMap<i64, VecDeque<Booking>>, chat_id: i64, event_id: &str) {
+    if let Some(chat) = bookings.get_mut(&chat_id) {
+        chat.retain(|booking| booking.event_id != event_id);
+    }
+}
+
+/// Whether a reply to a confirmation asks to cancel it. Short replies only, so
+/// "remove Ana and add Ben" doesn't delete the meeting.
+pub fn is_cancel(text: &str) -> bool {
+    let text = text.trim().to_lowercase();
+    text.split_whitespace().count() <= 4 && CANCEL_WORDS.iter().any(|word| text.contains(word))
+}
+
+/// Lines telling the LLM where the message came from and what it answers.
+pub fn describe(msg: &TgMessage, booking: Option<&Booking>) -> Option<String> {
+    let mut lines = vec![];
+    if let Some(origin) = &msg.forward_origin {
+        let from = match origin.as_ref() {
+            MessageOrigin::User(origin) => {
+                let user = &origin.sender_user;
+                let name = [Some(user.first_name.as_str()), user.last_name.as_deref()]
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                match &user.username {
+                    Some(username) => format!("{} (@{})", name, username),
+                    None => name,
+                }
+            }
+            MessageOrigin::HiddenUser(origin) => origin.sender_user_name.clone(),
+            MessageOrigin::Chat(origin) => origin.sender_chat.title.clone().unwrap_or_default(),
+            MessageOrigin::Channel(origin) => origin.chat.title.clone().unwrap_or_default(),
+        };
+        lines.push(format!(
+            "(Forwarded from {}. The request is theirs, so they are likely an attendee; ask for their email if it's needed.)",
+            from
+        ));
+    }
+    if let Some(replied) = &msg.reply_to_message {
+        let quote = replied
+            .text
+            .as_deref()
+            .or(replied.caption.as_deref())
+            .unwrap_or_default();
+        let quote: String = quote.chars().take(MAX_QUOTE_LEN).collect();
+        let author = match &replied.from {
+            Some(from) if from.is_bot => "your earlier message".to_string(),
+            Some(from) => format!("{}'s message", from.first_name),
+            None => "a message".to_string(),
+        };
+        if !quote.is_empty() {
+            lines.push(format!("(In reply to {}: \"{}\")", author, quote));
+        }
+    }
+    if let Some(booking) = booking {
+        lines.push(format!(
+            "(That message confirmed the event \"{}\".)",
+            booking.summary.as_deref().unwrap_or("untitled event")
+        ));
+    }
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
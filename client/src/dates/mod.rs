@@ -0,0 +1,871 @@
+//! Deterministic resolution of relative date expressions ("next tuesday",
+//! "in two weeks", "tomorrow afternoon"), done before the LLM sees a message.
+//!
+//! Conventions:
+//! - weeks start on monday.
+//! - a bare weekday ("friday", "on friday", "this friday") is the next one, today included.
+//! - "next <weekday>" is that day in next week.
+//! - "in N weeks"/"in N months" cover the whole week/month they land in.
+use chrono::{
+    DateTime, Datelike, Days, Duration, Months, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc,
+    Weekday,
+};
+use chrono_tz::Tz;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedRange {
+    pub phrase: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    /// whole local days, `to` exclusive.
+    Days { from: NaiveDate, to: NaiveDate },
+    /// a local span starting at a fixed point.
+    Moment { at: NaiveDateTime, minutes: i64 },
+    /// a clock time without a day, e.g. "3pm".
+    Time(NaiveTime),
+    /// a part of the day without a day, e.g. "afternoon", as (start hour, hours).
+    Part(u32, i64),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Match {
+    at: usize,
+    len: usize,
+    value: Value,
+}
+
+/// Parses from the start of the words, returning how many words were consumed.
+type Parser = fn(&[&str], NaiveDate, NaiveDateTime) -> Option<(usize, Value)>;
+
+const FILLERS: &[&str] = &["at", "on", "in", "the", "around", "by", "@"];
+// offsets further out than this aren't dates anyone means, and would bring the
+// arithmetic close to chrono's limits
+const MAX_OFFSET_YEARS: i32 = 100;
+
+pub fn resolve(text: &str, now: DateTime<Tz>) -> Vec<ResolvedRange> {
+    let lower = text.to_lowercase();
+    let words: Vec<&str> = lower
+        .split(|c: char| c.is_whitespace() || matches!(c, ',' | '.' | '?' | '!' | ';' | '(' | ')'))
+        .filter(|w| !w.is_empty())
+        .collect();
+
+    let today = now.date_naive();
+    let mut matches = vec![];
+    let mut i = 0;
+    while i < words.len() {
+        match parse_at(&words, i, today, now.naive_local()) {
+            Some(m) => {
+                i += m.len;
+                matches.push(m);
+            }
+            None => i += 1,
+        }
+    }
+
+    let tz = now.timezone();
+    let mut used = vec![false; matches.len()];
+    let mut resolved = vec![];
+
+    // single days first, so they can claim an adjacent time or part of day on either
+    // side: "tomorrow at 3pm", "3:30pm on friday", "afternoon on friday"
+    for idx in 0..matches.len() {
+        let m = matches[idx];
+        let Value::Days { from, to } = m.value else {
+            continue;
+        };
+        if to - from != Duration::days(1) {
+            continue;
+        }
+        let neighbour = [idx + 1, idx.wrapping_sub(1)]
+            .into_iter()
+            .filter(|&n| n < matches.len() && !used[n])
+            .find(|&n| {
+                matches!(matches[n].value, Value::Time(_) | Value::Part(..))
+                    && adjacent(&words, &m, &matches[n])
+            });
+        used[idx] = true;
+        let (value, first, last) = match neighbour {
+            Some(n) => {
+                used[n] = true;
+                let joined = on_day(from, matches[n].value);
+                (
+                    joined,
+                    m.at.min(matches[n].at),
+                    end_of(&m).max(end_of(&matches[n])),
+                )
+            }
+            None => (m.value, m.at, end_of(&m)),
+        };
+        resolved.push((first, to_range(tz, &words[first..last].join(" "), value)));
+    }
+
+    // everything else stands alone, times without a day are today
+    for (idx, m) in matches.iter().enumerate() {
+        if used[idx] {
+            continue;
+        }
+        let value = on_day(today, m.value);
+        resolved.push((m.at, to_range(tz, &words[m.at..end_of(m)].join(" "), value)));
+    }
+
+    resolved.sort_by_key(|(at, _)| *at);
+    let mut resolved: Vec<ResolvedRange> = resolved.into_iter().map(|(_, r)| r).collect();
+    resolved.dedup_by(|a, b| a.start == b.start && a.end == b.end);
+    resolved
+}
+
+/// Lines for the LLM prompt, empty when nothing was resolved.
+pub fn prompt_hint(ranges: &[ResolvedRange], tz: Tz) -> String {
+    if ranges.is_empty() {
+        return String::new();
+    }
+    let lines = ranges
+        .iter()
+        .map(|r| {
+            format!(
+                "- \"{}\" = {} to {} (local: {})",
+                r.phrase,
+                r.start.format("%Y-%m-%dT%H:%M:%SZ"),
+                r.end.format("%Y-%m-%dT%H:%M:%SZ"),
+                describe(r, tz)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
+        "The following date expressions in the user input were resolved exactly, use these ranges instead of computing dates yourself:\n{}\n",
+        lines
+    )
+}
+
+/// Whether a start time picked by the LLM lies in one of the resolved ranges.
+/// Trivially true when nothing was resolved.
+pub fn start_matches(start: &str, ranges: &[ResolvedRange]) -> bool {
+    let Some(start) = parse_utc(start) else {
+        return false;
+    };
+    ranges.is_empty() || ranges.iter().any(|r| r.start <= start && start < r.end)
+}
+
+/// Whether a range picked by the LLM overlaps one of the resolved ranges.
+/// Trivially true when nothing was resolved.
+pub fn range_matches(start: &str, end: &str, ranges: &[ResolvedRange]) -> bool {
+    let (Some(start), Some(end)) = (parse_utc(start), parse_utc(end)) else {
+        return false;
+    };
+    ranges.is_empty() || ranges.iter().any(|r| r.start < end && start < r.end)
+}
+
+/// Reply used instead of acting when the LLM's times disagree with the resolved ranges.
+pub fn mismatch_reply(ranges: &[ResolvedRange], tz: Tz) -> String {
+    let understood = ranges
+        .iter()
+        .map(|r| format!("\"{}\" as {}", r.phrase, describe(r, tz)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "I read {}, but came up with a different time when processing your request, so I didn't change anything. Could you give the date explicitly?",
+        understood
+    )
+}
+
+/// Human readable local description of a range, e.g. "Tue 04 Jun 12:00 - 17:00".
+pub fn describe(range: &ResolvedRange, tz: Tz) -> String {
+    let start = range.start.with_timezone(&tz);
+    let end = range.end.with_timezone(&tz);
+    let whole_days = start.time() == NaiveTime::MIN && end.time() == NaiveTime::MIN;
+    if whole_days {
+        let last = end - Duration::days(1);
+        if last.date_naive() == start.date_naive() {
+            start.format("%a %d %b").to_string()
+        } else {
+            format!("{} - {}", start.format("%a %d %b"), last.format("%a %d %b"))
+        }
+    } else if start.date_naive() == end.date_naive() {
+        format!(
+            "{} - {}",
+            start.format("%a %d %b %H:%M"),
+            end.format("%H:%M")
+        )
+    } else {
+        format!(
+            "{} - {}",
+            start.format("%a %d %b %H:%M"),
+            end.format("%a %d %b %H:%M")
+        )
+    }
+}
+
+fn parse_utc(time: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time.trim())
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+fn end_of(m: &Match) -> usize {
+    m.at + m.len
+}
+
+fn adjacent(words: &[&str], a: &Match, b: &Match) -> bool {
+    let (first, second) = if a.at < b.at { (a, b) } else { (b, a) };
+    let gap = &words[end_of(first)..second.at];
+    gap.len() <= 1 && gap.iter().all(|w| FILLERS.contains(w))
+}
+
+fn on_day(day: NaiveDate, value: Value) -> Value {
+    match value {
+        Value::Time(time) => Value::Moment {
+            at: day.and_time(time),
+            minutes: 60,
+        },
+        Value::Part(hour, hours) => Value::Moment {
+            at: day.and_hms_opt(hour, 0, 0).unwrap(),
+            minutes: hours * 60,
+        },
+        other => other,
+    }
+}
+
+fn to_range(tz: Tz, phrase: &str, value: Value) -> ResolvedRange {
+    let (start, end) = match value {
+        Value::Days { from, to } => (
+            local_to_utc(tz, from.and_time(NaiveTime::MIN)),
+            local_to_utc(tz, to.and_time(NaiveTime::MIN)),
+        ),
+        // the length is real time, a moment in a DST gap still lasts as long
+        Value::Moment { at, minutes } => {
+            let start = local_to_utc(tz, at);
+            (start, start + Duration::minutes(minutes))
+        }
+        // always attached to a day by `resolve`.
+        Value::Time(_) | Value::Part(..) => unreachable!(),
+    };
+    ResolvedRange {
+        phrase: phrase.to_string(),
+        start,
+        end,
+    }
+}
+
+fn local_to_utc(tz: Tz, time: NaiveDateTime) -> DateTime<Utc> {
+    // in a DST gap the local time doesn't exist, take the first valid time after it.
+    tz.from_local_datetime(&time)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(time + Duration::hours(1)))
+                .earliest()
+        })
+        .map(|t| t.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&time))
+}
+
+fn parse_at(words: &[&str], i: usize, today: NaiveDate, now: NaiveDateTime) -> Option<Match> {
+    let parsers: [Parser; 7] = [
+        parse_named_day,
+        parse_offset,
+        parse_period,
+        parse_weekday,
+        parse_date,
+        parse_part,
+        parse_time,
+    ];
+    parsers.iter().find_map(|parse| {
+        parse(&words[i..], today, now).map(|(len, value)| Match { at: i, len, value })
+    })
+}
+
+fn single_day(day: NaiveDate) -> Value {
+    Value::Days {
+        from: day,
+        to: day + Duration::days(1),
+    }
+}
+
+fn parse_named_day(
+    words: &[&str],
+    today: NaiveDate,
+    _now: NaiveDateTime,
+) -> Option<(usize, Value)> {
+    match words {
+        ["day", "after", "tomorrow", ..] => Some((3, single_day(today + Duration::days(2)))),
+        ["day", "before", "yesterday", ..] => Some((3, single_day(today - Duration::days(2)))),
+        ["today", ..] => Some((1, single_day(today))),
+        ["tonight", ..] => Some((1, on_day(today, Value::Part(18, 6)))),
+        ["tomorrow" | "tmr" | "tmrw", ..] => Some((1, single_day(today + Duration::days(1)))),
+        ["yesterday", ..] => Some((1, single_day(today - Duration::days(1)))),
+        ["this", part, ..] => {
+            let Value::Part(hour, hours) = part_of_day(part)? else {
+                return None;
+            };
+            Some((2, on_day(today, Value::Part(hour, hours))))
+        }
+        _ => None,
+    }
+}
+
+fn number(words: &[&str]) -> Option<(usize, u32)> {
+    let first = *words.first()?;
+    if let Ok(n) = first.parse::<u32>() {
+        return Some((1, n));
+    }
+    if let ["a" | "an", "couple", "of", ..] | ["couple", "of", ..] = words {
+        return Some((if first == "couple" { 2 } else { 3 }, 2));
+    }
+    let n = match first {
+        "a" | "an" | "one" => 1,
+        "two" => 2,
+        "three" => 3,
+        "four" => 4,
+        "five" => 5,
+        "six" => 6,
+        "seven" => 7,
+        "eight" => 8,
+        "nine" => 9,
+        "ten" => 10,
+        "eleven" => 11,
+        "twelve" => 12,
+        _ => return None,
+    };
+    Some((1, n))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Unit {
+    Minute,
+    Hour,
+    Day,
+    Week,
+    Month,
+}
+
+fn unit(word: &str) -> Option<Unit> {
+    match word {
+        "min" | "mins" | "minute" | "minutes" => Some(Unit::Minute),
+        "h" | "hr" | "hrs" | "hour" | "hours" => Some(Unit::Hour),
+        "day" | "days" => Some(Unit::Day),
+        "week" | "weeks" => Some(Unit::Week),
+        "month" | "months" => Some(Unit::Month),
+        _ => None,
+    }
+}
+
+fn week_of(day: NaiveDate) -> Value {
+    let monday = day - Duration::days(day.weekday().num_days_from_monday() as i64);
+    Value::Days {
+        from: monday,
+        to: monday + Duration::days(7),
+    }
+}
+
+fn month_of(day: NaiveDate) -> Value {
+    let first = day.with_day(1).unwrap();
+    Value::Days {
+        from: first,
+        to: first + Months::new(1),
+    }
+}
+
+/// "in 2 weeks", "3 days from now", "a week from today", "the next 3 days", "in an hour"
+fn parse_offset(words: &[&str], today: NaiveDate, now: NaiveDateTime) -> Option<(usize, Value)> {
+    // the next/coming N days
+    let skip = usize::from(words.first() == Some(&"the"));
+    if let Some(["next" | "coming", rest @ ..]) = words.get(skip..) {
+        if let Some((n_len, n)) = number(rest) {
+            if rest.get(n_len).and_then(|w| unit(w)) == Some(Unit::Day) {
+                let to = today.checked_add_days(Days::new(n as u64 + 1))?;
+                return Some((
+                    skip + 1 + n_len + 1,
+                    Value::Days {
+                        from: today,
+                        to: within_reach(today, to)?,
+                    },
+                ));
+            }
+        }
+    }
+
+    let (prefix, rest) = match words {
+        ["in", rest @ ..] => (1, rest),
+        rest => (0, rest),
+    };
+    let (n_len, n) = number(rest)?;
+    let unit = unit(rest.get(n_len)?)?;
+    let mut len = prefix + n_len + 1;
+
+    // without "in", only "N units from now/today" counts as an offset
+    let from_now = matches!(
+        rest.get(n_len + 1..n_len + 3),
+        Some(["from", "now" | "today"])
+    );
+    if from_now {
+        len += 2;
+    } else if prefix == 0 {
+        return None;
+    }
+
+    // the number is the user's, anything that overflows isn't a date
+    let moment = |minutes: i64| {
+        let at = now.checked_add_signed(Duration::try_minutes(minutes)?)?;
+        within_reach(today, at.date())?;
+        Some(at)
+    };
+    let day = |days: u64| within_reach(today, today.checked_add_days(Days::new(days))?);
+    let value = match unit {
+        Unit::Minute => Value::Moment {
+            at: moment(n as i64)?,
+            minutes: 30,
+        },
+        Unit::Hour => Value::Moment {
+            at: moment(n as i64 * 60)?,
+            minutes: 60,
+        },
+        Unit::Day => single_day(day(n as u64)?),
+        Unit::Week if from_now => single_day(day(n as u64 * 7)?),
+        Unit::Week => week_of(day(n as u64 * 7)?),
+        Unit::Month => {
+            let later = within_reach(today, today.checked_add_months(Months::new(n))?)?;
+            if from_now {
+                single_day(later)
+            } else {
+                month_of(later)
+            }
+        }
+    };
+    Some((len, value))
+}
+
+/// `day`, unless it's more than MAX_OFFSET_YEARS after `today`.
+fn within_reach(today: NaiveDate, day: NaiveDate) -> Option<NaiveDate> {
+    (day.year() - today.year() <= MAX_OFFSET_YEARS).then_some(day)
+}
+
+/// this/next/last week, weekends, months, and "end of ..." variants.
+fn parse_period(words: &[&str], today: NaiveDate, _now: NaiveDateTime) -> Option<(usize, Value)> {
+    let weekday = today.weekday().num_days_from_monday() as i64;
+    let monday = today - Duration::days(weekday);
+    let first_of_month = today.with_day(1).unwrap();
+
+    let (len, value) = match words {
+        ["end", "of", "the" | "this", "week", ..] | ["end", "of", "week", ..] => {
+            let len = if words[2] == "week" { 3 } else { 4 };
+            let friday = monday + Duration::days(4);
+            let value = if today <= friday {
+                single_day(friday)
+            } else {
+                Value::Days {
+                    from: today,
+                    to: monday + Duration::days(7),
+                }
+            };
+            (len, value)
+        }
+        ["end", "of", "the" | "this", "month", ..] | ["end", "of", "month", ..] => {
+            let len = if words[2] == "month" { 3 } else { 4 };
+            let last = first_of_month + Months::new(1) - Duration::days(1);
+            (len, single_day(last))
+        }
+        ["end", "of", "next", "month", ..] => {
+            let last = first_of_month + Months::new(2) - Duration::days(1);
+            (4, single_day(last))
+        }
+        ["start" | "beginning", "of", "next", "month", ..] => {
+            (4, single_day(first_of_month + Months::new(1)))
+        }
+        ["start" | "beginning", "of", "next", "week", ..] => {
+            (4, single_day(monday + Duration::days(7)))
+        }
+        ["this", "week", ..] => (
+            2,
+            Value::Days {
+                from: today,
+                to: monday + Duration::days(7),
+            },
+        ),
+        ["next", "week", ..] => (2, week_of(monday + Duration::days(7))),
+        ["last", "week", ..] => (2, week_of(monday - Duration::days(7))),
+        ["this", "weekend", ..] | ["the", "weekend", ..] => {
+            let saturday = monday + Duration::days(5);
+            (
+                2,
+                Value::Days {
+                    from: saturday.max(today),
+                    to: monday + Duration::days(7),
+                },
+            )
+        }
+        ["next", "weekend", ..] => {
+            let saturday = monday + Duration::days(12);
+            (
+                2,
+                Value::Days {
+                    from: saturday,
+                    to: saturday + Duration::days(2),
+                },
+            )
+        }
+        ["this", "month", ..] => (
+            2,
+            Value::Days {
+                from: today,
+                to: first_of_month + Months::new(1),
+            },
+        ),
+        ["next", "month", ..] => (2, month_of(first_of_month + Months::new(1))),
+        ["last", "month", ..] => (2, month_of(first_of_month - Months::new(1))),
+        _ => return None,
+    };
+    Some((len, value))
+}
+
+fn weekday(word: &str, allow_short: bool) -> Option<Weekday> {
+    let day = match word {
+        "monday" | "mondays" => Weekday::Mon,
+        "tuesday" | "tuesdays" => Weekday::Tue,
+        "wednesday" | "wednesdays" => Weekday::Wed,
+        "thursday" | "thursdays" => Weekday::Thu,
+        "friday" | "fridays" => Weekday::Fri,
+        "saturday" | "saturdays" => Weekday::Sat,
+        "sunday" | "sundays" => Weekday::Sun,
+        _ if !allow_short => return None,
+        "mon" => Weekday::Mon,
+        "tue" | "tues" => Weekday::Tue,
+        "wed" => Weekday::Wed,
+        "thu" | "thur" | "thurs" => Weekday::Thu,
+        "fri" => Weekday::Fri,
+        "sat" => Weekday::Sat,
+        "sun" => Weekday::Sun,
+        _ => return None,
+    };
+    Some(day)
+}
+
+fn parse_weekday(words: &[&str], today: NaiveDate, _now: NaiveDateTime) -> Option<(usize, Value)> {
+    let current = today.weekday().num_days_from_monday() as i64;
+    let days_until = |day: Weekday| (day.num_days_from_monday() as i64 - current).rem_euclid(7);
+
+    match words {
+        ["next", word, ..] => {
+            let day = weekday(word, true)?;
+            let monday = today - Duration::days(current) + Duration::days(7);
+            Some((
+                2,
+                single_day(monday + Duration::days(day.num_days_from_monday() as i64)),
+            ))
+        }
+        ["last", word, ..] => {
+            let day = weekday(word, true)?;
+            let back = match (current - day.num_days_from_monday() as i64).rem_euclid(7) {
+                0 => 7,
+                n => n,
+            };
+            Some((2, single_day(today - Duration::days(back))))
+        }
+        ["this", "coming", word, ..] => {
+            let day = weekday(word, true)?;
+            Some((3, single_day(today + Duration::days(days_until(day)))))
+        }
+        ["this" | "on" | "coming", word, ..] => {
+            let day = weekday(word, true)?;
+            Some((2, single_day(today + Duration::days(days_until(day)))))
+        }
+        [word, ..] => {
+            let day = weekday(word, false)?;
+            Some((1, single_day(today + Duration::days(days_until(day)))))
+        }
+        _ => None,
+    }
+}
+
+fn month(word: &str) -> Option<u32> {
+    let month = match word {
+        "january" | "jan" => 1,
+        "february" | "feb" => 2,
+        "march" | "mar" => 3,
+        "april" | "apr" => 4,
+        "may" => 5,
+        "june" | "jun" => 6,
+        "july" | "jul" => 7,
+        "august" | "aug" => 8,
+        "september" | "sep" | "sept" => 9,
+        "october" | "oct" => 10,
+        "november" | "nov" => 11,
+        "december" | "dec" => 12,
+        _ => return None,
+    };
+    Some(month)
+}
+
+fn day_of_month(word: &str) -> Option<u32> {
+    let digits = word.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let suffix = &word[digits.len()..];
+    if !matches!(suffix, "" | "st" | "nd" | "rd" | "th") {
+        return None;
+    }
+    digits.parse().ok().filter(|d| (1..=31).contains(d))
+}
+
+/// "june 5", "june 5th 2025", "5 june", "5th of june", "2024-06-05"
+fn parse_date(words: &[&str], today: NaiveDate, _now: NaiveDateTime) -> Option<(usize, Value)> {
+    if let Ok(date) = NaiveDate::parse_from_str(words.first()?, "%Y-%m-%d") {
+        if (2000..=2100).contains(&date.year()) {
+            return Some((1, single_day(date)));
+        }
+    }
+
+    let (len, month, day) = match words {
+        [m, d, ..] if month(m).is_some() && day_of_month(d).is_some() => {
+            (2, month(m)?, day_of_month(d)?)
+        }
+        [d, "of", m, ..] if day_of_month(d).is_some() && month(m).is_some() => {
+            (3, month(m)?, day_of_month(d)?)
+        }
+        [d, m, ..] if day_of_month(d).is_some() && month(m).is_some() => {
+            (2, month(m)?, day_of_month(d)?)
+        }
+        _ => return None,
+    };
+
+    let explicit_year = words
+        .get(len)
+        .and_then(|w| w.parse::<i32>().ok())
+        .filter(|y| (2000..=2100).contains(y));
+    if let Some(year) = explicit_year {
+        return Some((
+            len + 1,
+            single_day(NaiveDate::from_ymd_opt(year, month, day)?),
+        ));
+    }
+
+    // dates without a year are the next occurrence
+    let this_year = NaiveDate::from_ymd_opt(today.year(), month, day)?;
+    let date = if this_year < today {
+        NaiveDate::from_ymd_opt(today.year() + 1, month, day)?
+    } else {
+        this_year
+    };
+    Some((len, single_day(date)))
+}
+
+fn part_of_day(word: &str) -> Option<Value> {
+    let (hour, hours) = match word {
+        "morning" => (6, 6),
+        "noon" | "midday" => (12, 1),
+        "lunch" | "lunchtime" => (12, 2),
+        "afternoon" => (12, 5),
+        "evening" => (17, 5),
+        "night" => (20, 4),
+        _ => return None,
+    };
+    Some(Value::Part(hour, hours))
+}
+
+fn parse_part(words: &[&str], _today: NaiveDate, _now: NaiveDateTime) -> Option<(usize, Value)> {
+    match words {
+        ["in" | "at", "the", word, ..] => part_of_day(word).map(|v| (3, v)),
+        [word, ..] => part_of_day(word).map(|v| (1, v)),
+        _ => None,
+    }
+}
+
+/// "3pm", "3:30 pm", "15:00", "at 9am"
+fn parse_time(words: &[&str], _today: NaiveDate, _now: NaiveDateTime) -> Option<(usize, Value)> {
+    let (prefix, rest) = match words {
+        ["at" | "@", rest @ ..] => (1, rest),
+        rest => (0, rest),
+    };
+    let word = *rest.first()?;
+
+    let (clock, meridiem, len) = if let Some(clock) = word.strip_suffix("am") {
+        (clock, Some(false), 1)
+    } else if let Some(clock) = word.strip_suffix("pm") {
+        (clock, Some(true), 1)
+    } else {
+        match rest.get(1) {
+            Some(&"am") => (word, Some(false), 2),
+            Some(&"pm") => (word, Some(true), 2),
+            _ => (word, None, 1),
+        }
+    };
+
+    let (hour, minute) = match clock.split_once(':') {
+        Some((h, m)) => (h.parse::<u32>().ok()?, m.parse::<u32>().ok()?),
+        // a bare number is only a time with am/pm, "3 people" isn't
+        None if meridiem.is_some() => (clock.parse::<u32>().ok()?, 0),
+        None => return None,
+    };
+
+    let hour = match meridiem {
+        Some(_) if !(1..=12).contains(&hour) => return None,
+        Some(false) => hour % 12,
+        Some(true) => hour % 12 + 12,
+        None => hour,
+    };
+    let time = NaiveTime::from_hms_opt(hour, minute, 0)?;
+    Some((prefix + len, Value::Time(time)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(tz: Tz, local: &str) -> DateTime<Tz> {
+        let time = NaiveDateTime::parse_from_str(local, "%Y-%m-%d %H:%M").unwrap();
+        tz.from_local_datetime(&time).unwrap()
+    }
+
+    fn ranges(text: &str, now: DateTime<Tz>) -> Vec<(String, String, String)> {
+        resolve(text, now)
+            .into_iter()
+            .map(|r| {
+                (
+                    r.phrase,
+                    r.start.format("%Y-%m-%dT%H:%MZ").to_string(),
+                    r.end.format("%Y-%m-%dT%H:%MZ").to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn phrases() {
+        // a wednesday, UTC+2
+        let now = at(Tz::Europe__Berlin, "2024-06-05 10:00");
+        let table = [
+            ("tomorrow", "2024-06-05T22:00Z", "2024-06-06T22:00Z"),
+            ("today", "2024-06-04T22:00Z", "2024-06-05T22:00Z"),
+            (
+                "day after tomorrow",
+                "2024-06-06T22:00Z",
+                "2024-06-07T22:00Z",
+            ),
+            ("friday", "2024-06-06T22:00Z", "2024-06-07T22:00Z"),
+            ("wednesday", "2024-06-04T22:00Z", "2024-06-05T22:00Z"),
+            ("next tuesday", "2024-06-10T22:00Z", "2024-06-11T22:00Z"),
+            ("last monday", "2024-06-02T22:00Z", "2024-06-03T22:00Z"),
+            ("tomorrow at 3pm", "2024-06-06T13:00Z", "2024-06-06T14:00Z"),
+            ("3:30pm on friday", "2024-06-07T13:30Z", "2024-06-07T14:30Z"),
+            (
+                "tomorrow afternoon",
+                "2024-06-06T10:00Z",
+                "2024-06-06T15:00Z",
+            ),
+            ("tonight", "2024-06-05T16:00Z", "2024-06-05T22:00Z"),
+            ("in 2 hours", "2024-06-05T10:00Z", "2024-06-05T11:00Z"),
+            ("3 days from now", "2024-06-07T22:00Z", "2024-06-08T22:00Z"),
+            ("the next 3 days", "2024-06-04T22:00Z", "2024-06-08T22:00Z"),
+            ("in two weeks", "2024-06-16T22:00Z", "2024-06-23T22:00Z"),
+            (
+                "a week from today",
+                "2024-06-11T22:00Z",
+                "2024-06-12T22:00Z",
+            ),
+            (
+                "in a couple of months",
+                "2024-07-31T22:00Z",
+                "2024-08-31T22:00Z",
+            ),
+            ("this week", "2024-06-04T22:00Z", "2024-06-09T22:00Z"),
+            ("next week", "2024-06-09T22:00Z", "2024-06-16T22:00Z"),
+            ("next weekend", "2024-06-14T22:00Z", "2024-06-16T22:00Z"),
+            ("end of the month", "2024-06-29T22:00Z", "2024-06-30T22:00Z"),
+            ("next month", "2024-06-30T22:00Z", "2024-07-31T22:00Z"),
+            ("june 20th", "2024-06-19T22:00Z", "2024-06-20T22:00Z"),
+            ("5th of may", "2025-05-04T22:00Z", "2025-05-05T22:00Z"),
+            ("2024-12-24", "2024-12-23T23:00Z", "2024-12-24T23:00Z"),
+        ];
+        for (phrase, start, end) in table {
+            assert_eq!(
+                ranges(phrase, now),
+                vec![(phrase.to_string(), start.to_string(), end.to_string())],
+                "{}",
+                phrase
+            );
+        }
+    }
+
+    #[test]
+    fn phrases_in_a_sentence() {
+        let now = at(Tz::Europe__Berlin, "2024-06-05 10:00");
+        assert_eq!(
+            ranges("Can we meet next tuesday at 9am, or friday?", now),
+            vec![
+                (
+                    "next tuesday at 9am".to_string(),
+                    "2024-06-11T07:00Z".to_string(),
+                    "2024-06-11T08:00Z".to_string()
+                ),
+                (
+                    "friday".to_string(),
+                    "2024-06-06T22:00Z".to_string(),
+                    "2024-06-07T22:00Z".to_string()
+                ),
+            ]
+        );
+        assert!(ranges("3 people want lunch", now).len() == 1);
+        assert!(ranges("book it for the team", now).is_empty());
+    }
+
+    #[test]
+    fn huge_offsets_are_not_dates() {
+        let now = at(Tz::Europe__Berlin, "2024-06-05 10:00");
+        for phrase in [
+            "in 99999999 days",
+            "in 4000000000 days",
+            "in 4000000000 months",
+            "in 4000000000 weeks",
+            "in 4000000000 hours",
+            "in 4000000000 minutes",
+            "4000000000 days from now",
+            "the next 4000000000 days",
+            "in 1300 months",
+            "+262143-12-31",
+        ] {
+            assert!(ranges(phrase, now).is_empty(), "{}", phrase);
+        }
+    }
+
+    #[test]
+    fn dst() {
+        let berlin = Tz::Europe__Berlin;
+        // the day clocks go forward is 23 hours, 02:00-03:00 doesn't exist
+        let spring = at(berlin, "2024-03-30 12:00");
+        assert_eq!(
+            ranges("tomorrow", spring),
+            vec![(
+                "tomorrow".to_string(),
+                "2024-03-30T23:00Z".to_string(),
+                "2024-03-31T22:00Z".to_string()
+            )]
+        );
+        assert_eq!(
+            ranges("tomorrow at 2:30am", spring),
+            vec![(
+                "tomorrow at 2:30am".to_string(),
+                "2024-03-31T01:30Z".to_string(),
+                "2024-03-31T02:30Z".to_string()
+            )]
+        );
+        // the day clocks go back is 25 hours, 02:00-03:00 happens twice
+        let autumn = at(berlin, "2024-10-26 12:00");
+        assert_eq!(
+            ranges("tomorrow", autumn),
+            vec![(
+                "tomorrow".to_string(),
+                "2024-10-26T22:00Z".to_string(),
+                "2024-10-27T23:00Z".to_string()
+            )]
+        );
+        assert_eq!(
+            ranges("tomorrow at 2:30am", autumn),
+            vec![(
+                "tomorrow at 2:30am".to_string(),
+                "2024-10-27T00:30Z".to_string(),
+                "2024-10-27T01:30Z".to_string()
+            )]
+        );
+    }
+}
@@ -26,8 +26,10 @@ pub struct Calendar {
     pub summary: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    #[serde(default)]
     pub location: String,
     pub time_zone: String,
+    #[serde(default)]
     pub conference_properties: ConferenceProperties,
 }
 
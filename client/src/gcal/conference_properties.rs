@@ -1,9 +1,10 @@
 use serde_derive::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ConferenceProperties {
     #[serde(rename = "allowedConferenceSolutionTypes")]
+    #[serde(default)]
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub allowed_solution_types: Vec<AllowedSolutionType>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -26,7 +27,7 @@ pub enum ReminderMethod {
     PopUp,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum AllowedSolutionType {
     EventHangout,
@@ -445,7 +445,7 @@ pub struct EventAttendees {
     pub appears_as_self: Option<bool>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub enum EventResponseStatus {
     #[default]
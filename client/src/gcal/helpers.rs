@@ -1,9 +1,136 @@
-use crate::{gcal::*, groq, prompts::EVENTS_PROMPT};
-use chrono::{DateTime, Duration, Utc};
-use kinode_process_lib::http;
+use crate::{
+    attendees,
+    backend::{Http, HttpResponse, Llm},
+    dates::{self, ResolvedRange},
+    gcal::*,
+    meetings::{self, MeetingSettings, Solution},
+    prompts::EVENTS_PROMPT,
+    tg::format::{escape_html, link, strip_html},
+    timezones,
+    undo::Inverse,
+};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use frankenstein::InlineKeyboardMarkup;
+use kinode_process_lib::{http, println};
+use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, str::FromStr};
 use url::Url;
 
+/// Google rejected the access token, it expired or was revoked. Callers can get a
+/// fresh one from the oauth process and retry.
+#[derive(Debug)]
+pub struct Unauthorized;
+
+impl std::fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "google access token expired or revoked")
+    }
+}
+
+impl std::error::Error for Unauthorized {}
+
+fn check_authorized(res: &HttpResponse) -> anyhow::Result<()> {
+    if res.status() == http::StatusCode::UNAUTHORIZED {
+        return Err(Unauthorized.into());
+    }
+    Ok(())
+}
+
+/// What handling an LLM response came to: the text for the user and what it did.
+pub struct Reply {
+    pub text: String,
+    pub action: Action,
+    // reverts the calendar change, if there was one
+    pub undo: Option<Inverse>,
+    // buttons sent under the text
+    pub keyboard: Option<InlineKeyboardMarkup>,
+    // `text` with formatting, sent instead of it when set
+    pub html: Option<String>,
+}
+
+pub enum Action {
+    // a plain answer, nothing done
+    None,
+    Listed,
+    Scheduled(Box<Event>),
+    // the LLM needs more information from the user
+    Incomplete,
+    Rejected,
+    // the LLM's dates didn't match the ones in the message
+    Mismatch,
+    Undone,
+    // confirm mode: nothing written yet, the user has to confirm first
+    Proposed(Box<Proposal>),
+}
+
+/// An event the LLM asked for, waiting to be written.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Proposal {
+    pub event: Event,
+    pub meeting: bool,
+    // where the meeting happens, when there is one
+    #[serde(default)]
+    pub solution: Solution,
+    // the reply once it's booked
+    pub answer: String,
+}
+
+/// How a request that asks for an event gets booked.
+pub struct BookingOptions<'a> {
+    // propose the event instead of writing it
+    pub confirm: bool,
+    // named in the message, adds a link even without attendees
+    pub solution: Option<Solution>,
+    pub meetings: MeetingSettings,
+    // only called when a zoom meeting is made
+    pub zoom_token: &'a dyn Fn() -> anyhow::Result<String>,
+    // who google emails about the new event
+    pub send_updates: SendUpdates,
+}
+
+impl BookingOptions<'_> {
+    /// Whether an event gets a meeting link, and which kind.
+    fn meeting(&self, has_attendees: bool) -> (bool, Solution) {
+        match self.solution {
+            Some(solution) => (true, solution),
+            None => (has_attendees, self.meetings.default),
+        }
+    }
+}
+
+impl Reply {
+    pub fn new(text: impl Into<String>, action: Action) -> Self {
+        Reply {
+            text: text.into(),
+            action,
+            undo: None,
+            keyboard: None,
+            html: None,
+        }
+    }
+
+    /// A reply written in telegram HTML, `text` being what it reads as.
+    pub fn html(html: impl Into<String>, action: Action) -> Self {
+        let html = html.into();
+        Reply {
+            html: Some(html.clone()),
+            ..Reply::new(strip_html(&html), action)
+        }
+    }
+
+    pub fn with_keyboard(mut self, keyboard: InlineKeyboardMarkup) -> Self {
+        self.keyboard = Some(keyboard);
+        self
+    }
+
+    /// A reply to a calendar change that `/undo` can revert with `inverse`.
+    pub fn undoable(mut self, inverse: Option<Inverse>) -> Self {
+        self.undo = inverse;
+        self
+    }
+}
+
 pub fn create_event(
     summary: &str,
     description: &str,
@@ -11,21 +138,7 @@ pub fn create_event(
     end_time: &str,
     timezone: Option<String>,
     attendees: Vec<EventAttendees>,
-    meeting: bool,
 ) -> anyhow::Result<Event> {
-    let event_id = rand::random::<u64>().to_string();
-    let conference_data = if meeting {
-        Some(EventConferenceData {
-            create_request: Some(EventCreateConferenceRequest {
-                request_id: event_id,
-                ..Default::default()
-            }),
-            ..Default::default()
-        })
-    } else {
-        None
-    };
-
     let attendees = if attendees.is_empty() {
         None
     } else {
@@ -46,26 +159,109 @@ pub fn create_event(
             time_zone: timezone,
         }),
         attendees,
-        conference_data,
         ..Default::default()
     };
     Ok(event)
 }
 
-pub fn schedule_event(token: &str, event: &Event, schedule_meeting: bool) -> anyhow::Result<Event> {
-    let base_url = "https://www.googleapis.com/calendar/v3/calendars/primary/events";
-    let url = if schedule_meeting {
-        Url::from_str(&format!("{}?conferenceDataVersion=1", base_url))?
+/// The google conference type to request on a calendar allowing `allowed`, Meet
+/// where it can be had.
+fn google_conference(allowed: &[AllowedSolutionType]) -> Option<EventConferenceSolutionKeyType> {
+    if allowed.contains(&AllowedSolutionType::HangoutsMeet) {
+        Some(EventConferenceSolutionKeyType::HangoutsMeet)
+    } else if allowed.contains(&AllowedSolutionType::EventNamedHangout) {
+        Some(EventConferenceSolutionKeyType::EventNamedHangout)
+    } else if allowed.contains(&AllowedSolutionType::EventHangout) {
+        Some(EventConferenceSolutionKeyType::EventHangout)
     } else {
-        Url::from_str(base_url)?
-    };
+        None
+    }
+}
+
+/// Writes `proposal.event` with the meeting it asks for. Google conferences are
+/// only requested when the primary calendar allows them, otherwise the event is
+/// written without one. A Zoom meeting is deleted again if the event can't be
+/// written, so a retry doesn't leave one behind.
+pub fn schedule_with_meeting(
+    http: &impl Http,
+    token: &str,
+    proposal: &Proposal,
+    options: &BookingOptions,
+) -> anyhow::Result<Event> {
+    let mut event = proposal.event.clone();
+    // made for this event, with the token it was made with
+    let mut zoom_meeting = None;
+    if proposal.meeting {
+        let summary = event.summary.clone().unwrap_or_default();
+        match proposal.solution {
+            Solution::Meet => {
+                let allowed = get_primary_calendar(http, token)?
+                    .conference_properties
+                    .allowed_solution_types;
+                if let Some(typ) = google_conference(&allowed) {
+                    event.conference_data = Some(EventConferenceData {
+                        create_request: Some(EventCreateConferenceRequest {
+                            conference_solution_key: EventConferenceSolutionKey { typ, name: None },
+                            request_id: rand::random::<u64>().to_string(),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    });
+                }
+            }
+            Solution::Jitsi => {
+                let url = meetings::jitsi_room(&options.meetings.jitsi_url, &summary);
+                meetings::add_link(&mut event, Solution::Jitsi, &url);
+            }
+            Solution::Zoom => {
+                let zoom_token = (options.zoom_token)()?;
+                let meeting = meetings::create_zoom_meeting(http, &zoom_token, &event)?;
+                meetings::add_link(&mut event, Solution::Zoom, &meeting.join_url);
+                zoom_meeting = Some((meeting.id, zoom_token));
+            }
+            Solution::Custom => {
+                let Some(url) = &options.meetings.custom_url else {
+                    return Err(anyhow::anyhow!(
+                        "no meeting link set, add one with /settings link <url>"
+                    ));
+                };
+                meetings::add_link(&mut event, Solution::Custom, url);
+            }
+        }
+    }
+    let conference = event.conference_data.is_some();
+    let scheduled = schedule_event(http, token, &event, conference, options.send_updates);
+    if let (Err(_), Some((id, zoom_token))) = (&scheduled, zoom_meeting) {
+        if let Err(e) = meetings::delete_zoom_meeting(http, &zoom_token, id) {
+            println!("failed to delete unused zoom meeting: {:?}", e);
+        }
+    }
+    scheduled
+}
+
+pub fn schedule_event(
+    http: &impl Http,
+    token: &str,
+    event: &Event,
+    schedule_meeting: bool,
+    send_updates: SendUpdates,
+) -> anyhow::Result<Event> {
+    let base_url = "https://www.googleapis.com/calendar/v3/calendars/primary/events";
+    let mut url = Url::from_str(base_url)?;
+    if schedule_meeting {
+        url.query_pairs_mut()
+            .append_pair("conferenceDataVersion", "1");
+    }
+    url.query_pairs_mut()
+        .append_pair("sendUpdates", &send_updates.to_string());
     let headers = HashMap::from([
         ("Authorization".to_string(), format!("Bearer {}", token)),
         ("Content-Type".to_string(), "application/json".to_string()),
     ]);
 
     let body = serde_json::to_vec(event)?;
-    let res = http::send_request_await_response(http::Method::POST, url, Some(headers), 30, body)?;
+    let res = http.request(http::Method::POST, url, headers, 30, body)?;
+    check_authorized(&res)?;
 
     if res.status().is_success() {
         let event: Event = serde_json::from_slice(&res.body())?;
@@ -79,7 +275,99 @@ pub fn schedule_event(token: &str, event: &Event, schedule_meeting: bool) -> any
     }
 }
 
-pub fn process_schedule_request(token: &str, response: &str) -> anyhow::Result<String> {
+/// The command the LLM put before ENDMARKER, if it gave one.
+pub fn llm_command(response: &str) -> Option<&str> {
+    let (command, _) = response.trim().trim_matches('"').split_once("ENDMARKER")?;
+    Some(command.trim())
+}
+
+/// Writes the proposed event, the reply gets the meeting link if there is one.
+pub fn book(
+    http: &impl Http,
+    token: &str,
+    proposal: Proposal,
+    options: &BookingOptions,
+) -> anyhow::Result<Reply> {
+    let event = schedule_with_meeting(http, token, &proposal, options)?;
+    let mut answer = escape_html(&proposal.answer);
+    let url = match proposal.solution {
+        Solution::Meet => event.hangout_link.as_ref(),
+        _ => event.location.as_ref(),
+    };
+    match url {
+        Some(url) if proposal.meeting => {
+            let label = format!("Join on {}", proposal.solution.label());
+            answer = format!("{}\n{}", answer, link(&label, url));
+        }
+        None if proposal.meeting => {
+            answer = format!(
+                "{}\n(No {} link, the calendar doesn't allow it.)",
+                answer,
+                proposal.solution.label()
+            );
+        }
+        _ => {}
+    }
+    let undo = Inverse::created(&event, options.send_updates);
+    Ok(Reply::html(answer, Action::Scheduled(Box::new(event))).undoable(undo))
+}
+
+fn propose(proposal: Proposal, tz: Tz) -> Reply {
+    Reply::new(preview(&proposal, tz), Action::Proposed(Box::new(proposal)))
+}
+
+/// What confirm mode shows before writing anything.
+pub fn preview(proposal: &Proposal, tz: Tz) -> String {
+    let time = |date: &Option<EventCalendarDate>| {
+        let Some(date) = date else {
+            return "?".to_string();
+        };
+        let raw = date
+            .date_time
+            .clone()
+            .or(date.date.clone())
+            .unwrap_or_default();
+        match timezones::format_local(&raw, tz) {
+            Some(local) => local,
+            None => match &date.time_zone {
+                Some(zone) => format!("{} {}", raw, zone),
+                None => raw,
+            },
+        }
+    };
+    let event = &proposal.event;
+    let attendees = match &event.attendees {
+        Some(attendees) if !attendees.is_empty() => attendees
+            .iter()
+            .map(|attendee| attendee.email.as_str())
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => "none".to_string(),
+    };
+    format!(
+        "Book this?\nTitle: {}\nWhen: {} - {}\nAttendees: {}\nCalendar: primary\nMeeting link: {}",
+        event.summary.as_deref().unwrap_or("Untitled Event"),
+        time(&event.start),
+        time(&event.end),
+        attendees,
+        if proposal.meeting {
+            proposal.solution.label()
+        } else {
+            "none"
+        },
+    )
+}
+
+/// With `confirm`, a SCHEDULE_REQUEST comes back as a proposal instead of being booked.
+pub fn process_schedule_request(
+    http: &impl Http,
+    token: &str,
+    response: &str,
+    owner_tz: Tz,
+    guest_tz: Option<Tz>,
+    ranges: &[ResolvedRange],
+    options: &BookingOptions,
+) -> anyhow::Result<Reply> {
     let cleaned_response = response
         .trim()
         .trim_matches('"')
@@ -101,6 +389,13 @@ pub fn process_schedule_request(token: &str, response: &str) -> anyhow::Result<S
             let title = parts[3].trim();
             let description = parts[4].trim();
 
+            if !dates::start_matches(start, ranges) {
+                return Ok(Reply::new(
+                    dates::mismatch_reply(ranges, guest_tz.unwrap_or(owner_tz)),
+                    Action::Mismatch,
+                ));
+            }
+
             let event = create_event(
                 title,
                 description,
@@ -108,13 +403,24 @@ pub fn process_schedule_request(token: &str, response: &str) -> anyhow::Result<S
                 end,
                 None,
                 vec![], // todo email parsing with longer context.
-                true,
             )?;
-            let event = schedule_event(token, &event, true)?;
-            if let Some(meet) = event.hangout_link {
-                return Ok(format!("{}, link: {}", human_like_response, meet));
+
+            let mut answer = human_like_response.to_string();
+            if let Some(times) = timezones::both_local_times(start, end, guest_tz, owner_tz) {
+                answer = format!("{}\n{}", answer, times);
+            }
+            // a guest always gets a way to join
+            let (_, solution) = options.meeting(true);
+            let proposal = Proposal {
+                event,
+                meeting: true,
+                solution,
+                answer,
+            };
+            if options.confirm {
+                return Ok(propose(proposal, guest_tz.unwrap_or(owner_tz)));
             }
-            return Ok(human_like_response.to_string());
+            return book(http, token, proposal, options);
         } else if command.starts_with("INCOMPLETE_REQUEST") {
             let parts: Vec<&str> = command.split(',').collect();
             if parts.len() < 2 {
@@ -122,9 +428,12 @@ pub fn process_schedule_request(token: &str, response: &str) -> anyhow::Result<S
             }
 
             let missing_info = parts[1].trim();
-            return Ok(format!(
-                "Incomplete request. Please provide the following missing information: {}",
-                missing_info
+            return Ok(Reply::new(
+                format!(
+                    "Incomplete request. Please provide the following missing information: {}",
+                    missing_info
+                ),
+                Action::Incomplete,
             ));
         } else if command.starts_with("REJECTED_REQUEST") {
             let parts: Vec<&str> = command.split(',').collect();
@@ -133,14 +442,25 @@ pub fn process_schedule_request(token: &str, response: &str) -> anyhow::Result<S
             }
 
             let reason = parts[1].trim();
-            return Ok(format!("Request rejected. Reason: {}", reason));
+            return Ok(Reply::new(
+                format!("Request rejected. Reason: {}", reason),
+                Action::Rejected,
+            ));
         }
     }
 
-    Ok(response.to_string())
+    Ok(Reply::new(response, Action::None))
 }
 
-pub fn process_response(token: &str, response: &str) -> anyhow::Result<String> {
+/// With `confirm`, a SCHEDULE comes back as a proposal instead of being booked.
+pub fn process_response<B: Http + Llm>(
+    backend: &B,
+    token: &str,
+    response: &str,
+    tz: Tz,
+    ranges: &[ResolvedRange],
+    options: &BookingOptions,
+) -> anyhow::Result<Reply> {
     let cleaned_response = response
         .trim()
         .trim_matches('"')
@@ -160,17 +480,23 @@ pub fn process_response(token: &str, response: &str) -> anyhow::Result<String> {
             let end_date = parts[2].trim();
             let _timezone = parts[3].trim();
 
-            let events = get_events_from_primary_calendar(token, start_date, end_date)?;
+            if !dates::range_matches(start_date, end_date, ranges) {
+                return Ok(Reply::new(
+                    dates::mismatch_reply(ranges, tz),
+                    Action::Mismatch,
+                ));
+            }
+
+            let events = get_events_from_primary_calendar(backend, token, start_date, end_date)?;
             let filtered_events = events
                 .items
                 .iter()
                 .map(|e| e.into())
                 .collect::<Vec<SimpleEvent>>();
 
-            let llm_events =
-                groq::get_groq_answer(&format!("{} {:?}", EVENTS_PROMPT, filtered_events))?;
+            let llm_events = backend.answer(&format!("{} {:?}", EVENTS_PROMPT, filtered_events))?;
 
-            return Ok(llm_events);
+            return Ok(Reply::new(llm_events, Action::Listed));
         } else if command.starts_with("SCHEDULE") {
             let parts: Vec<&str> = command.split(',').collect();
             if parts.len() < 5 {
@@ -179,6 +505,14 @@ pub fn process_response(token: &str, response: &str) -> anyhow::Result<String> {
             let start = parts[1].trim();
             let end = parts[2].trim();
             let timezone = parts[3].trim();
+
+            if !dates::start_matches(start, ranges) {
+                return Ok(Reply::new(
+                    dates::mismatch_reply(ranges, tz),
+                    Action::Mismatch,
+                ));
+            }
+
             let title = parts.get(4).map(|s| s.trim()).unwrap_or("Untitled Event");
             let description = parts
                 .get(5)
@@ -206,7 +540,7 @@ pub fn process_response(token: &str, response: &str) -> anyhow::Result<String> {
                 vec![]
             };
 
-            let meeting = !attendees.is_empty();
+            let (meeting, solution) = options.meeting(!attendees.is_empty());
 
             let event = create_event(
                 title,
@@ -215,29 +549,59 @@ pub fn process_response(token: &str, response: &str) -> anyhow::Result<String> {
                 end,
                 Some(timezone.into()),
                 attendees,
-                meeting,
             )?;
-            schedule_event(token, &event, meeting)?;
-            return Ok(human_like_response.to_string());
+            let proposal = Proposal {
+                event,
+                meeting,
+                solution,
+                answer: human_like_response.to_string(),
+            };
+            if options.confirm {
+                return Ok(propose(proposal, tz));
+            }
+            return book(backend, token, proposal, options);
+        } else if command.starts_with("ATTENDEES") {
+            let parts: Vec<&str> = command.split(',').collect();
+            if parts.len() < 4 {
+                return Err(anyhow::anyhow!("Invalid ATTENDEES command format"));
+            }
+            let start_date = parts[1].trim();
+            let end_date = parts[2].trim();
+
+            if !dates::range_matches(start_date, end_date, ranges) {
+                return Ok(Reply::new(
+                    dates::mismatch_reply(ranges, tz),
+                    Action::Mismatch,
+                ));
+            }
+
+            let words = attendees::title_words(&parts.get(4..).unwrap_or_default().join(" "));
+            let events = get_events_from_primary_calendar(backend, token, start_date, end_date)?;
+            let text = match attendees::pick(&events.items, &words, tz) {
+                Ok(event) => attendees::responses(event, tz),
+                Err(text) => text,
+            };
+            return Ok(Reply::new(text, Action::Listed));
         }
     }
 
-    Ok(response.to_string())
+    Ok(Reply::new(response, Action::None))
 }
 
-fn is_valid_email(email: &str) -> bool {
+pub fn is_valid_email(email: &str) -> bool {
     let email_regex =
         regex::Regex::new(r"^[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Z|a-z]{2,}$").unwrap();
     email_regex.is_match(email)
 }
 
 pub fn get_events_from_primary_calendar(
+    http: &impl Http,
     token: &str,
     time_min: &str,
     time_max: &str,
 ) -> anyhow::Result<Events> {
     let url = Url::from_str(&format!(
-        "https://www.googleapis.com/calendar/v3/calendars/primary/events?timeMin={}&timeMax={}",
+        "https://www.googleapis.com/calendar/v3/calendars/primary/events?timeMin={}&timeMax={}&singleEvents=true&orderBy=startTime",
         time_min, time_max
     ))?;
     let headers = HashMap::from([
@@ -246,13 +610,188 @@ pub fn get_events_from_primary_calendar(
     ]);
     let body = Vec::new(); // No body for GET request
 
-    let res = http::send_request_await_response(http::Method::GET, url, Some(headers), 5, body)?;
+    let res = http.request(http::Method::GET, url, headers, 5, body)?;
+    check_authorized(&res)?;
 
     let events: Events = serde_json::from_slice(&res.body())?;
 
     Ok(events)
 }
 
+/// Upcoming events of the primary calendar changed since `updated_min`, cancelled
+/// ones included so they can be told apart from unchanged ones.
+pub fn get_changed_events(
+    http: &impl Http,
+    token: &str,
+    updated_min: &str,
+    time_min: &str,
+) -> anyhow::Result<Vec<Event>> {
+    let mut url = Url::from_str(EVENTS_URL)?;
+    url.query_pairs_mut()
+        .append_pair("updatedMin", updated_min)
+        .append_pair("timeMin", time_min)
+        .append_pair("singleEvents", "true")
+        .append_pair("showDeleted", "true")
+        .append_pair("maxResults", "250");
+    let headers = HashMap::from([("Authorization".to_string(), format!("Bearer {}", token))]);
+
+    let res = http.request(http::Method::GET, url, headers, 10, vec![])?;
+    check_authorized(&res)?;
+    if !res.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "Failed to get changed events: {}",
+            String::from_utf8_lossy(res.body())
+        ));
+    }
+    let events: Events = serde_json::from_slice(res.body())?;
+    Ok(events.items)
+}
+
+/// Busy periods of the primary calendar between two UTC times, in order.
+pub struct FreeBusy {
+    // merged across the calendars, sorted by start
+    pub busy: Vec<(DateTime<Utc>, DateTime<Utc>)>,
+    // calendars google wouldn't tell about, e.g. because they aren't shared
+    // with the owner
+    pub unknown: Vec<String>,
+}
+
+pub fn get_busy(
+    http: &impl Http,
+    token: &str,
+    time_min: &DateTime<Utc>,
+    time_max: &DateTime<Utc>,
+    calendars: &[String],
+) -> anyhow::Result<FreeBusy> {
+    let url = Url::from_str("https://www.googleapis.com/calendar/v3/freeBusy")?;
+    let headers = HashMap::from([
+        ("Authorization".to_string(), format!("Bearer {}", token)),
+        ("Content-Type".to_string(), "application/json".to_string()),
+    ]);
+    let items: Vec<_> = calendars
+        .iter()
+        .map(|id| serde_json::json!({ "id": id }))
+        .collect();
+    let body = serde_json::json!({
+        "timeMin": time_min.to_rfc3339_opts(SecondsFormat::Secs, true),
+        "timeMax": time_max.to_rfc3339_opts(SecondsFormat::Secs, true),
+        "items": items,
+    });
+
+    let res = http.request(
+        http::Method::POST,
+        url,
+        headers,
+        5,
+        serde_json::to_vec(&body)?,
+    )?;
+    check_authorized(&res)?;
+    if !res.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "Failed to get free/busy: {}",
+            String::from_utf8_lossy(res.body())
+        ));
+    }
+
+    let json: serde_json::Value = serde_json::from_slice(res.body())?;
+    let parse = |time: &serde_json::Value| {
+        DateTime::parse_from_rfc3339(time.as_str()?)
+            .ok()
+            .map(|time| time.with_timezone(&Utc))
+    };
+    let mut busy = vec![];
+    let mut unknown = vec![];
+    for id in calendars {
+        let calendar = &json["calendars"][id];
+        if calendar.is_null() || calendar["errors"].is_array() {
+            unknown.push(id.clone());
+            continue;
+        }
+        if let Some(periods) = calendar["busy"].as_array() {
+            busy.extend(
+                periods
+                    .iter()
+                    .filter_map(|period| Some((parse(&period["start"])?, parse(&period["end"])?))),
+            );
+        }
+    }
+    busy.sort();
+    Ok(FreeBusy { busy, unknown })
+}
+
+const EVENTS_URL: &str = "https://www.googleapis.com/calendar/v3/calendars/primary/events";
+
+pub fn get_event(http: &impl Http, token: &str, event_id: &str) -> anyhow::Result<Event> {
+    let url = Url::from_str(&format!("{}/{}", EVENTS_URL, event_id))?;
+    let headers = HashMap::from([("Authorization".to_string(), format!("Bearer {}", token))]);
+
+    let res = http.request(http::Method::GET, url, headers, 5, vec![])?;
+    check_authorized(&res)?;
+    if !res.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "Failed to get event: {}",
+            String::from_utf8_lossy(res.body())
+        ));
+    }
+    Ok(serde_json::from_slice(res.body())?)
+}
+
+/// Changes only the fields set in `patch`.
+pub fn update_event(
+    http: &impl Http,
+    token: &str,
+    event_id: &str,
+    patch: &Event,
+    send_updates: SendUpdates,
+) -> anyhow::Result<Event> {
+    let mut url = Url::from_str(&format!("{}/{}", EVENTS_URL, event_id))?;
+    url.query_pairs_mut()
+        .append_pair("sendUpdates", &send_updates.to_string());
+    let headers = HashMap::from([
+        ("Authorization".to_string(), format!("Bearer {}", token)),
+        ("Content-Type".to_string(), "application/json".to_string()),
+    ]);
+
+    let res = http.request(
+        http::Method::PATCH,
+        url,
+        headers,
+        30,
+        serde_json::to_vec(patch)?,
+    )?;
+    check_authorized(&res)?;
+    if !res.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "Failed to update event: {}",
+            String::from_utf8_lossy(res.body())
+        ));
+    }
+    Ok(serde_json::from_slice(res.body())?)
+}
+
+pub fn delete_event(
+    http: &impl Http,
+    token: &str,
+    event_id: &str,
+    send_updates: SendUpdates,
+) -> anyhow::Result<()> {
+    let mut url = Url::from_str(&format!("{}/{}", EVENTS_URL, event_id))?;
+    url.query_pairs_mut()
+        .append_pair("sendUpdates", &send_updates.to_string());
+    let headers = HashMap::from([("Authorization".to_string(), format!("Bearer {}", token))]);
+
+    let res = http.request(http::Method::DELETE, url, headers, 30, vec![])?;
+    check_authorized(&res)?;
+    // 410 is already deleted, fine by us.
+    if !res.status().is_success() && res.status() != http::StatusCode::GONE {
+        return Err(anyhow::anyhow!(
+            "Failed to delete event: {}",
+            String::from_utf8_lossy(res.body())
+        ));
+    }
+    Ok(())
+}
+
 pub fn _get_time_24h() -> (String, String) {
     let now: DateTime<Utc> = Utc::now();
     let time_min = now.format("%Y-%m-%dT%H:%M:%SZ").to_string(); // UTC time, no milliseconds
@@ -263,7 +802,7 @@ pub fn _get_time_24h() -> (String, String) {
     (time_min, time_max)
 }
 
-pub fn _get_primary_calendar(token: &str) -> anyhow::Result<calendar::Calendar> {
+pub fn get_primary_calendar(http: &impl Http, token: &str) -> anyhow::Result<calendar::Calendar> {
     let url = Url::from_str("https://www.googleapis.com/calendar/v3/calendars/primary").unwrap();
 
     let headers = HashMap::from([
@@ -271,12 +810,19 @@ pub fn _get_primary_calendar(token: &str) -> anyhow::Result<calendar::Calendar>
         ("Content-Type".to_string(), "application/json".to_string()),
     ]);
 
-    let res = http::send_request_await_response(http::Method::GET, url, Some(headers), 5, vec![])?;
+    let res = http.request(http::Method::GET, url, headers, 5, vec![])?;
+    check_authorized(&res)?;
+    if !res.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "Failed to get calendar: {}",
+            String::from_utf8_lossy(res.body())
+        ));
+    }
     let cal: calendar::Calendar = serde_json::from_slice(&res.body())?;
     Ok(cal)
 }
 
-pub fn get_timezone(token: &str) -> anyhow::Result<String> {
+pub fn get_timezone(http: &impl Http, token: &str) -> anyhow::Result<String> {
     let url = Url::from_str("https://www.googleapis.com/calendar/v3/users/me/settings/timezone")?;
 
     let headers = HashMap::from([
@@ -284,7 +830,8 @@ pub fn get_timezone(token: &str) -> anyhow::Result<String> {
         ("Content-Type".to_string(), "application/json".to_string()),
     ]);
 
-    let res = http::send_request_await_response(http::Method::GET, url, Some(headers), 5, vec![])?;
+    let res = http.request(http::Method::GET, url, headers, 5, vec![])?;
+    check_authorized(&res)?;
     let json: serde_json::Value = serde_json::from_slice(&res.body())?;
 
     let timezone = json
@@ -295,3 +842,358 @@ pub fn get_timezone(token: &str) -> anyhow::Result<String> {
 
     Ok(timezone.to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audit,
+        backend::{fake_calendar::TOKEN, mock::MockBackend},
+    };
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    const BERLIN: Tz = Tz::Europe__Berlin;
+
+    fn no_zoom() -> anyhow::Result<String> {
+        Err(anyhow::anyhow!("zoom isn't connected"))
+    }
+
+    fn options(confirm: bool) -> BookingOptions<'static> {
+        BookingOptions {
+            confirm,
+            solution: None,
+            meetings: MeetingSettings::default(),
+            zoom_token: &no_zoom,
+            send_updates: SendUpdates::None,
+        }
+    }
+
+    /// What `text` resolves to on Wednesday 5 June 2024, 10:00 in Berlin.
+    fn ranges(text: &str) -> Vec<ResolvedRange> {
+        let now = BERLIN.with_ymd_and_hms(2024, 6, 5, 10, 0, 0).unwrap();
+        dates::resolve(text, now)
+    }
+
+    fn standup(backend: &MockBackend) -> String {
+        backend.calendar.insert(json!({
+            "summary": "Standup",
+            "start": { "dateTime": "2024-06-06T08:00:00Z" },
+            "end": { "dateTime": "2024-06-06T08:15:00Z" },
+            "organizer": { "email": "owner@example.com", "self": true },
+            "attendees": [
+                { "email": "owner@example.com", "self": true, "responseStatus": "accepted" },
+                { "email": "ana@example.com", "displayName": "Ana", "responseStatus": "accepted" },
+                { "email": "bo@example.com", "responseStatus": "needsAction" },
+            ],
+        }))
+    }
+
+    #[test]
+    fn list_summarizes_the_events_in_range() {
+        let backend = MockBackend::answering(&["You have a standup at 10."]);
+        standup(&backend);
+        let response = "LIST,2024-06-05T22:00:00Z,2024-06-06T22:00:00Z,UTC,ENDMARKER Here you go.";
+
+        let reply = process_response(
+            &backend,
+            TOKEN,
+            response,
+            BERLIN,
+            &ranges("what's on tomorrow?"),
+            &options(false),
+        )
+        .unwrap();
+
+        assert!(matches!(reply.action, Action::Listed));
+        assert_eq!(reply.text, "You have a standup at 10.");
+        let prompts = backend.prompts.borrow();
+        assert!(prompts[0].starts_with(EVENTS_PROMPT));
+        assert!(prompts[0].contains("Standup"));
+    }
+
+    #[test]
+    fn list_on_other_dates_is_refused() {
+        let backend = MockBackend::answering(&[]);
+        let response = "LIST,2024-06-07T00:00:00Z,2024-06-08T00:00:00Z,UTC,ENDMARKER";
+
+        let reply = process_response(
+            &backend,
+            TOKEN,
+            response,
+            BERLIN,
+            &ranges("what's on tomorrow?"),
+            &options(false),
+        )
+        .unwrap();
+
+        assert!(matches!(reply.action, Action::Mismatch));
+        assert!(reply.text.contains("\"tomorrow\" as Thu 06 Jun"));
+        assert_eq!(
+            backend
+                .calendar
+                .calls(http::Method::GET, "/calendars/primary/events"),
+            0
+        );
+    }
+
+    #[test]
+    fn schedule_books_with_a_meet_link() {
+        let backend = MockBackend::answering(&[]);
+        let response = "SCHEDULE,2024-06-06T13:00:00Z,2024-06-06T14:00:00Z,UTC,Lunch with Ana,Catch up,[ana@example.com],ENDMARKER Booked lunch.";
+
+        let reply = process_response(
+            &backend,
+            TOKEN,
+            response,
+            BERLIN,
+            &ranges("lunch with ana tomorrow at 3pm"),
+            &options(false),
+        )
+        .unwrap();
+
+        let Action::Scheduled(event) = &reply.action else {
+            panic!("not scheduled: {}", reply.text);
+        };
+        let id = event.id.clone().unwrap();
+        let stored = backend.calendar.event(&id).unwrap();
+        assert_eq!(stored["summary"], "Lunch with Ana");
+        assert_eq!(stored["attendees"][0]["email"], "ana@example.com");
+        let link = stored["hangoutLink"].as_str().unwrap();
+        assert!(reply.html.unwrap().contains(link));
+        assert!(
+            matches!(reply.undo, Some(Inverse::Delete { ref event_id, .. }) if *event_id == id)
+        );
+    }
+
+    #[test]
+    fn schedule_notes_when_meet_is_not_allowed() {
+        let mut backend = MockBackend::answering(&[]);
+        backend.calendar.conference_types = vec![];
+        let response = "SCHEDULE,2024-06-06T13:00:00Z,2024-06-06T14:00:00Z,UTC,Lunch,,[ana@example.com],ENDMARKER Booked.";
+
+        let reply =
+            process_response(&backend, TOKEN, response, BERLIN, &[], &options(false)).unwrap();
+
+        assert!(matches!(reply.action, Action::Scheduled(_)));
+        assert!(reply
+            .text
+            .contains("(No Google Meet link, the calendar doesn't allow it.)"));
+        assert_eq!(backend.calendar.events()[0]["conferenceData"], json!(null));
+    }
+
+    fn zoom_lunch() -> Proposal {
+        let event = create_event(
+            "Lunch with Ana",
+            "",
+            "2024-06-06T13:00:00Z",
+            "2024-06-06T14:00:00Z",
+            None,
+            vec![],
+        )
+        .unwrap();
+        Proposal {
+            event,
+            meeting: true,
+            solution: Solution::Zoom,
+            answer: "Booked lunch.".to_string(),
+        }
+    }
+
+    #[test]
+    fn zoom_meetings_are_made_once_per_event() {
+        let backend = MockBackend::default();
+        let zoom = || Ok("zoom-token".to_string());
+        let options = BookingOptions {
+            zoom_token: &zoom,
+            ..options(false)
+        };
+
+        let reply = book(&backend, TOKEN, zoom_lunch(), &options).unwrap();
+        assert!(reply.html.unwrap().contains("https://zoom.us/j/1"));
+        assert_eq!(
+            backend.calendar.events()[0]["location"],
+            "https://zoom.us/j/1"
+        );
+        assert_eq!(backend.zoom_meetings.get(), 1);
+        assert!(backend.zoom_deleted.borrow().is_empty());
+    }
+
+    #[test]
+    fn zoom_meetings_are_deleted_when_the_event_isnt_written() {
+        let backend = MockBackend::default();
+        let zoom = || Ok("zoom-token".to_string());
+        let options = BookingOptions {
+            zoom_token: &zoom,
+            ..options(false)
+        };
+
+        // an expired google token, retried with a fresh one
+        let Err(e) = book(&backend, "expired", zoom_lunch(), &options) else {
+            panic!("booked with an expired token");
+        };
+        assert!(e.is::<Unauthorized>());
+        book(&backend, TOKEN, zoom_lunch(), &options).unwrap();
+        assert_eq!(backend.zoom_meetings.get(), 2);
+        assert_eq!(*backend.zoom_deleted.borrow(), vec![1]);
+
+        backend
+            .calendar
+            .fail_inserts
+            .set(Some(http::StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(book(&backend, TOKEN, zoom_lunch(), &options).is_err());
+        assert_eq!(*backend.zoom_deleted.borrow(), vec![1, 3]);
+        assert_eq!(backend.calendar.events().len(), 1);
+    }
+
+    #[test]
+    fn zoom_bookings_leave_the_start_url_out_of_the_audit_log() {
+        let backend = MockBackend::default();
+        let zoom = || Ok("zoom-token".to_string());
+        let options = BookingOptions {
+            zoom_token: &zoom,
+            ..options(false)
+        };
+        let user: frankenstein::User =
+            serde_json::from_value(json!({ "id": 1, "is_bot": false, "first_name": "Owner" }))
+                .unwrap();
+
+        for bodies in [false, true] {
+            let recorder = audit::Recorder::new(&backend, bodies);
+            let mut entry = audit::AuditEntry::new(1, &user, "lunch", audit::Prompt::Command);
+            let reply = book(&recorder, TOKEN, zoom_lunch(), &options);
+            entry.finish(recorder.take_calls(), &reply);
+
+            assert!(entry
+                .http_calls
+                .iter()
+                .any(|call| call.url.starts_with("https://api.zoom.us")));
+            let line = serde_json::to_string(&entry).unwrap();
+            assert!(!line.contains("host-token"), "{}", line);
+            assert_eq!(line.contains("start_url"), bodies, "{}", line);
+        }
+    }
+
+    #[test]
+    fn schedule_in_confirm_mode_writes_nothing() {
+        let backend = MockBackend::answering(&[]);
+        let response = "SCHEDULE,2024-06-06T13:00:00Z,2024-06-06T14:00:00Z,UTC,Dentist,Checkup,[],ENDMARKER Booked.";
+
+        let reply =
+            process_response(&backend, TOKEN, response, BERLIN, &[], &options(true)).unwrap();
+
+        assert!(matches!(reply.action, Action::Proposed(_)));
+        assert!(reply.text.contains("Title: Dentist"));
+        assert!(reply.text.contains("Thu 06 Jun 15:00 CEST"));
+        assert!(backend.calendar.events().is_empty());
+    }
+
+    #[test]
+    fn attendees_lists_who_answered() {
+        let backend = MockBackend::answering(&[]);
+        standup(&backend);
+        let response = "ATTENDEES,2024-06-05T22:00:00Z,2024-06-06T22:00:00Z,UTC,standup,ENDMARKER";
+
+        let reply = process_response(
+            &backend,
+            TOKEN,
+            response,
+            BERLIN,
+            &ranges("who hasn't answered tomorrow's standup?"),
+            &options(false),
+        )
+        .unwrap();
+
+        assert_eq!(
+            reply.text,
+            "Standup, Thu 06 Jun 10:00 CEST\nAccepted: Ana (ana@example.com)\nNo answer yet: bo@example.com"
+        );
+    }
+
+    #[test]
+    fn plain_answers_pass_through() {
+        let backend = MockBackend::answering(&[]);
+        let reply =
+            process_response(&backend, TOKEN, "Hello!", BERLIN, &[], &options(false)).unwrap();
+        assert!(matches!(reply.action, Action::None));
+        assert_eq!(reply.text, "Hello!");
+    }
+
+    #[test]
+    fn an_expired_token_is_unauthorized() {
+        let backend = MockBackend::answering(&[]);
+        let response = "LIST,2024-06-05T22:00:00Z,2024-06-06T22:00:00Z,UTC,ENDMARKER";
+        let error = process_response(&backend, "stale", response, BERLIN, &[], &options(false))
+            .err()
+            .unwrap();
+        assert!(error.is::<Unauthorized>());
+    }
+
+    #[test]
+    fn guest_requests_are_booked_with_both_times() {
+        let backend = MockBackend::answering(&[]);
+        let response = "SCHEDULE_REQUEST,2024-06-06T13:00:00Z,2024-06-06T14:00:00Z,Intro call,Meet Jo,ENDMARKER Done!";
+
+        let reply = process_schedule_request(
+            &backend,
+            TOKEN,
+            response,
+            BERLIN,
+            Some(Tz::America__New_York),
+            &ranges("tomorrow at 9am"),
+            &options(false),
+        );
+        // 9am in New York isn't what the guest's message resolved to in Berlin
+        assert!(matches!(reply.unwrap().action, Action::Mismatch));
+
+        let reply = process_schedule_request(
+            &backend,
+            TOKEN,
+            response,
+            BERLIN,
+            Some(Tz::America__New_York),
+            &[],
+            &options(false),
+        )
+        .unwrap();
+        assert!(matches!(reply.action, Action::Scheduled(_)));
+        assert!(reply
+            .text
+            .contains("Your time: Thu 06 Jun 09:00 EDT - Thu 06 Jun 10:00 EDT"));
+        assert!(reply
+            .text
+            .contains("Host time: Thu 06 Jun 15:00 CEST - Thu 06 Jun 16:00 CEST"));
+        // guests always get a way to join
+        assert!(backend.calendar.events()[0]["hangoutLink"].is_string());
+    }
+
+    #[test]
+    fn incomplete_and_rejected_guest_requests() {
+        let backend = MockBackend::answering(&[]);
+        let reply = process_schedule_request(
+            &backend,
+            TOKEN,
+            "INCOMPLETE_REQUEST,your name,ENDMARKER",
+            BERLIN,
+            None,
+            &[],
+            &options(false),
+        )
+        .unwrap();
+        assert!(matches!(reply.action, Action::Incomplete));
+        assert!(reply.text.ends_with("your name"));
+
+        let reply = process_schedule_request(
+            &backend,
+            TOKEN,
+            "REJECTED_REQUEST,outside working hours,ENDMARKER",
+            BERLIN,
+            None,
+            &[],
+            &options(false),
+        )
+        .unwrap();
+        assert!(matches!(reply.action, Action::Rejected));
+        assert!(backend.calendar.requests.borrow().is_empty());
+    }
+}
@@ -0,0 +1,125 @@
+//! Group chats. There the bot only answers commands and messages that mention it
+//! or reply to it, and members can link their calendar to the group so `/free`
+//! looks for time that suits everyone and bookings can invite them. `/free` reads
+//! linked calendars with the owner's token, so the owner approves each link.
+use frankenstein::{Chat, ChatType, Message as TgMessage};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Group {
+    pub title: Option<String>,
+    // telegram user id -> their linked calendar
+    pub members: HashMap<u64, Member>,
+    // telegram user id -> calendar they asked to link, until the owner approves it
+    #[serde(default)]
+    pub pending: HashMap<u64, Member>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Member {
+    pub name: String,
+    // a google calendar id, for most people their email. The owner's account
+    // needs to see its free/busy, e.g. through sharing or the same workspace.
+    pub calendar: String,
+}
+
+impl Group {
+    /// The linked calendars, in a stable order.
+    pub fn calendars(&self) -> Vec<String> {
+        let mut calendars: Vec<_> = self.members.values().map(|m| m.calendar.clone()).collect();
+        calendars.sort();
+        calendars.dedup();
+        calendars
+    }
+
+    /// Links the calendar `user_id` asked for, returning it.
+    pub fn approve(&mut self, user_id: u64) -> Option<&Member> {
+        let member = self.pending.remove(&user_id)?;
+        self.members.insert(user_id, member);
+        self.members.get(&user_id)
+    }
+
+    /// Told to the LLM with requests from the group, so it can invite the members.
+    pub fn describe(&self) -> Option<String> {
+        if self.members.is_empty() {
+            return None;
+        }
+        let mut members: Vec<_> = self
+            .members
+            .values()
+            .map(|m| format!("{} <{}>", m.name, m.calendar))
+            .collect();
+        members.sort();
+        Some(format!(
+            "(Sent in a group chat. Linked members, invite them when the request is for the group: {})",
+            members.join(", ")
+        ))
+    }
+}
+
+pub fn is_group(chat: &Chat) -> bool {
+    matches!(chat.type_field, ChatType::Group | ChatType::Supergroup)
+}
+
+/// Whether a group message is for the bot: a command not meant for another bot,
+/// a mention, or a reply to one of the bot's messages.
+pub fn addressed(msg: &TgMessage, text: &str, bot_username: &str) -> bool {
+    let mention = format!("@{}", bot_username.to_lowercase());
+    if let Some(command) = text.trim_start().strip_prefix('/') {
+        let name = command.split_whitespace().next().unwrap_or_default();
+        return match name.split_once('@') {
+            Some((_, bot)) => bot.eq_ignore_ascii_case(bot_username),
+            None => true,
+        };
+    }
+    if text.to_ascii_lowercase().contains(&mention) {
+        return true;
+    }
+    msg.reply_to_message
+        .as_ref()
+        .and_then(|reply| reply.from.as_ref())
+        .and_then(|from| from.username.as_deref())
+        .is_some_and(|username| username.eq_ignore_ascii_case(bot_username))
+}
+
+/// The text without the bot's @mention.
+pub fn strip_mention(text: &str, bot_username: &str) -> String {
+    let mention = format!("@{}", bot_username.to_lowercase());
+    // ascii lowercasing keeps byte offsets the same
+    let Some(start) = text.to_ascii_lowercase().find(&mention) else {
+        return text.to_string();
+    };
+    let rest = &text[start + mention.len()..];
+    format!("{}{}", &text[..start], rest.trim_start_matches([',', ':']))
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(calendar: &str) -> Member {
+        Member {
+            name: "Ana".to_string(),
+            calendar: calendar.to_string(),
+        }
+    }
+
+    #[test]
+    fn only_approved_calendars_are_linked() {
+        let mut group = Group::default();
+        group.pending.insert(7, member("ana@example.com"));
+        assert!(group.calendars().is_empty());
+        assert_eq!(group.describe(), None);
+
+        assert!(group.approve(8).is_none());
+        let approved = group.approve(7).unwrap();
+        assert_eq!(approved.calendar, "ana@example.com");
+        assert_eq!(group.calendars(), vec!["ana@example.com"]);
+        assert!(group.pending.is_empty());
+        // a second press finds nothing left to approve
+        assert!(group.approve(7).is_none());
+    }
+}
@@ -1,23 +1,57 @@
-use kinode_process_lib::{
-    await_message, call_init, get_blob, http, http::send_response, println, Address, Message,
-    Request,
+use chrono::{DateTime, SecondsFormat, Utc};
+use frankenstein::{
+    CallbackQuery, ChatMember, ChatMemberUpdated, MaybeInaccessibleMessage, Message as TgMessage,
+    UpdateContent,
 };
-use kinode_process_lib::{get_state, set_state, ProcessId};
+use kinode_process_lib::{await_message, call_init, http, println, Address, Message, Request};
+use kinode_process_lib::{get_state, set_state, timer, ProcessId};
 use prompts::{get_default_rules, get_schedule_prompt};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::str::FromStr;
 
+mod activity;
+mod api;
+mod attachments;
+mod attendees;
+mod audit;
+mod backend;
+mod callbacks;
+mod commands;
+mod confirm;
+mod context;
+mod dates;
 mod gcal;
 mod groq;
+mod groups;
+mod meetings;
+mod multipart;
 mod prompts;
+#[path = "../../shared/secrets.rs"]
+mod secrets;
 mod stt;
 mod tg;
+mod timezones;
+mod tts;
+mod undo;
 
+use backend::{Backend, Http, Kinode, Llm, Telegram};
 use gcal::helpers::*;
 use tg::*;
 
+use crate::activity::{Activity, Outcome};
+use crate::attendees::{AttendeeSettings, Rsvps};
+use crate::audit::{AuditEntry, HttpCall, Prompt};
+use crate::callbacks::Callback;
+use crate::confirm::{Choice, Preview};
+use crate::context::Booking;
+use crate::groups::Group;
+use crate::meetings::{MeetingSettings, Solution};
 use crate::prompts::get_default_prompt;
+use crate::stt::{SttOptions, SttProvider, SttSettings};
+use crate::timezones::{GuestTimezone, Mention};
+use crate::tts::TtsSettings;
+use crate::undo::Mutation;
 
 pub const LLM_ADDRESS: (&str, &str, &str, &str) = ("our", "openai", "kcal", "appattacc.os");
 pub const TG_ADDRESS: (&str, &str, &str, &str) = ("our", "tg", "kcal", "appattacc.os");
@@ -25,29 +59,101 @@ pub const STT_ADDRESS: (&str, &str, &str, &str) = ("our", "speech_to_text", "kca
 
 const ICON: &str = include_str!("icon");
 
+// telegram's limit for the text under a voice note
+const MAX_CAPTION_LEN: usize = 1024;
+
+// telegram may restart update ids after this long without updates
+const UPDATE_ID_RESET_SECS: u64 = 6 * 24 * 60 * 60;
+
+// provider names in the oauth process
+const GOOGLE_PROVIDER: &str = "google";
+const ZOOM_PROVIDER: &str = "zoom";
+
+fn default_provider() -> String {
+    GOOGLE_PROVIDER.to_string()
+}
+
 wit_bindgen::generate!({
     path: "wit",
     world: "process",
 });
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct State {
+    #[serde(default, with = "secrets::option")]
     pub google_token: Option<String>,
+    #[serde(default, with = "secrets::option")]
     pub telegram_token: Option<String>,
+    #[serde(default, with = "secrets::option")]
     pub openai_token: Option<String>,
+    #[serde(default, with = "secrets::option")]
     pub groq_token: Option<String>,
     pub timezone: Option<String>,
     pub user_id: Option<u64>,
     pub schedule_rules: String, // could be an option too but we have defaults
+    #[serde(default)]
+    pub guest_timezones: HashMap<u64, String>, // telegram user id -> IANA timezone
+    #[serde(default)]
+    pub google_scopes: Vec<String>, // granted, as reported by the oauth process
+    #[serde(default)]
+    pub oauth_node: Option<String>, // node whose oauth process issued google_token
+    #[serde(default)]
+    pub pending_oauth_node: Option<String>, // node last asked for a login url, its token is taken once
+    #[serde(default)]
+    pub activity: VecDeque<Activity>, // recent messages and what came of them, for the dashboard
+    #[serde(default)]
+    pub undo: HashMap<u64, Vec<Mutation>>, // telegram user id -> bot changes /undo can revert
+    #[serde(default)]
+    pub confirm_mode: bool, // preview bookings and wait for a confirm before writing them
+    #[serde(default)]
+    pub previews: HashMap<i64, Preview>, // chat id -> booking waiting for a confirm
+    #[serde(default)]
+    pub last_update_id: Option<u32>, // last telegram update handled
+    #[serde(default)]
+    pub last_update_at: u64, // unix secs
+    #[serde(default)]
+    pub groups: HashMap<i64, Group>, // group chat id -> members who linked a calendar
+    #[serde(default)]
+    pub bookings: HashMap<i64, VecDeque<Booking>>, // chat id -> bot messages that confirmed a booking
+    #[serde(default)]
+    pub tts: TtsSettings, // voice replies to voice messages
+    #[serde(default)]
+    pub stt: SttSettings, // how voice messages are transcribed
+    #[serde(default)]
+    pub stt_languages: HashMap<u64, String>, // telegram user id -> language their voice messages are in
+    #[serde(default)]
+    pub meetings: MeetingSettings, // which meeting links events get
+    #[serde(default)]
+    pub attendees: AttendeeSettings, // answer alerts and who google emails about changes
+    #[serde(default)]
+    pub rsvps: Rsvps, // attendee answers seen so far, for the alerts
+    #[serde(default)]
+    pub audit_bodies: bool, // keep http bodies, secrets redacted, in the audit log
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 enum CalendarRequest {
     // forwarded/accepted to/from oauth kinode
-    GenerateUrl { target: String },
-    Token { token: String },
+    GenerateUrl {
+        target: String,
+        #[serde(default)]
+        scopes: Vec<String>,
+    },
+    Token {
+        token: String,
+        #[serde(default)]
+        scopes: Vec<String>,
+        #[serde(default = "default_provider")]
+        provider: String,
+    },
     AddApis(Tokens),
-    RefreshToken { target: String },
+    // our refresh token was rejected, the owner has to connect again
+    ReauthRequired {
+        provider: String,
+    },
+    RefreshToken {
+        target: String,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -60,40 +166,238 @@ struct Tokens {
 #[derive(Debug, Serialize, Deserialize)]
 enum OauthResponse {
     GenerateUrl,
-    Url { url: String },
-    Error { error: String },
+    GenerateUrlWithScopes {
+        scopes: Vec<String>,
+    },
+    Url {
+        url: String,
+    },
+    Error {
+        error: String,
+    },
     // todo: remove, for manual refreshing request to oauth node
     RefreshToken,
+    // a token for another provider, e.g. zoom, or a login url if there's none yet
+    RequestToken {
+        provider: String,
+        scopes: Vec<String>,
+    },
+    Revoke,
+    Ok,
+    Token {
+        token: String,
+        #[serde(default)]
+        scopes: Vec<String>,
+    },
 }
 
-// for UI?
+/// What the UI gets to see of the state, credentials only as whether they're set.
 #[derive(Debug, Serialize, Deserialize)]
-enum CalendarResponse {
-    State { state: State },
-    Error { error: String },
+struct StatusView {
+    google_configured: bool,
+    telegram_configured: bool,
+    openai_configured: bool,
+    groq_configured: bool,
+    timezone: Option<String>,
+    schedule_rules: String,
+    google_scopes: Vec<String>,
+    confirm_mode: bool,
+    voice_replies: bool,
+    tts_url: String,
+    tts_voice: String,
+    stt_provider: SttProvider,
+    stt_timeout_secs: u64,
+    echo_transcripts: bool,
+    meeting_default: Solution,
+    jitsi_url: String,
+    meeting_link: Option<String>,
+    rsvp_alerts: bool,
+    notify: attendees::Notify,
+    audit_bodies: bool,
 }
 
-pub fn handle_telegram_message(
+impl From<&State> for StatusView {
+    fn from(state: &State) -> Self {
+        StatusView {
+            google_configured: state.google_token.is_some(),
+            telegram_configured: state.telegram_token.is_some(),
+            openai_configured: state.openai_token.is_some(),
+            groq_configured: state.groq_token.is_some(),
+            timezone: state.timezone.clone(),
+            schedule_rules: state.schedule_rules.clone(),
+            google_scopes: state.google_scopes.clone(),
+            confirm_mode: state.confirm_mode,
+            voice_replies: state.tts.enabled,
+            tts_url: state.tts.url.clone(),
+            tts_voice: state.tts.voice.clone(),
+            stt_provider: state.stt.provider.clone(),
+            stt_timeout_secs: state.stt.timeout_secs,
+            echo_transcripts: state.stt.echo,
+            meeting_default: state.meetings.default,
+            jitsi_url: state.meetings.jitsi_url.clone(),
+            meeting_link: state.meetings.custom_url.clone(),
+            rsvp_alerts: state.attendees.alerts,
+            notify: state.attendees.notify.clone(),
+            audit_bodies: state.audit_bodies,
+        }
+    }
+}
+
+/// Handles every update of a batch from the tg process, in order. Updates handled
+/// before, e.g. redelivered after a restart, are skipped, and one failing doesn't
+/// stop the rest.
+pub fn handle_telegram_updates(
+    backend: &impl Backend,
     our: &Address,
     message: &Message,
     state: &mut State,
 ) -> anyhow::Result<()> {
-    let Some(token) = &state.google_token else {
-        return Err(anyhow::anyhow!("No google token found"));
-    };
-    let Some(msg) = get_last_tg_msg(&message) else {
+    for update in get_tg_updates(message)? {
+        if !is_new_update(state, update.update_id) {
+            continue;
+        }
+        let result = match &update.content {
+            UpdateContent::Message(msg) | UpdateContent::ChannelPost(msg) => {
+                handle_telegram_message(backend, our, msg, state)
+            }
+            UpdateContent::EditedMessage(msg) | UpdateContent::EditedChannelPost(msg) => {
+                handle_edited_message(backend, our, msg, state)
+            }
+            UpdateContent::CallbackQuery(query) => handle_callback(backend, our, state, query),
+            UpdateContent::MyChatMember(member) => handle_chat_member(backend, state, member),
+            _ => Ok(()),
+        };
+        state.last_update_id = Some(update.update_id);
+        state.last_update_at = Utc::now().timestamp() as u64;
+        save(state);
+        if let Err(e) = result {
+            println!(
+                "error handling telegram update {}: {:?}",
+                update.update_id, e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Update ids go up by one, but after a week without updates telegram starts
+/// from a random one, so an old last id says nothing then.
+fn is_new_update(state: &State, update_id: u32) -> bool {
+    // a clock set back leaves last_update_at in the future, that counts as just now
+    let elapsed = (Utc::now().timestamp() as u64).saturating_sub(state.last_update_at);
+    let quiet = elapsed > UPDATE_ID_RESET_SECS;
+    match state.last_update_id {
+        Some(last) if !quiet => update_id > last,
+        _ => true,
+    }
+}
+
+/// Editing a request that still waits for a confirm redoes it. Anything already
+/// done stays as it is.
+fn handle_edited_message(
+    backend: &impl Backend,
+    our: &Address,
+    msg: &TgMessage,
+    state: &mut State,
+) -> anyhow::Result<()> {
+    let pending = state
+        .previews
+        .get(&msg.chat.id)
+        .is_some_and(|preview| preview.request_message_id == Some(msg.message_id));
+    if !pending {
         return Ok(());
-    };
+    }
+    handle_telegram_message(backend, our, msg, state)
+}
+
+/// The bot was added to, removed from or blocked in a chat.
+fn handle_chat_member(
+    backend: &impl Backend,
+    state: &mut State,
+    update: &ChatMemberUpdated,
+) -> anyhow::Result<()> {
+    let was_in = matches!(
+        update.old_chat_member,
+        ChatMember::Member(_) | ChatMember::Administrator(_) | ChatMember::Creator(_)
+    );
+    match &update.new_chat_member {
+        ChatMember::Left(_) | ChatMember::Kicked(_) => {
+            println!("kcal removed from chat {}", update.chat.id);
+            let preview = state.previews.remove(&update.chat.id);
+            let group = state.groups.remove(&update.chat.id);
+            if preview.is_some() || group.is_some() {
+                save(state);
+            }
+        }
+        ChatMember::Member(_) | ChatMember::Administrator(_) if !was_in => {
+            let greeting = if groups::is_group(&update.chat) {
+                "Hi! I answer commands and messages that mention me. /link your calendar so /free finds time that works for everyone here."
+            } else {
+                "Hi! Send /help to see what I can do."
+            };
+            backend.send_message(update.chat.id, greeting)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+pub fn handle_telegram_message(
+    backend: &impl Backend,
+    our: &Address,
+    msg: &TgMessage,
+    state: &mut State,
+) -> anyhow::Result<()> {
     let id = msg.chat.id;
-    let mut text = msg.text.clone().unwrap_or_default();
 
-    // if voice_message, use STT process to transcribe
+    // photos and documents come with a caption instead of text
+    let mut text = msg
+        .text
+        .clone()
+        .or_else(|| msg.caption.clone())
+        .unwrap_or_default();
+
+    let group = groups::is_group(&msg.chat);
+    if group {
+        let username = backend.bot_username()?;
+        // after Edit on a preview the change doesn't need to mention the bot
+        let editing = state.previews.get(&id).is_some_and(|preview| {
+            preview.editing && msg.from.as_ref().map(|from| from.id) == Some(preview.user_id)
+        });
+        if !editing && !groups::addressed(msg, &text, &username) {
+            return Ok(());
+        }
+        text = groups::strip_mention(&text, &username);
+    }
+
     if let Some(voice) = msg.voice.clone() {
-        let audio = get_file(&voice.file_id)?;
-        text += &get_text(audio)?;
+        let audio = backend.get_file(&voice.file_id)?;
+        let user_id = msg.from.as_ref().map(|from| from.id);
+        let options = SttOptions {
+            settings: &state.stt,
+            openai_key: state.openai_token.as_deref(),
+            language: user_id
+                .and_then(|id| state.stt_languages.get(&id))
+                .map(String::as_str),
+        };
+        let heard = match backend.transcribe(audio, &options) {
+            Ok(heard) => heard,
+            Err(e) => {
+                backend.send_message(id, "Sorry, I couldn't make out that voice message.")?;
+                return Err(e);
+            }
+        };
+        if heard.is_empty() {
+            backend.send_message(id, "I didn't hear anything in that voice message.")?;
+            return Ok(());
+        }
+        if state.stt.echo {
+            backend.send_message(id, &format!("I heard: {}", heard))?;
+        }
+        text += &heard;
     }
 
-    if state.user_id.is_none() {
+    if state.user_id.is_none() && state.google_token.is_some() && !group {
         if let Some(user) = &msg.from {
             // if this is the first time we ever get contacted, assume it's the admin.
             state.user_id = Some(user.id);
@@ -101,164 +405,745 @@ pub fn handle_telegram_message(
         }
     }
 
-    if let Some(user_id) = state.user_id {
-        if let Some(user) = &msg.from {
-            if user.id == user_id {
-                let llm_answer = groq::get_groq_answer(&format!(
-                    "{} {}",
-                    get_default_prompt(&state.timezone),
-                    text
-                ))?;
+    confirm::sweep(&mut state.previews);
+    if let Some(user) = &msg.from {
+        // commands work without google too, the ones that need it say so
+        if let Some((command, args)) = commands::parse(&text) {
+            let recorder = audit::Recorder::new(backend, state.audit_bodies);
+            let entry = AuditEntry::new(id, user, &text, Prompt::Command);
+            let reply = commands::run(&recorder, state, &msg.chat, user, command, &args);
+            let guest = state.user_id != Some(user.id);
+            record(state, entry, guest, recorder.take_calls(), &reply);
+            return send_reply(backend, state, msg, user.id, guest, &text, reply?);
+        }
 
-                let initial_answer = process_response(token, &llm_answer)?;
+        // after Edit on a preview, this message says what to change about the request
+        let editing = state
+            .previews
+            .get(&id)
+            .is_some_and(|preview| preview.editing && preview.user_id == user.id);
+        if editing {
+            if let Some(preview) = state.previews.remove(&id) {
+                text = format!("{}\nChange: {}", preview.text, text);
+            }
+        }
+        save(state);
+    }
 
-                let _message = send_bot_message(&initial_answer, id);
-            } else {
-                let rules = &state.schedule_rules;
+    if state.google_token.is_none() {
+        return Err(anyhow::anyhow!("No google token found"));
+    }
+    let (Some(owner), Some(user)) = (state.user_id, &msg.from) else {
+        return Ok(());
+    };
+    let guest = user.id != owner;
 
-                let llm_answer = groq::get_groq_answer(&format!(
-                    "{} {}",
-                    get_schedule_prompt(&our, &state.timezone, &rules),
-                    text
-                ))?;
+    let booking = context::replied_booking(&state.bookings, msg).cloned();
+    if let Some(booking) = &booking {
+        if context::is_cancel(&text) {
+            return cancel_booking(backend, state, msg, user, &text, booking);
+        }
+    }
 
-                let initial_answer = process_schedule_request(token, &llm_answer)?;
-                let _message = send_bot_message(&initial_answer, id);
-            }
+    // what's read out of a file is a guess, so it's always shown before booking
+    let mut confirm = state.confirm_mode;
+    match attachments::read(backend, state.openai_token.as_deref(), msg) {
+        Ok(Some(attachment)) => {
+            text = format!("Make an event from this.\n{}\n{}", attachment, text);
+            confirm = true;
+        }
+        Ok(None) => {}
+        Err(e) => {
+            backend.send_message(id, &format!("I couldn't read that: {}", e))?;
+            return Err(e);
         }
     }
 
-    Ok(())
+    if let Some(context) = context::describe(msg, booking.as_ref()) {
+        text = format!("{}\n{}", text, context);
+    }
+    if let Some(members) = state.groups.get(&id).and_then(Group::describe) {
+        text = format!("{}\n{}", text, members);
+    }
+    let reply = answer_request(backend, our, state, id, user, &text, confirm)?;
+    send_reply(backend, state, msg, user.id, guest, &text, reply)
 }
 
-fn handle_http_message(state: &mut State, req: &http::HttpServerRequest) -> anyhow::Result<()> {
-    if let http::HttpServerRequest::Http(incoming) = req {
-        if incoming.path()? == "/status" {
-            let headers =
-                HashMap::from([("Content-Type".to_string(), "application/json".to_string())]);
-            send_response(
-                http::StatusCode::OK,
-                Some(headers),
-                serde_json::to_vec(&CalendarResponse::State {
-                    state: state.clone(),
-                })?,
-            );
-            return Ok(());
-        } else if incoming.path()? == "/generate" {
-            let Some(blob) = get_blob() else {
-                return Err(anyhow::anyhow!("Failed to get blob"));
-            };
-            let json = serde_json::from_slice::<serde_json::Value>(&blob.bytes)?;
+/// Asks the LLM about a request and does what it answered: the owner's with the
+/// default prompt, anyone else's as a guest under the schedule rules. With
+/// `confirm`, bookings come back as a proposal instead of being written.
+fn answer_request(
+    backend: &impl Backend,
+    our: &Address,
+    state: &mut State,
+    chat_id: i64,
+    user: &frankenstein::User,
+    text: &str,
+    confirm: bool,
+) -> anyhow::Result<Reply> {
+    let recorder = audit::Recorder::new(backend, state.audit_bodies);
+    let owner_tz = timezones::parse_tz(&state.timezone);
+    let (oauth_node, owner) = (state.oauth_node.clone(), state.user_id);
+    let zoom = || zoom_token(backend, oauth_node.as_deref(), owner);
+    let options = booking_options(state, text, confirm, &zoom);
 
-            let target_str = json
-                .get("target")
-                .and_then(|v| v.as_str())
-                .ok_or_else(|| anyhow::anyhow!("Failed to get target"))?;
+    if state.user_id == Some(user.id) {
+        let ranges = dates::resolve(text, Utc::now().with_timezone(&owner_tz));
+        let prompt = format!("{} {}", get_default_prompt(&state.timezone, &ranges), text);
+        let mut entry = AuditEntry::new(chat_id, user, text, Prompt::Default);
+        let reply = recorder.answer(&prompt).and_then(|llm_answer| {
+            entry.set_llm_response(&llm_answer);
+            with_google_token(state, |token| {
+                process_response(&recorder, token, &llm_answer, owner_tz, &ranges, &options)
+            })
+        });
+        record(state, entry, false, recorder.take_calls(), &reply);
+        reply
+    } else {
+        let guest_tz = get_guest_timezone(state, user, text);
+        let ranges = dates::resolve(
+            text,
+            Utc::now().with_timezone(&guest_tz.tz().unwrap_or(owner_tz)),
+        );
+        let prompt = format!(
+            "{} {}",
+            get_schedule_prompt(
+                our,
+                &state.timezone,
+                &guest_tz,
+                &state.schedule_rules,
+                &ranges
+            ),
+            text
+        );
+        let mut entry = AuditEntry::new(chat_id, user, text, Prompt::Schedule);
+        let reply = recorder.answer(&prompt).and_then(|llm_answer| {
+            entry.set_llm_response(&llm_answer);
+            with_google_token(state, |token| {
+                process_schedule_request(
+                    &recorder,
+                    token,
+                    &llm_answer,
+                    owner_tz,
+                    guest_tz.tz(),
+                    &ranges,
+                    &options,
+                )
+            })
+        });
+        record(state, entry, true, recorder.take_calls(), &reply);
+        reply
+    }
+}
 
-            let target = Address::new::<String, ProcessId>(
-                target_str.to_string(),
-                ProcessId::from_str("oauth:kcal:appattacc.os")?,
+/// Sends the reply to `request`, with its buttons if it has any. A proposal goes
+/// out with its confirm buttons and is kept until answered, replacing the chat's
+/// previous one.
+fn send_reply(
+    backend: &impl Backend,
+    state: &mut State,
+    request: &TgMessage,
+    user_id: u64,
+    guest: bool,
+    text: &str,
+    reply: Reply,
+) -> anyhow::Result<()> {
+    let chat_id = request.chat.id;
+    // confirmations are remembered so replies to them can refer to the event
+    let booked = match &reply.action {
+        Action::Scheduled(event) => Some(event.clone()),
+        _ => None,
+    };
+    let Action::Proposed(proposal) = reply.action else {
+        let mut sent = None;
+        // buttons can't go under a voice note
+        if request.voice.is_some() && state.tts.enabled && reply.keyboard.is_none() {
+            match send_voice_reply(backend, state, chat_id, &reply.text) {
+                Ok((message, true)) => sent = Some(message),
+                Ok((_, false)) => {}
+                Err(e) => println!("voice reply failed, answering in text: {:?}", e),
+            }
+        }
+        let sent = match (sent, reply.html, reply.keyboard) {
+            (Some(message), _, _) => Ok(message),
+            (None, Some(html), keyboard) => backend.send_html(chat_id, &html, keyboard),
+            (None, None, Some(keyboard)) => backend.send_keyboard(chat_id, &reply.text, keyboard),
+            (None, None, None) => backend.send_message(chat_id, &reply.text),
+        };
+        if let (Ok(message), Some(event)) = (sent, booked) {
+            context::track(
+                &mut state.bookings,
+                chat_id,
+                message.message_id,
+                user_id,
+                &event,
             );
+            save(state);
+        }
+        return Ok(());
+    };
+    let mut preview = Preview::new(user_id, guest, *proposal, text, request.message_id);
+    let message = backend.send_keyboard(chat_id, &reply.text, confirm::keyboard(preview.id))?;
+    preview.message_id = Some(message.message_id);
+    if let Some(old) = state.previews.insert(chat_id, preview) {
+        if let Some(message_id) = old.message_id {
+            let _ = backend.edit_message(chat_id, message_id, "Replaced by a newer request.");
+        }
+    }
+    save(state);
+    Ok(())
+}
 
-            let resp = Request::new()
-                .target(target)
-                .body(serde_json::to_vec(&OauthResponse::GenerateUrl)?)
-                .send_and_await_response(5)??;
-
-            let res = serde_json::from_slice::<OauthResponse>(resp.body())?;
-            if let OauthResponse::Url { url } = res {
-                let headers =
-                    HashMap::from([("Content-Type".to_string(), "application/json".to_string())]);
-                send_response(
-                    http::StatusCode::OK,
-                    Some(headers),
-                    serde_json::to_vec(&OauthResponse::Url { url })?,
-                );
+/// A button press. Telegram keeps a spinner on the button until it's answered, so
+/// every path ends in `answer_callback`.
+fn handle_callback(
+    backend: &impl Backend,
+    our: &Address,
+    state: &mut State,
+    query: &CallbackQuery,
+) -> anyhow::Result<()> {
+    let Some(callback) = query.data.as_deref().and_then(Callback::parse) else {
+        return backend.answer_callback(&query.id, None);
+    };
+    let (chat_id, message) = match &query.message {
+        Some(MaybeInaccessibleMessage::Message(message)) => (message.chat.id, Some(message)),
+        Some(MaybeInaccessibleMessage::InaccessibleMessage(message)) => (message.chat.id, None),
+        None => return backend.answer_callback(&query.id, None),
+    };
+    match callback {
+        Callback::Preview(choice, preview_id) => {
+            handle_preview_choice(backend, state, query, chat_id, choice, preview_id)
+        }
+        Callback::Slot { start, end } => match message {
+            Some(message) => handle_slot_pick(backend, our, state, query, message, start, end),
+            None => {
+                backend.answer_callback(&query.id, Some("This list is too old, send /free again."))
             }
-        } else if incoming.path()? == "/submit_config" {
-            let Some(blob) = get_blob() else {
-                return Err(anyhow::anyhow!("Failed to get blob"));
-            };
-            let json = serde_json::from_slice::<serde_json::Value>(&blob.bytes)?;
-
-            let mut tokens = Tokens {
-                telegram: json
-                    .get("telegram")
-                    .and_then(|v| v.as_str())
-                    .map(|s| s.to_string()),
-                openai: json
-                    .get("openai")
-                    .and_then(|v| v.as_str())
-                    .map(|s| s.to_string()),
-                groq: json
-                    .get("groq")
-                    .and_then(|v| v.as_str())
-                    .map(|s| s.to_string()),
-            };
-            if let Some(telegram_token) = tokens.telegram.take() {
-                state.telegram_token = Some(telegram_token.clone());
-                init_tg(&telegram_token)?;
-                let _ = subscribe();
+        },
+        Callback::Link(user_id) => handle_link_approval(backend, state, query, message, user_id),
+    }
+}
+
+/// Approve under a group member's `/link`, only the owner's press counts.
+fn handle_link_approval(
+    backend: &impl Backend,
+    state: &mut State,
+    query: &CallbackQuery,
+    message: Option<&TgMessage>,
+    user_id: u64,
+) -> anyhow::Result<()> {
+    if state.user_id != Some(query.from.id) {
+        return backend
+            .answer_callback(&query.id, Some("Only the calendar owner can approve this."));
+    }
+    let Some(message) = message else {
+        return backend.answer_callback(&query.id, None);
+    };
+    let approved = state
+        .groups
+        .get_mut(&message.chat.id)
+        .and_then(|group| group.approve(user_id))
+        .map(|member| format!("Linked {} for {}.", member.calendar, member.name));
+    let Some(text) = approved else {
+        return backend.answer_callback(&query.id, Some("That was approved or unlinked already."));
+    };
+    save(state);
+    backend.edit_message(message.chat.id, message.message_id, &text)?;
+    backend.answer_callback(&query.id, None)
+}
+
+/// Confirm, Edit or Cancel under a preview.
+fn handle_preview_choice(
+    backend: &impl Backend,
+    state: &mut State,
+    query: &CallbackQuery,
+    chat_id: i64,
+    choice: Choice,
+    preview_id: u32,
+) -> anyhow::Result<()> {
+    confirm::sweep(&mut state.previews);
+    let preview = match state.previews.get(&chat_id) {
+        Some(preview) if preview.id == preview_id && preview.user_id == query.from.id => {
+            preview.clone()
+        }
+        Some(preview) if preview.id == preview_id => {
+            return backend.answer_callback(&query.id, Some("Only whoever asked can answer this."));
+        }
+        _ => {
+            return backend.answer_callback(
+                &query.id,
+                Some("This request expired, please send it again."),
+            );
+        }
+    };
+    let edit = |text: &str| match preview.message_id {
+        Some(message_id) => backend.edit_message(chat_id, message_id, text),
+        None => backend.send_message(chat_id, text).map(|_| ()),
+    };
+
+    match choice {
+        Choice::Cancel => {
+            state.previews.remove(&chat_id);
+            save(state);
+            edit("❌ Cancelled, nothing was booked.")?;
+            backend.answer_callback(&query.id, None)
+        }
+        Choice::Edit => {
+            if let Some(preview) = state.previews.get_mut(&chat_id) {
+                preview.editing = true;
             }
-            if let Some(openai_token) = tokens.openai.take() {
-                state.openai_token = Some(openai_token.clone());
-                stt::init_stt(&openai_token)?;
+            save(state);
+            edit("What should change? Send it as a message.")?;
+            backend.answer_callback(&query.id, None)
+        }
+        Choice::Confirm => {
+            state.previews.remove(&chat_id);
+            save(state);
+            let recorder = audit::Recorder::new(backend, state.audit_bodies);
+            let entry = AuditEntry::new(chat_id, &query.from, &preview.text, Prompt::Command);
+            let (oauth_node, owner) = (state.oauth_node.clone(), state.user_id);
+            let zoom = || zoom_token(backend, oauth_node.as_deref(), owner);
+            let options = booking_options(state, "", false, &zoom);
+            let reply = with_google_token(state, |token| {
+                book(&recorder, token, preview.proposal.clone(), &options)
+            });
+            record(state, entry, preview.guest, recorder.take_calls(), &reply);
+            match reply {
+                Ok(reply) => {
+                    edit(&format!("✅ Booked.\n{}", reply.text))?;
+                    if let (Action::Scheduled(event), Some(message_id)) =
+                        (&reply.action, preview.message_id)
+                    {
+                        context::track(
+                            &mut state.bookings,
+                            chat_id,
+                            message_id,
+                            preview.user_id,
+                            event,
+                        );
+                        save(state);
+                    }
+                    backend.answer_callback(&query.id, Some("Booked"))
+                }
+                Err(e) => {
+                    let _ = backend.answer_callback(&query.id, Some("Booking failed"));
+                    let _ = backend.send_message(chat_id, &format!("Booking failed: {}", e));
+                    Err(e)
+                }
             }
-            if let Some(groq_token) = tokens.groq.take() {
-                state.groq_token = Some(groq_token.clone());
-                groq::init_groq(&groq_token)?;
+        }
+    }
+}
+
+/// A slot tapped under `/free` is asked for like a typed request for a meeting
+/// then, so the schedule rules and confirm mode apply to it the same way.
+fn handle_slot_pick(
+    backend: &impl Backend,
+    our: &Address,
+    state: &mut State,
+    query: &CallbackQuery,
+    message: &TgMessage,
+    start: i64,
+    end: i64,
+) -> anyhow::Result<()> {
+    let user = &query.from;
+    let (Some(owner), Some(_)) = (state.user_id, &state.google_token) else {
+        return backend.answer_callback(&query.id, Some("The calendar isn't connected."));
+    };
+    let (Some(start), Some(end)) = (
+        DateTime::from_timestamp(start, 0),
+        DateTime::from_timestamp(end, 0),
+    ) else {
+        return backend.answer_callback(&query.id, None);
+    };
+    // the llm can take longer than telegram waits for an answer
+    backend.answer_callback(&query.id, Some("On it..."))?;
+
+    let guest = user.id != owner;
+    let owner_tz = timezones::parse_tz(&state.timezone);
+    let tz = match state.guest_timezones.get(&user.id) {
+        Some(tz) if guest => tz.parse().unwrap_or(owner_tz),
+        _ => owner_tz,
+    };
+    let (start, end) = (start.with_timezone(&tz), end.with_timezone(&tz));
+    let slot = format!(
+        "{} {}-{}",
+        start.format("%a %d %b"),
+        start.format("%H:%M"),
+        end.format("%H:%M")
+    );
+    let text = format!(
+        "Book a meeting on {} from {} to {} ({})",
+        start.format("%Y-%m-%d"),
+        start.format("%H:%M"),
+        end.format("%H:%M"),
+        tz
+    );
+
+    let chat_id = message.chat.id;
+    let mut text = text;
+    if let Some(group) = state.groups.get(&chat_id) {
+        let calendars = group.calendars();
+        if !calendars.is_empty() {
+            text = format!("{}, inviting {}", text, calendars.join(", "));
+        }
+    }
+    let confirm = state.confirm_mode;
+    let reply = match answer_request(backend, our, state, chat_id, user, &text, confirm) {
+        Ok(reply) => reply,
+        Err(e) => {
+            backend.send_message(chat_id, &format!("Booking {} failed: {}", slot, e))?;
+            return Err(e);
+        }
+    };
+    if let Action::Scheduled(event) = &reply.action {
+        context::track(
+            &mut state.bookings,
+            chat_id,
+            message.message_id,
+            user.id,
+            event,
+        );
+        save(state);
+        return backend.edit_message(chat_id, message.message_id, &format!("✅ {}", reply.text));
+    }
+    backend.edit_message(chat_id, message.message_id, &format!("Picked {}.", slot))?;
+    send_reply(backend, state, message, user.id, guest, &text, reply)
+}
+
+/// Reads `text` out as a voice note. Short answers go as its caption, longer
+/// ones still need their own message, which is when this returns false.
+fn send_voice_reply(
+    backend: &impl Backend,
+    state: &State,
+    chat_id: i64,
+    text: &str,
+) -> anyhow::Result<(TgMessage, bool)> {
+    let audio = tts::synthesize(backend, &state.tts, state.openai_token.as_deref(), text)?;
+    let fits = text.encode_utf16().count() <= MAX_CAPTION_LEN;
+    let message = backend.send_voice(chat_id, audio, fits.then_some(text))?;
+    Ok((message, fits))
+}
+
+/// "cancel this" in reply to a booking confirmation. The owner can cancel any
+/// booking, anyone else only their own.
+fn cancel_booking(
+    backend: &impl Backend,
+    state: &mut State,
+    msg: &TgMessage,
+    user: &frankenstein::User,
+    text: &str,
+    booking: &Booking,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat.id;
+    let guest = state.user_id != Some(user.id);
+    if guest && booking.user_id != user.id {
+        backend.send_message(chat_id, "Only whoever booked it can cancel it.")?;
+        return Ok(());
+    }
+    let recorder = audit::Recorder::new(backend, state.audit_bodies);
+    let entry = AuditEntry::new(chat_id, user, text, Prompt::Command);
+    let summary = booking.summary.as_deref().unwrap_or("untitled event");
+    let send_updates = state.attendees.notify.cancel;
+    let reply = with_google_token(state, |token| {
+        delete_event(&recorder, token, &booking.event_id, send_updates)
+    })
+    .map(|()| Reply::new(format!("Cancelled \"{}\".", summary), Action::None));
+    record(state, entry, guest, recorder.take_calls(), &reply);
+    let reply = match reply {
+        Ok(reply) => reply,
+        Err(e) => {
+            backend.send_message(
+                chat_id,
+                &format!("Cancelling \"{}\" failed: {}", summary, e),
+            )?;
+            return Err(e);
+        }
+    };
+    context::forget(&mut state.bookings, chat_id, &booking.event_id);
+    // nothing left for /undo to delete
+    undo::forget_event(&mut state.undo, &booking.event_id);
+    save(state);
+    backend.send_message(chat_id, &reply.text)?;
+    Ok(())
+}
+
+/// Writes a handled message to the audit log and the dashboard's activity feed.
+/// Changes the reply made are kept for `/undo`.
+fn record(
+    state: &mut State,
+    mut entry: AuditEntry,
+    guest: bool,
+    calls: Vec<HttpCall>,
+    reply: &anyhow::Result<Reply>,
+) {
+    entry.finish(calls, reply);
+    if let Ok(Reply {
+        undo: Some(inverse),
+        ..
+    }) = reply
+    {
+        undo::push(
+            &mut state.undo,
+            entry.user_id,
+            Mutation {
+                at: entry.at.clone(),
+                inverse: inverse.clone(),
+            },
+        );
+    }
+    if let Err(e) = audit::append(&entry) {
+        println!("audit: failed to write entry: {:?}", e);
+    }
+
+    let outcome = match reply {
+        Ok(reply) => Outcome::from(&reply.action),
+        Err(e) => Outcome::Failed {
+            error: e.to_string(),
+        },
+    };
+    activity::record(
+        &mut state.activity,
+        Activity {
+            at: entry.at,
+            chat_id: entry.chat_id,
+            user_id: entry.user_id,
+            user_name: entry.user_name,
+            guest,
+            text: entry.text,
+            reply: entry.reply.unwrap_or_default(),
+            outcome,
+        },
+    );
+    save(state);
+}
+
+/// Reverts the latest change the bot made for `user_id`. It stays undoable if
+/// reverting fails.
+fn undo_last(http: &impl Http, state: &mut State, user_id: u64) -> anyhow::Result<Reply> {
+    let Some(mutation) = state.undo.get_mut(&user_id).and_then(Vec::pop) else {
+        return Ok(Reply::new("Nothing to undo.", Action::None));
+    };
+    match with_google_token(state, |token| mutation.inverse.apply(http, token)) {
+        Ok(()) => Ok(Reply::new(
+            format!("Undone, {}.", mutation.inverse.describe()),
+            Action::Undone,
+        )),
+        Err(e) => {
+            undo::push(&mut state.undo, user_id, mutation);
+            Err(e)
+        }
+    }
+}
+
+/// Runs google calls with the current token. If google rejects it, gets a fresh one
+/// from the oauth process and retries once.
+fn with_google_token<T>(
+    state: &mut State,
+    f: impl Fn(&str) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let Some(token) = state.google_token.clone() else {
+        return Err(anyhow::anyhow!("No google token found"));
+    };
+    match f(&token) {
+        Err(e) if e.is::<Unauthorized>() => {
+            let token = refresh_google_token(state)?;
+            f(&token)
+        }
+        result => result,
+    }
+}
+
+fn refresh_google_token(state: &mut State) -> anyhow::Result<String> {
+    let Some(node) = state.oauth_node.clone() else {
+        return Err(anyhow::anyhow!(
+            "Google token expired and no oauth node known, connect again"
+        ));
+    };
+    let target =
+        Address::new::<String, ProcessId>(node, ProcessId::from_str("oauth:kcal:appattacc.os")?);
+    let resp = Request::new()
+        .target(target)
+        .body(serde_json::to_vec(&OauthResponse::RefreshToken)?)
+        .send_and_await_response(10)??;
+
+    match serde_json::from_slice::<OauthResponse>(resp.body())? {
+        OauthResponse::Token { token, scopes } => {
+            state.google_token = Some(token.clone());
+            if !scopes.is_empty() {
+                state.google_scopes = scopes;
             }
+            save(state);
+            Ok(token)
+        }
+        OauthResponse::Error { error } => Err(anyhow::anyhow!("Token refresh failed: {}", error)),
+        other => Err(anyhow::anyhow!("unexpected oauth response: {:?}", other)),
+    }
+}
 
-            let headers =
-                HashMap::from([("Content-Type".to_string(), "application/json".to_string())]);
-            send_response(
-                http::StatusCode::OK,
-                Some(headers),
-                serde_json::to_vec(&CalendarResponse::State {
-                    state: state.clone(),
-                })?,
-            );
-        } else if incoming.path()? == "/prompt" {
-            // todo generalize
-            let Some(blob) = get_blob() else {
-                return Err(anyhow::anyhow!("Failed to get blob"));
-            };
-            let json = serde_json::from_slice::<serde_json::Value>(&blob.bytes)?;
+/// A zoom access token from the oauth process, refreshed by it if needed. Without
+/// one the oauth process sends a login url, which goes to the owner only.
+fn zoom_token(
+    backend: &impl Telegram,
+    oauth_node: Option<&str>,
+    owner: Option<u64>,
+) -> anyhow::Result<String> {
+    let Some(node) = oauth_node else {
+        return Err(anyhow::anyhow!(
+            "Zoom needs an oauth node, connect google first"
+        ));
+    };
+    let target =
+        Address::new::<&str, ProcessId>(node, ProcessId::from_str("oauth:kcal:appattacc.os")?);
+    let resp = Request::new()
+        .target(target)
+        .body(serde_json::to_vec(&OauthResponse::RequestToken {
+            provider: ZOOM_PROVIDER.to_string(),
+            scopes: vec![],
+        })?)
+        .send_and_await_response(10)??;
 
-            let new_prompt = json
-                .get("new_prompt")
-                .and_then(|v| v.as_str())
-                .ok_or_else(|| anyhow::anyhow!("Failed to get prompt"))?;
+    match serde_json::from_slice::<OauthResponse>(resp.body())? {
+        OauthResponse::Token { token, .. } => Ok(token),
+        OauthResponse::Url { url } => {
+            if let Some(owner) = owner {
+                let _ = backend.send_message(
+                    owner as i64,
+                    &format!("Connect Zoom to book Zoom meetings: {}", url),
+                );
+            }
+            Err(anyhow::anyhow!(
+                "Zoom isn't connected yet, the owner got a link to connect it"
+            ))
+        }
+        OauthResponse::Error { error } => Err(anyhow::anyhow!("zoom: {}", error)),
+        other => Err(anyhow::anyhow!("unexpected oauth response: {:?}", other)),
+    }
+}
 
-            state.schedule_rules = new_prompt.to_string();
+/// How a request is booked. A meeting named in `text` wins over the default one.
+fn booking_options<'a>(
+    state: &State,
+    text: &str,
+    confirm: bool,
+    zoom_token: &'a dyn Fn() -> anyhow::Result<String>,
+) -> BookingOptions<'a> {
+    BookingOptions {
+        confirm,
+        solution: Solution::find_in_text(text),
+        meetings: state.meetings.clone(),
+        zoom_token,
+        send_updates: state.attendees.notify.book,
+    }
+}
+
+/// A timezone mentioned in the message wins and is remembered for the guest,
+/// then a previously remembered one, then a guess from their Telegram language.
+fn get_guest_timezone(state: &mut State, user: &frankenstein::User, text: &str) -> GuestTimezone {
+    match timezones::find_in_text(text) {
+        Some(Mention::Stated(tz)) => {
+            state.guest_timezones.insert(user.id, tz.name().to_string());
             save(state);
+            return GuestTimezone::Known(tz);
+        }
+        // only for this message, "3pm EST" doesn't mean they live there
+        Some(Mention::Named(tz)) => return GuestTimezone::Known(tz),
+        None => {}
+    }
+    if let Some(tz) = state
+        .guest_timezones
+        .get(&user.id)
+        .and_then(|tz| tz.parse().ok())
+    {
+        return GuestTimezone::Known(tz);
+    }
+    match user
+        .language_code
+        .as_deref()
+        .and_then(timezones::from_language_code)
+    {
+        Some(tz) => GuestTimezone::Inferred(tz),
+        None => GuestTimezone::Unknown,
+    }
+}
 
-            let headers =
-                HashMap::from([("Content-Type".to_string(), "application/json".to_string())]);
+/// Context of our timers, to tell them apart when they fire.
+#[derive(Debug, Serialize, Deserialize)]
+enum TimerContext {
+    // the poll timer with this id, see Rsvps::timer
+    PollRsvps(u64),
+}
 
-            send_response(
-                http::StatusCode::OK,
-                Some(headers),
-                serde_json::to_vec(&CalendarResponse::State {
-                    state: state.clone(),
-                })?,
-            );
+fn arm_rsvp_poll(id: u64) {
+    if let Ok(context) = serde_json::to_vec(&TimerContext::PollRsvps(id)) {
+        timer::set_timer(attendees::POLL_INTERVAL_MS, Some(context));
+    }
+}
+
+/// Polls for attendee answers from now on, superseding any poll timer armed before.
+fn start_rsvp_polling(state: &mut State) {
+    state.rsvps.timer += 1;
+    arm_rsvp_poll(state.rsvps.timer);
+}
+
+/// Turns answer alerts on or off, the caller saves. Answers given while they
+/// were off aren't reported once they're back on.
+fn set_rsvp_alerts(state: &mut State, on: bool) {
+    if on && !state.attendees.alerts {
+        state.rsvps.checked_at = Some(Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true));
+        start_rsvp_polling(state);
+    }
+    state.attendees.alerts = on;
+}
+
+fn handle_timer(backend: &impl Backend, state: &mut State, context: &[u8]) -> anyhow::Result<()> {
+    match serde_json::from_slice(context)? {
+        TimerContext::PollRsvps(id) => {
+            // turned off, or a newer timer took over
+            if !state.attendees.alerts || id != state.rsvps.timer {
+                return Ok(());
+            }
+            arm_rsvp_poll(id);
+            poll_rsvps(backend, state)
         }
     }
+}
 
+/// Tells the owner who accepted or declined since the last poll.
+fn poll_rsvps(backend: &impl Backend, state: &mut State) -> anyhow::Result<()> {
+    let (Some(owner), Some(_)) = (state.user_id, &state.google_token) else {
+        return Ok(());
+    };
+    let tz = timezones::parse_tz(&state.timezone);
+    let rsvps = state.rsvps.clone();
+    let (rsvps, alerts) =
+        with_google_token(state, |token| attendees::poll(backend, token, &rsvps, tz))?;
+    state.rsvps = rsvps;
+    save(state);
+    for alert in alerts {
+        backend.send_message(owner as i64, &alert)?;
+    }
     Ok(())
 }
 
 fn handle_message(our: &Address, state: &mut State) -> anyhow::Result<()> {
     let msg = await_message()?;
+    let backend = Kinode;
+
+    if !msg.is_request() && msg.source().process == "timer:distro:sys" {
+        if let Some(context) = msg.context() {
+            handle_timer(&backend, state, context)?;
+        }
+        return Ok(());
+    }
 
     if msg.source().process == "http_server:distro:sys" {
         if msg.source().node != our.node {
             return Err(anyhow::anyhow!("src not our in http message..."));
         }
-        let req = serde_json::from_slice::<http::HttpServerRequest>(msg.body())?;
-
-        handle_http_message(state, &req)?;
+        if let http::HttpServerRequest::Http(req) = serde_json::from_slice(msg.body())? {
+            api::handle(&backend, state, &req);
+        }
         return Ok(());
     }
     let mut tg_address = Address::from(TG_ADDRESS);
@@ -266,49 +1151,20 @@ fn handle_message(our: &Address, state: &mut State) -> anyhow::Result<()> {
     tg_address.node = our.node.clone();
 
     if msg.source() == &tg_address {
-        handle_telegram_message(our, &msg, state)?;
+        handle_telegram_updates(&backend, our, &msg, state)?;
         return Ok(());
     }
 
     match serde_json::from_slice::<CalendarRequest>(msg.body())? {
-        CalendarRequest::GenerateUrl { target } => {
-            // todo cleanup
-            let target: Address = Address::new::<String, ProcessId>(
-                target,
-                ProcessId::from_str("oauth:kcal:appattacc.os")?,
-            );
-
-            let url = Request::new()
-                .target(target)
-                .body(serde_json::to_vec(&OauthResponse::GenerateUrl)?)
-                .send_and_await_response(5)??;
-
-            let res = serde_json::from_slice::<OauthResponse>(url.body())?;
-            match res {
-                OauthResponse::Url { url } => {
-                    println!("got url: {:?}", url);
-                    // todo remove, this mostly happens through http redirects.
-                    // open url in browser
-                    // or send to UI
-                }
-                _ => {}
-            }
+        CalendarRequest::GenerateUrl { target, scopes } => {
+            let url = request_auth_url(state, target, scopes)?;
+            println!("got url: {:?}", url);
+            // todo remove, this mostly happens through http redirects.
+            // open url in browser
+            // or send to UI
         }
-        CalendarRequest::AddApis(mut tokens) => {
-            if let Some(telegram_token) = tokens.telegram.take() {
-                state.telegram_token = Some(telegram_token.clone());
-                init_tg(&telegram_token)?;
-                let _ = subscribe();
-            }
-            if let Some(openai_token) = tokens.openai.take() {
-                state.openai_token = Some(openai_token.clone());
-                stt::init_stt(&openai_token)?;
-            }
-            if let Some(groq_token) = tokens.groq.take() {
-                state.groq_token = Some(groq_token.clone());
-                groq::init_groq(&groq_token)?;
-            }
-            save(state);
+        CalendarRequest::AddApis(tokens) => {
+            set_tokens(state, tokens)?;
         }
         CalendarRequest::RefreshToken { target } => {
             // todo cleanup
@@ -321,10 +1177,47 @@ fn handle_message(our: &Address, state: &mut State) -> anyhow::Result<()> {
                 .body(serde_json::to_vec(&OauthResponse::RefreshToken)?)
                 .send_and_await_response(5)?;
         }
-        CalendarRequest::Token { token } => {
-            // todo: verify if it's from the right place too.
+        CalendarRequest::ReauthRequired { provider } => {
+            if provider != GOOGLE_PROVIDER || !from_oauth(msg.source(), &state.oauth_node) {
+                return Ok(());
+            }
+            println!("oauth asks to reconnect {}", provider);
+            state.google_token = None;
+            state.google_scopes.clear();
+            save(state);
+            if let Some(user_id) = state.user_id {
+                let _ = backend.send_message(
+                    user_id as i64,
+                    "Google access expired or was revoked, please connect again in the kcal UI.",
+                );
+            }
+        }
+        CalendarRequest::Token {
+            token,
+            scopes,
+            provider,
+        } => {
+            // others, like zoom, are asked for when they're needed
+            if provider != GOOGLE_PROVIDER {
+                return Ok(());
+            }
+            // refreshes come from the node we're connected through, a new login
+            // from the one we last asked for a url.
+            if !from_oauth(msg.source(), &state.oauth_node)
+                && !from_oauth(msg.source(), &state.pending_oauth_node)
+            {
+                return Err(anyhow::anyhow!(
+                    "google token from unexpected source {}",
+                    msg.source()
+                ));
+            }
             state.google_token = Some(token.clone());
-            let timezone = get_timezone(&token)?;
+            state.oauth_node = Some(msg.source().node.clone());
+            state.pending_oauth_node = None;
+            if !scopes.is_empty() {
+                state.google_scopes = scopes;
+            }
+            let timezone = get_timezone(&backend, &token)?;
             state.timezone = Some(timezone);
             save(state);
         }
@@ -333,37 +1226,106 @@ fn handle_message(our: &Address, state: &mut State) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Whether `source` is the oauth process on `node`.
+fn from_oauth(source: &Address, node: &Option<String>) -> bool {
+    source.process == "oauth:kcal:appattacc.os" && node.as_ref() == Some(&source.node)
+}
+
+/// Asks the oauth process on `target` for a google login url, and remembers to
+/// take the token it sends once the login is done.
+fn request_auth_url(
+    state: &mut State,
+    target: String,
+    scopes: Vec<String>,
+) -> anyhow::Result<String> {
+    state.pending_oauth_node = Some(target.clone());
+    save(state);
+    // todo cleanup
+    let target: Address =
+        Address::new::<String, ProcessId>(target, ProcessId::from_str("oauth:kcal:appattacc.os")?);
+
+    let resp = Request::new()
+        .target(target)
+        .body(generate_url_request(scopes)?)
+        .send_and_await_response(5)??;
+
+    match serde_json::from_slice::<OauthResponse>(resp.body())? {
+        OauthResponse::Url { url } => Ok(url),
+        OauthResponse::Error { error } => Err(anyhow::anyhow!("oauth: {}", error)),
+        other => Err(anyhow::anyhow!("unexpected oauth response: {:?}", other)),
+    }
+}
+
+/// Stores and starts using the given api keys, missing ones are left as they are.
+fn set_tokens(state: &mut State, mut tokens: Tokens) -> anyhow::Result<()> {
+    if let Some(telegram_token) = tokens.telegram.take() {
+        state.telegram_token = Some(telegram_token.clone());
+        // a new bot has its own update ids
+        state.last_update_id = None;
+        init_tg(&telegram_token)?;
+        let _ = subscribe();
+        if let Err(e) = set_my_commands(&commands::menu()) {
+            println!("failed to register bot commands: {:?}", e);
+        }
+    }
+    if let Some(openai_token) = tokens.openai.take() {
+        state.openai_token = Some(openai_token.clone());
+        stt::init_stt(&openai_token)?;
+    }
+    if let Some(groq_token) = tokens.groq.take() {
+        state.groq_token = Some(groq_token.clone());
+        groq::init_groq(&groq_token)?;
+    }
+    save(state);
+    Ok(())
+}
+
+/// Plain GenerateUrl (the full calendar scope) unless specific scopes are asked for,
+/// so oauth nodes that predate scope selection keep working.
+fn generate_url_request(scopes: Vec<String>) -> anyhow::Result<Vec<u8>> {
+    let request = if scopes.is_empty() {
+        OauthResponse::GenerateUrl
+    } else {
+        OauthResponse::GenerateUrlWithScopes { scopes }
+    };
+    Ok(serde_json::to_vec(&request)?)
+}
+
+/// Clears the google token locally, then asks the oauth process that issued it to
+/// revoke it. The local token is gone even if the oauth node can't be reached.
+fn disconnect(state: &mut State) -> anyhow::Result<()> {
+    state.google_token = None;
+    state.google_scopes.clear();
+    let oauth_node = state.oauth_node.take();
+    save(state);
+
+    let Some(node) = oauth_node else {
+        return Ok(());
+    };
+    let target =
+        Address::new::<String, ProcessId>(node, ProcessId::from_str("oauth:kcal:appattacc.os")?);
+    let resp = Request::new()
+        .target(target)
+        .body(serde_json::to_vec(&OauthResponse::Revoke)?)
+        .send_and_await_response(10)??;
+
+    match serde_json::from_slice::<OauthResponse>(resp.body())? {
+        OauthResponse::Error { error } => Err(anyhow::anyhow!(error)),
+        _ => Ok(()),
+    }
+}
+
 fn save(state: &State) {
     let state = serde_json::to_vec(state).unwrap();
     set_state(&state);
 }
 
 fn initialize() -> State {
-    if let Some(state) = get_state() {
-        if let Ok(state) = serde_json::from_slice(&state) {
-            state
-        } else {
-            return State {
-                google_token: None,
-                telegram_token: None,
-                openai_token: None,
-                groq_token: None,
-                timezone: None,
-                user_id: None,
-                schedule_rules: get_default_rules(),
-            };
-        }
-    }
-
-    State {
-        google_token: None,
-        telegram_token: None,
-        openai_token: None,
-        groq_token: None,
-        timezone: None,
-        user_id: None,
+    let saved = get_state().and_then(|state| serde_json::from_slice(&state).ok());
+    saved.unwrap_or_else(|| State {
         schedule_rules: get_default_rules(),
-    }
+        ..Default::default()
+    })
 }
 
 call_init!(init);
@@ -371,10 +1333,7 @@ fn init(our: Address) {
     println!("client begin");
 
     http::serve_index_html(&our, "client-ui/", true, false, vec!["/"]).unwrap();
-    http::bind_http_path("/status", true, false).unwrap();
-    http::bind_http_path("/generate", true, false).unwrap();
-    http::bind_http_path("/submit_config", true, false).unwrap();
-    http::bind_http_path("/prompt", true, false).unwrap();
+    api::bind().unwrap();
 
     Request::to(("our", "homepage", "homepage", "sys"))
         .body(
@@ -392,7 +1351,31 @@ fn init(our: Address) {
         .send()
         .unwrap();
 
+    // before initialize, the saved tokens are encrypted with it.
+    secrets::init(&our).unwrap();
+    audit::init(&our).unwrap();
     let mut state = initialize();
+    let lost = secrets::take_failures();
+    if lost > 0 {
+        // re-encrypt what's left, the lost ones have to be set again.
+        save(&state);
+    }
+    if state.attendees.alerts {
+        start_rsvp_polling(&mut state);
+    }
+    if let Some(telegram_token) = &state.telegram_token {
+        tg::set_bot_token(telegram_token);
+        if let Err(e) = set_my_commands(&commands::menu()) {
+            println!("failed to register bot commands: {:?}", e);
+        }
+    }
+    if lost > 0 {
+        let text = "Some saved tokens couldn't be decrypted and were cleared, please set them again in the kcal UI.";
+        println!("{}", text);
+        if let (Some(user_id), Some(_)) = (state.user_id, &state.telegram_token) {
+            let _ = Kinode.send_message(user_id as i64, text);
+        }
+    }
 
     loop {
         match handle_message(&our, &mut state) {
@@ -403,3 +1386,34 @@ fn init(our: Address) {
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_update(id: u32, at: u64) -> State {
+        State {
+            last_update_id: Some(id),
+            last_update_at: at,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn update_ids_are_compared_unless_telegram_may_have_reset_them() {
+        let now = Utc::now().timestamp() as u64;
+
+        let state = last_update(10, now);
+        assert!(is_new_update(&state, 11));
+        assert!(!is_new_update(&state, 10));
+
+        // a week and more without updates
+        let state = last_update(10, now - UPDATE_ID_RESET_SECS - 60);
+        assert!(is_new_update(&state, 3));
+
+        // saved by a clock that was ahead
+        let state = last_update(10, now + 3600);
+        assert!(is_new_update(&state, 11));
+        assert!(!is_new_update(&state, 9));
+    }
+}
@@ -0,0 +1,218 @@
+//! Where a meeting happens. Google Meet is created by the calendar itself, the
+//! others are plain links put into the event: a generated Jitsi room, a Zoom
+//! meeting made through Zoom's API, or a fixed link of the owner's.
+use crate::{backend::Http, gcal::Event};
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+use kinode_process_lib::http;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, str::FromStr};
+use url::Url;
+
+pub const DEFAULT_JITSI_URL: &str = "https://meet.jit.si";
+const ZOOM_MEETINGS_URL: &str = "https://api.zoom.us/v2/users/me/meetings";
+// a single meeting, by id
+const ZOOM_MEETING_URL: &str = "https://api.zoom.us/v2/meetings";
+// when the event's times can't be read
+const DEFAULT_MINUTES: i64 = 60;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Solution {
+    #[default]
+    Meet,
+    Jitsi,
+    Zoom,
+    // MeetingSettings::custom_url
+    Custom,
+}
+
+impl Solution {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "meet" | "google" | "google meet" => Some(Solution::Meet),
+            "jitsi" => Some(Solution::Jitsi),
+            "zoom" => Some(Solution::Zoom),
+            "custom" => Some(Solution::Custom),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Solution::Meet => "Google Meet",
+            Solution::Jitsi => "Jitsi",
+            Solution::Zoom => "Zoom",
+            Solution::Custom => "your meeting link",
+        }
+    }
+
+    /// The solution a message asks for by name, e.g. "a zoom call with Ana".
+    pub fn find_in_text(text: &str) -> Option<Self> {
+        let text = text.to_lowercase();
+        let words: Vec<&str> = text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect();
+        let has = |word: &str| words.contains(&word);
+        if has("zoom") {
+            Some(Solution::Zoom)
+        } else if has("jitsi") {
+            Some(Solution::Jitsi)
+        } else if text.contains("google meet") || has("gmeet") || has("hangouts") {
+            Some(Solution::Meet)
+        } else if text.contains("my room") || text.contains("my meeting link") {
+            Some(Solution::Custom)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MeetingSettings {
+    // used for events with attendees unless the message names another
+    pub default: Solution,
+    pub jitsi_url: String,
+    // e.g. a personal zoom room
+    pub custom_url: Option<String>,
+}
+
+impl Default for MeetingSettings {
+    fn default() -> Self {
+        MeetingSettings {
+            default: Solution::Meet,
+            jitsi_url: DEFAULT_JITSI_URL.to_string(),
+            custom_url: None,
+        }
+    }
+}
+
+/// A fresh room on the Jitsi server, named after the event so it reads well in
+/// the link, with a random part so it can't be guessed.
+pub fn jitsi_room(base_url: &str, summary: &str) -> String {
+    let name: String = summary
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .take(30)
+        .collect();
+    format!(
+        "{}/{}{:012x}",
+        base_url.trim_end_matches('/'),
+        name,
+        rand::random::<u64>() & 0xffff_ffff_ffff
+    )
+}
+
+#[derive(Deserialize)]
+pub struct ZoomMeeting {
+    pub id: u64,
+    pub join_url: String,
+}
+
+/// Creates a scheduled Zoom meeting for `event`.
+pub fn create_zoom_meeting(
+    http: &impl Http,
+    token: &str,
+    event: &Event,
+) -> anyhow::Result<ZoomMeeting> {
+    let start = event
+        .start
+        .as_ref()
+        .and_then(|start| start.date_time.clone());
+    let end = event.end.as_ref().and_then(|end| end.date_time.clone());
+    let Some(start) = start else {
+        return Err(anyhow::anyhow!("zoom meetings need a start time"));
+    };
+    let mut body = serde_json::json!({
+        "topic": event.summary.as_deref().unwrap_or("Meeting"),
+        // a meeting at a set time
+        "type": 2,
+        "duration": end.as_deref().and_then(|end| minutes(&start, end)).unwrap_or(DEFAULT_MINUTES),
+    });
+    // zoom takes UTC with a Z, or a local time with its timezone
+    match DateTime::parse_from_rfc3339(&start) {
+        Ok(start) => {
+            body["start_time"] = start
+                .with_timezone(&Utc)
+                .to_rfc3339_opts(SecondsFormat::Secs, true)
+                .into();
+        }
+        Err(_) => {
+            body["start_time"] = start.into();
+            if let Some(tz) = event
+                .start
+                .as_ref()
+                .and_then(|start| start.time_zone.clone())
+            {
+                body["timezone"] = tz.into();
+            }
+        }
+    }
+
+    let headers = HashMap::from([
+        ("Authorization".to_string(), format!("Bearer {}", token)),
+        ("Content-Type".to_string(), "application/json".to_string()),
+    ]);
+    let res = http.request(
+        http::Method::POST,
+        Url::from_str(ZOOM_MEETINGS_URL)?,
+        headers,
+        30,
+        serde_json::to_vec(&body)?,
+    )?;
+    if !res.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "creating the zoom meeting failed: {}",
+            String::from_utf8_lossy(res.body())
+        ));
+    }
+    Ok(serde_json::from_slice(res.body())?)
+}
+
+/// Deletes a meeting made by `create_zoom_meeting`, for when its event couldn't be written.
+pub fn delete_zoom_meeting(http: &impl Http, token: &str, id: u64) -> anyhow::Result<()> {
+    let headers = HashMap::from([("Authorization".to_string(), format!("Bearer {}", token))]);
+    let res = http.request(
+        http::Method::DELETE,
+        Url::from_str(&format!("{}/{}", ZOOM_MEETING_URL, id))?,
+        headers,
+        30,
+        vec![],
+    )?;
+    if !res.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "deleting zoom meeting {} failed: {}",
+            id,
+            String::from_utf8_lossy(res.body())
+        ));
+    }
+    Ok(())
+}
+
+fn minutes(start: &str, end: &str) -> Option<i64> {
+    let minutes = match (
+        DateTime::parse_from_rfc3339(start),
+        DateTime::parse_from_rfc3339(end),
+    ) {
+        (Ok(start), Ok(end)) => (end - start).num_minutes(),
+        _ => {
+            let parse = |time: &str| NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%S");
+            (parse(end).ok()? - parse(start).ok()?).num_minutes()
+        }
+    };
+    (minutes > 0).then_some(minutes)
+}
+
+/// Puts a meeting link where attendees look for one: the location, and the
+/// description for calendar apps that don't show locations as links.
+pub fn add_link(event: &mut Event, solution: Solution, url: &str) {
+    event.location = Some(url.to_string());
+    let line = match solution {
+        Solution::Custom => format!("Join the meeting: {}", url),
+        _ => format!("Join on {}: {}", solution.label(), url),
+    };
+    event.description = Some(match event.description.take() {
+        Some(description) if !description.is_empty() => format!("{}\n\n{}", description, line),
+        _ => line,
+    });
+}
@@ -0,0 +1,50 @@
+//! `multipart/form-data` bodies, for the endpoints that take files: telegram's
+//! sendVoice and the speech-to-text services.
+
+pub struct Form {
+    boundary: String,
+    body: Vec<u8>,
+}
+
+impl Form {
+    pub fn new() -> Self {
+        Form {
+            boundary: format!("kcal{:016x}", rand::random::<u64>()),
+            body: vec![],
+        }
+    }
+
+    pub fn text(mut self, name: &str, value: &str) -> Self {
+        self.body.extend(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                self.boundary, name, value
+            )
+            .into_bytes(),
+        );
+        self
+    }
+
+    pub fn file(mut self, name: &str, filename: &str, content_type: &str, bytes: Vec<u8>) -> Self {
+        self.body.extend(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+                self.boundary, name, filename, content_type
+            )
+            .into_bytes(),
+        );
+        self.body.extend(bytes);
+        self.body.extend(b"\r\n");
+        self
+    }
+
+    /// The Content-Type header and the body.
+    pub fn finish(mut self) -> (String, Vec<u8>) {
+        self.body
+            .extend(format!("--{}--\r\n", self.boundary).into_bytes());
+        (
+            format!("multipart/form-data; boundary={}", self.boundary),
+            self.body,
+        )
+    }
+}
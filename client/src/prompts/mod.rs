@@ -1,13 +1,11 @@
+use crate::dates::{self, ResolvedRange};
+use crate::timezones::{parse_tz, GuestTimezone};
 use chrono::{DateTime, SecondsFormat, Utc};
-use chrono_tz::Tz;
 use kinode_process_lib::Address;
 
-pub fn get_default_prompt(timezone: &Option<String>) -> String {
-    let tz: Tz = timezone
-        .as_deref()
-        .unwrap_or("UTC")
-        .parse()
-        .unwrap_or(Tz::UTC);
+pub fn get_default_prompt(timezone: &Option<String>, ranges: &[ResolvedRange]) -> String {
+    let tz = parse_tz(timezone);
+    let date_hint = dates::prompt_hint(ranges, tz);
 
     let current_utc_time: DateTime<Utc> = Utc::now();
     let current_local_time = current_utc_time.with_timezone(&tz);
@@ -36,7 +34,11 @@ SCHEDULE,start_in_YYYY-MM-DDTHH:MM:SSZ_format,end_in_YYYY-MM-DDTHH:MM:SSZ_format
 Attendees should be in the format [email1,email2,email3].
 Followed by a human-like confirmation of the scheduled event.
 
-3. For any other query, provide a helpful and relevant response.
+3. If the user asks who is coming to an event or who hasn't answered its invitation:
+ATTENDEES,start_date_in_YYYY-MM-DDTHH:MM:SSZ_format,end_date_in_YYYY-MM-DDTHH:MM:SSZ_format,UTC,words of the event title,ENDMARKER
+The date range covers the day the event is on.
+
+4. For any other query, provide a helpful and relevant response.
 
 Examples:
 Input: What's on my calendar for next week?
@@ -49,27 +51,54 @@ Output:
 SCHEDULE,2024-05-23T22:00:00Z,2024-05-23T23:00:00Z,UTC,Dentist Appointment,Regular checkup,[],ENDMARKER
 Your dentist appointment has been scheduled for tomorrow at 3:00 PM.
 
+Input: Who hasn't responded to Friday's standup?
+Output:
+ATTENDEES,2024-05-24T00:00:00Z,2024-05-24T23:59:59Z,UTC,standup,ENDMARKER
+
+{date_hint}
 User input:
 "#,
         timezone = tz,
         local_time = formatted_local_time,
+        date_hint = date_hint,
     )
 }
 
-pub fn get_schedule_prompt(our: &Address, timezone: &Option<String>, rules: &str) -> String {
-    let tz: Tz = timezone
-        .as_deref()
-        .unwrap_or("UTC")
-        .parse()
-        .unwrap_or(Tz::UTC);
+pub fn get_schedule_prompt(
+    our: &Address,
+    timezone: &Option<String>,
+    guest_timezone: &GuestTimezone,
+    rules: &str,
+    ranges: &[ResolvedRange],
+) -> String {
+    let tz = parse_tz(timezone);
+    let date_hint = dates::prompt_hint(ranges, guest_timezone.tz().unwrap_or(tz));
 
     let current_utc_time: DateTime<Utc> = Utc::now();
     let formatted_utc_time = current_utc_time.to_rfc3339_opts(SecondsFormat::Secs, true);
 
+    let guest_timezone_info = match guest_timezone {
+        GuestTimezone::Known(guest_tz) => format!(
+            r#"The user's time zone is "{guest_tz}". Their current local time is {}."#,
+            current_utc_time
+                .with_timezone(guest_tz)
+                .to_rfc3339_opts(SecondsFormat::Secs, true)
+        ),
+        GuestTimezone::Inferred(guest_tz) => format!(
+            r#"The user's time zone is probably "{guest_tz}" (guessed from their language settings). Their current local time would be {}. Mention the assumed time zone in your reply so they can correct it."#,
+            current_utc_time
+                .with_timezone(guest_tz)
+                .to_rfc3339_opts(SecondsFormat::Secs, true)
+        ),
+        GuestTimezone::Unknown => "The user's time zone is unknown. If they give a time without a time zone or city, respond with INCOMPLETE_REQUEST asking which city or time zone they are in.".to_string(),
+    };
+
     let our_node = &our.node;
     format!(
         r#"
-        You are an AI assistant helping people schedule events with {our_node}. The current UTC time is {utc_time}, and the user's time zone is "{timezone}".
+        You are an AI assistant helping people schedule events with {our_node}. The current UTC time is {utc_time}.
+        {our_node}'s time zone is "{timezone}", the rules below are written in that time zone.
+        {guest_timezone_info}
         
         The rules for a successful schedule are currently:
         {rules}
@@ -77,9 +106,10 @@ pub fn get_schedule_prompt(our: &Address, timezone: &Option<String>, rules: &str
         Instructions:
         
         Parse the user's input to understand their intent and extract relevant information.
-        If the user provides any time-related information, assume it is in their local time zone "{timezone}".
-        Convert all time-related information from the user's local time zone to UTC.
+        If the user provides any time-related information, assume it is in the user's own time zone, unless they name another time zone explicitly.
+        Convert all time-related information from that time zone to UTC.
         Format all times in the YYYY-MM-DDTHH:MM:SSZ format.
+        When you mention a time in your reply, give it in the user's time zone followed by {our_node}'s time zone in parentheses.
         
         
         Respond in the following format without any additional text or explanations:
@@ -93,20 +123,23 @@ pub fn get_schedule_prompt(our: &Address, timezone: &Option<String>, rules: &str
         REJECTED_REQUEST,[Reason for rejection],ENDMARKER
         For any other query, provide a helpful and relevant response, including the user's time zone if applicable.
         
-        Assuming the current date is Wednesday, May 22, 2024, and the user's timezone is "America/Los_Angeles", here are some examples:
+        Assuming the current date is Wednesday, May 22, 2024, the user's timezone is "America/Los_Angeles" and {our_node}'s time zone is "Europe/Berlin", here are some examples:
         Input: I'd like to schedule a meeting, on June 5, 2024, at 2:00 PM EST for 60 minutes. My name is John Doe.
         Output:
         SCHEDULE_REQUEST,2024-06-05T18:00:00Z,2024-06-05T19:00:00Z,Meeting with John Doe,meet John Doe,ENDMARKER
-        Your meeting with {our_node} has been scheduled for June 5, 2024, at 2:00 PM EST (11:00 AM PST).
+        Your meeting with {our_node} has been scheduled for June 5, 2024, at 11:00 AM PDT (8:00 PM CEST).
         Input: I want to meet with {our_node} next week.
         Output:
         INCOMPLETE_REQUEST,Please provide the following missing information: event title, proposed date and time, and your name.,ENDMARKER
         Input: What's my local time?
         Output: Your local time zone is America/Los_Angeles.
+        {date_hint}
         User input:
         "#,
         utc_time = formatted_utc_time,
+        date_hint = date_hint,
         timezone = tz,
+        guest_timezone_info = guest_timezone_info,
         our_node = our_node,
         rules = rules
     )
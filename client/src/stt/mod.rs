@@ -1,6 +1,14 @@
-use crate::STT_ADDRESS;
-use kinode_process_lib::Request;
+//! Speech to text for voice messages. Audio goes either to OpenAI's transcription
+//! endpoint or to a local whisper.cpp server, which keeps it on the machine.
+use crate::{backend::Http, multipart::Form, STT_ADDRESS};
+use kinode_process_lib::{http, Request};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, str::FromStr};
 use stt_interface::STTRequest;
+use url::Url;
+
+const OPENAI_URL: &str = "https://api.openai.com/v1/audio/transcriptions";
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
 
 pub fn init_stt(key: &str) -> anyhow::Result<()> {
     let req = serde_json::to_vec(&STTRequest::RegisterApiKey(key.to_string()))?;
@@ -11,3 +19,90 @@ pub fn init_stt(key: &str) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SttProvider {
+    Openai,
+    // whisper.cpp's `server`, started with `--convert` so it takes telegram's ogg
+    Whisper { url: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SttSettings {
+    pub provider: SttProvider,
+    pub timeout_secs: u64,
+    // say what was heard before acting on it
+    pub echo: bool,
+}
+
+impl Default for SttSettings {
+    fn default() -> Self {
+        SttSettings {
+            provider: SttProvider::Openai,
+            timeout_secs: DEFAULT_TIMEOUT_SECS,
+            echo: true,
+        }
+    }
+}
+
+/// What a transcription needs besides the audio.
+pub struct SttOptions<'a> {
+    pub settings: &'a SttSettings,
+    pub openai_key: Option<&'a str>,
+    // ISO-639-1, None to let the model detect it
+    pub language: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct Transcription {
+    text: String,
+}
+
+/// Transcribes `audio`, an ogg voice message.
+pub fn transcribe(
+    http: &impl Http,
+    audio: Vec<u8>,
+    options: &SttOptions,
+) -> anyhow::Result<String> {
+    let mut headers = HashMap::new();
+    let (url, mut form) = match &options.settings.provider {
+        SttProvider::Openai => {
+            let Some(key) = options.openai_key else {
+                return Err(anyhow::anyhow!(
+                    "transcribing with openai needs an openai key"
+                ));
+            };
+            headers.insert("Authorization".to_string(), format!("Bearer {}", key));
+            let form = Form::new().text("model", "whisper-1");
+            (Url::from_str(OPENAI_URL)?, form)
+        }
+        SttProvider::Whisper { url } => {
+            let url = Url::from_str(&format!("{}/inference", url.trim_end_matches('/')))?;
+            let form = Form::new().text("temperature", "0");
+            (url, form)
+        }
+    };
+    form = form.text("response_format", "json");
+    if let Some(language) = options.language {
+        form = form.text("language", language);
+    }
+    let (content_type, body) = form.file("file", "voice.ogg", "audio/ogg", audio).finish();
+    headers.insert("Content-Type".to_string(), content_type);
+
+    let res = http.request(
+        http::Method::POST,
+        url,
+        headers,
+        options.settings.timeout_secs,
+        body,
+    )?;
+    if !res.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "transcription failed: {}",
+            String::from_utf8_lossy(res.body())
+        ));
+    }
+    let transcription: Transcription = serde_json::from_slice(res.body())?;
+    Ok(transcription.text.trim().to_string())
+}
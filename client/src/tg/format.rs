@@ -0,0 +1,373 @@
+//! Telegram formatting and its limits. Formatted replies use the HTML parse mode,
+//! which only needs `<`, `>` and `&` escaped where MarkdownV2 reserves most
+//! punctuation, so event titles and links can go in with little care. The
+//! MarkdownV2 escapes are in `markdown`, for text headed for that parse mode.
+
+// telegram's limit, in UTF-16 code units
+pub const MAX_MESSAGE_LEN: usize = 4096;
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn bold(text: &str) -> String {
+    format!("<b>{}</b>", escape_html(text))
+}
+
+pub fn link(label: &str, url: &str) -> String {
+    format!(
+        "<a href=\"{}\">{}</a>",
+        escape_html(url),
+        escape_html(label)
+    )
+}
+
+/// Escaping for the MarkdownV2 parse mode, which has different rules for plain
+/// text, code and link urls.
+#[allow(unused)]
+pub mod markdown {
+    // reserved anywhere in plain text
+    const RESERVED: &str = "_*[]()~`>#+-=|{}.!\\";
+
+    fn escape_chars(text: &str, reserved: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            if reserved.contains(c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    }
+
+    pub fn escape(text: &str) -> String {
+        escape_chars(text, RESERVED)
+    }
+
+    /// For inside `` `code` `` and ```` ```pre``` ```` blocks.
+    pub fn escape_code(text: &str) -> String {
+        escape_chars(text, "`\\")
+    }
+
+    /// For the url inside `(…)` of an inline link.
+    pub fn escape_url(url: &str) -> String {
+        escape_chars(url, ")\\")
+    }
+
+    pub fn bold(text: &str) -> String {
+        format!("*{}*", escape(text))
+    }
+
+    pub fn link(label: &str, url: &str) -> String {
+        format!("[{}]({})", escape(label), escape_url(url))
+    }
+}
+
+/// What an HTML reply reads as without the markup, for logs and for sending it
+/// plain when telegram rejects the markup. Links keep their url after the label.
+pub fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut url = None;
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            rest = &rest[start..];
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        if let Some(href) = tag
+            .strip_prefix("a href=\"")
+            .and_then(|href| href.strip_suffix('"'))
+        {
+            url = Some(href);
+        } else if tag == "/a" {
+            if let Some(url) = url.take() {
+                text.push_str(&format!(" ({})", url));
+            }
+        }
+        rest = &rest[start + end + 1..];
+    }
+    text.push_str(rest);
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
+fn len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// Splits plain `text` into messages of at most `max` UTF-16 units, between lines
+/// where it can, else between words, else anywhere.
+pub fn split(text: &str, max: usize) -> Vec<String> {
+    split_atoms(text, max, false)
+}
+
+/// `split` for HTML. Tags and entities are never cut, and formatting still open
+/// at a cut is closed at the end of one message and opened again in the next.
+pub fn split_html(html: &str, max: usize) -> Vec<String> {
+    split_atoms(html, max, true)
+}
+
+fn split_atoms(text: &str, max: usize, html: bool) -> Vec<String> {
+    if len(text) <= max {
+        return vec![text.to_string()];
+    }
+    let atoms = atoms(text, html);
+    let mut chunks = Chunks {
+        max,
+        done: vec![],
+        chunk: String::new(),
+        len: 0,
+        has_text: false,
+        open: vec![],
+    };
+    for line in atoms.split_inclusive(|atom| *atom == "\n") {
+        if chunks.fits(line) {
+            chunks.push(line);
+            continue;
+        }
+        for word in line.split_inclusive(|atom| *atom == " ") {
+            if chunks.fits(word) {
+                chunks.push(word);
+                continue;
+            }
+            for atom in word {
+                chunks.push(std::slice::from_ref(atom));
+            }
+        }
+    }
+    chunks.finish()
+}
+
+/// `text` in the pieces a split can't go through: characters and, in HTML, whole
+/// tags and entities.
+fn atoms(text: &str, html: bool) -> Vec<&str> {
+    let mut atoms = vec![];
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let end = match c {
+            '<' if html => rest.find('>').map(|end| end + 1),
+            '&' if html => rest
+                .char_indices()
+                .skip(1)
+                .take(10)
+                .find(|(_, c)| !(c.is_ascii_alphanumeric() || *c == '#'))
+                .filter(|(i, c)| *c == ';' && *i > 1)
+                .map(|(i, _)| i + 1),
+            _ => None,
+        }
+        .unwrap_or(c.len_utf8());
+        atoms.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    atoms
+}
+
+/// The name of a tag atom, and whether it closes, e.g. `("a", false)` for `<a href="…">`.
+fn tag(atom: &str) -> Option<(&str, bool)> {
+    let inner = atom.strip_prefix('<')?.strip_suffix('>')?;
+    let (inner, closing) = match inner.strip_prefix('/') {
+        Some(inner) => (inner, true),
+        None => (inner, false),
+    };
+    let name = inner.split_whitespace().next()?;
+    Some((name, closing))
+}
+
+fn closing_tag(open: &str) -> String {
+    format!("</{}>", tag(open).map(|(name, _)| name).unwrap_or_default())
+}
+
+struct Chunks<'a> {
+    max: usize,
+    done: Vec<String>,
+    chunk: String,
+    // of `chunk`, in UTF-16 units
+    len: usize,
+    // whether `chunk` has anything but markup and whitespace
+    has_text: bool,
+    // opening tags of the formatting `chunk` leaves open, outermost first
+    open: Vec<&'a str>,
+}
+
+impl<'a> Chunks<'a> {
+    /// Whether `piece` fits in a message on its own, with the formatting around it.
+    fn fits(&self, piece: &[&'a str]) -> bool {
+        let reopen: usize = self.open.iter().map(|tag| len(tag)).sum();
+        reopen + piece.iter().map(|atom| len(atom)).sum::<usize>() + closing_len(&self.after(piece))
+            <= self.max
+    }
+
+    /// The tags left open once `piece` is added.
+    fn after(&self, piece: &[&'a str]) -> Vec<&'a str> {
+        let mut open = self.open.clone();
+        for atom in piece {
+            match tag(atom) {
+                Some((name, true)) => {
+                    if let Some(i) = open
+                        .iter()
+                        .rposition(|open| tag(open).map(|t| t.0) == Some(name))
+                    {
+                        open.remove(i);
+                    }
+                }
+                Some((_, false)) => open.push(atom),
+                None => {}
+            }
+        }
+        open
+    }
+
+    /// Adds `piece` to the current chunk, starting a new one if it doesn't fit.
+    fn push(&mut self, piece: &[&'a str]) {
+        let piece_len: usize = piece.iter().map(|atom| len(atom)).sum();
+        let after = self.after(piece);
+        if self.len + piece_len + closing_len(&after) > self.max && self.has_text {
+            self.cut();
+            for tag in &self.open {
+                self.chunk.push_str(tag);
+                self.len += len(tag);
+            }
+        }
+        for atom in piece {
+            self.chunk.push_str(atom);
+            self.has_text |= tag(atom).is_none() && !atom.trim().is_empty();
+        }
+        self.len += piece_len;
+        self.open = after;
+    }
+
+    /// Ends the current chunk, closing what it left open.
+    fn cut(&mut self) {
+        let mut chunk = std::mem::take(&mut self.chunk).trim_end().to_string();
+        for tag in self.open.iter().rev() {
+            chunk.push_str(&closing_tag(tag));
+        }
+        if self.has_text {
+            self.done.push(chunk);
+        }
+        self.len = 0;
+        self.has_text = false;
+    }
+
+    fn finish(mut self) -> Vec<String> {
+        self.cut();
+        self.done
+    }
+}
+
+fn closing_len(open: &[&str]) -> usize {
+    open.iter().map(|tag| len(&closing_tag(tag))).sum()
+}
+
+/// `text` cut to fit one message, for edits which can't be split.
+pub fn truncate(text: &str, max: usize) -> String {
+    if len(text) <= max {
+        return text.to_string();
+    }
+    let mut cut = String::new();
+    let mut cut_len = 1; // the ellipsis
+    for c in text.chars() {
+        cut_len += c.len_utf16();
+        if cut_len > max {
+            break;
+        }
+        cut.push(c);
+    }
+    cut.push('…');
+    cut
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_prefers_lines_then_words() {
+        assert_eq!(split("short", 10), vec!["short"]);
+        assert_eq!(
+            split("one two\nthree four", 10),
+            vec!["one two", "three four"]
+        );
+        assert_eq!(split("one two three", 8), vec!["one two", "three"]);
+        assert_eq!(split("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+        // plain text has no markup to keep whole
+        assert_eq!(split("a<b>&amp;", 3), vec!["a<b", ">&a", "mp;"]);
+    }
+
+    #[test]
+    fn html_tags_and_entities_stay_whole() {
+        let html = "a&amp;b&amp;c <a href=\"https://example.com\">x</a>";
+        for max in 5..html.len() {
+            let chunks = split_html(html, max);
+            for chunk in &chunks {
+                assert_eq!(
+                    atoms(chunk, true).concat(),
+                    *chunk,
+                    "{:?} at {}",
+                    chunk,
+                    max
+                );
+                assert!(
+                    !chunk.contains("&am;") && !chunk.ends_with('&'),
+                    "{:?}",
+                    chunk
+                );
+                assert_eq!(
+                    chunk.matches('<').count(),
+                    chunk.matches('>').count(),
+                    "{:?}",
+                    chunk
+                );
+            }
+            let text: String = chunks.iter().map(|chunk| strip_html(chunk)).collect();
+            assert_eq!(text.replace(' ', ""), "a&b&cx(https://example.com)");
+        }
+    }
+
+    #[test]
+    fn html_formatting_is_reopened_after_a_cut() {
+        let html = "<b>one two three four</b> five";
+        let chunks = split_html(html, 16);
+        assert_eq!(
+            chunks,
+            vec!["<b>one two</b>", "<b>three</b>", "<b>four</b> five"]
+        );
+        for chunk in &chunks {
+            assert!(len(chunk) <= 16);
+        }
+
+        let html = format!("<i>{}</i>", "word ".repeat(2000));
+        for chunk in split_html(&html, MAX_MESSAGE_LEN) {
+            assert!(chunk.starts_with("<i>") && chunk.ends_with("</i>"));
+            assert!(len(&chunk) <= MAX_MESSAGE_LEN);
+        }
+    }
+
+    #[test]
+    fn markdown_escapes() {
+        assert_eq!(
+            markdown::escape("1:1 (sync) - v2.0!"),
+            "1:1 \\(sync\\) \\- v2\\.0\\!"
+        );
+        assert_eq!(markdown::escape_code("a `b` \\ c."), "a \\`b\\` \\\\ c.");
+        assert_eq!(
+            markdown::link("Meet [team]", "https://x.com/a_(b)"),
+            "[Meet \\[team\\]](https://x.com/a_(b\\))"
+        );
+        assert_eq!(markdown::bold("*hi*"), "*\\*hi\\**");
+    }
+}
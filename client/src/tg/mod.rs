@@ -1,10 +1,21 @@
-use crate::{STT_ADDRESS, TG_ADDRESS};
+use crate::{multipart::Form, TG_ADDRESS};
 use frankenstein::GetFileParams;
-use frankenstein::{ChatId, Message as TgMessage, SendMessageParams, UpdateContent};
-use kinode_process_lib::{get_blob, Message, Request};
-use stt_interface::STTRequest;
-use stt_interface::STTResponse;
+use frankenstein::{
+    AnswerCallbackQueryParams, BotCommand, ChatId, EditMessageTextParams, InlineKeyboardMarkup,
+    Message as TgMessage, ParseMode, ReplyMarkup, SendMessageParams, SetMyCommandsParams, Update,
+};
+use kinode_process_lib::{get_blob, http, println, Message, Request};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{collections::HashMap, sync::Mutex};
 use telegram_interface::*;
+use url::Url;
+
+pub mod format;
+
+// for the bot api calls the tg process doesn't offer
+static BOT_TOKEN: Mutex<Option<String>> = Mutex::new(None);
+// the bot's @username, asked for once per token
+static BOT_USERNAME: Mutex<Option<String>> = Mutex::new(None);
 
 pub fn init_tg(key: &str) -> anyhow::Result<()> {
     let init_req = TgInitialize {
@@ -13,6 +24,7 @@ pub fn init_tg(key: &str) -> anyhow::Result<()> {
     };
 
     let req = serde_json::to_vec(&TgRequest::RegisterApiKey(init_req))?;
+    set_bot_token(key);
 
     let response = Request::to(TG_ADDRESS)
         .body(req)
@@ -24,10 +36,72 @@ pub fn init_tg(key: &str) -> anyhow::Result<()> {
 }
 
 pub fn send_bot_message(text: &str, id: i64) -> anyhow::Result<TgMessage> {
-    let params = SendMessageParams::builder()
+    send_chunks(text, id, None, None)
+}
+
+pub fn send_bot_keyboard(
+    text: &str,
+    id: i64,
+    keyboard: InlineKeyboardMarkup,
+) -> anyhow::Result<TgMessage> {
+    send_chunks(text, id, None, Some(keyboard))
+}
+
+/// Sends `html` with the HTML parse mode. If telegram rejects the markup of a
+/// message, that one goes out plain instead of not at all.
+pub fn send_bot_html(
+    html: &str,
+    id: i64,
+    keyboard: Option<InlineKeyboardMarkup>,
+) -> anyhow::Result<TgMessage> {
+    let chunks = format::split_html(html, format::MAX_MESSAGE_LEN);
+    each_chunk(chunks, keyboard, |chunk, keyboard| {
+        send(chunk.clone(), id, Some(ParseMode::Html), keyboard.clone()).or_else(|e| {
+            println!("sending html failed, sending plain: {:?}", e);
+            send(format::strip_html(&chunk), id, None, keyboard)
+        })
+    })
+}
+
+/// Sends `text` as one message or, past telegram's limit, several, with the
+/// keyboard under the last. Returns the last one.
+fn send_chunks(
+    text: &str,
+    id: i64,
+    parse_mode: Option<ParseMode>,
+    keyboard: Option<InlineKeyboardMarkup>,
+) -> anyhow::Result<TgMessage> {
+    let chunks = format::split(text, format::MAX_MESSAGE_LEN);
+    each_chunk(chunks, keyboard, |chunk, keyboard| {
+        send(chunk, id, parse_mode, keyboard)
+    })
+}
+
+/// Sends `chunks` in order, the keyboard with the last. Returns the last one.
+fn each_chunk(
+    mut chunks: Vec<String>,
+    keyboard: Option<InlineKeyboardMarkup>,
+    send: impl Fn(String, Option<InlineKeyboardMarkup>) -> anyhow::Result<TgMessage>,
+) -> anyhow::Result<TgMessage> {
+    let last = chunks.pop().unwrap_or_default();
+    for chunk in chunks {
+        send(chunk, None)?;
+    }
+    send(last, keyboard)
+}
+
+fn send(
+    text: String,
+    id: i64,
+    parse_mode: Option<ParseMode>,
+    keyboard: Option<InlineKeyboardMarkup>,
+) -> anyhow::Result<TgMessage> {
+    let mut params = SendMessageParams::builder()
         .chat_id(ChatId::Integer(id))
         .text(text)
         .build();
+    params.parse_mode = parse_mode;
+    params.reply_markup = keyboard.map(ReplyMarkup::InlineKeyboardMarkup);
     let send_message_request = serde_json::to_vec(&TgRequest::SendMessage(params))?;
     let response = Request::to(TG_ADDRESS)
         .body(send_message_request)
@@ -38,15 +112,111 @@ pub fn send_bot_message(text: &str, id: i64) -> anyhow::Result<TgMessage> {
     Ok(message)
 }
 
-pub fn get_text(audio: Vec<u8>) -> anyhow::Result<String> {
-    let stt_request = serde_json::to_vec(&STTRequest::OpenaiTranscribe(audio))?;
-    let response = Request::to(STT_ADDRESS)
-        .body(stt_request)
-        .send_and_await_response(3)??;
-    let STTResponse::OpenaiTranscribed(text) = serde_json::from_slice(response.body())? else {
-        return Err(anyhow::anyhow!("Failed to parse STT response"));
+/// Where the tg process has no request for it, the bot api is called directly.
+/// The token is the one given to `init_tg`, set again on restart.
+pub fn set_bot_token(key: &str) {
+    *BOT_TOKEN.lock().unwrap() = Some(key.to_string());
+    *BOT_USERNAME.lock().unwrap() = None;
+}
+
+#[derive(Deserialize)]
+struct BotApiResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+fn bot_api<P: Serialize, T: DeserializeOwned>(method: &str, params: &P) -> anyhow::Result<T> {
+    bot_api_raw(method, "application/json", serde_json::to_vec(params)?)
+}
+
+fn bot_api_raw<T: DeserializeOwned>(
+    method: &str,
+    content_type: &str,
+    body: Vec<u8>,
+) -> anyhow::Result<T> {
+    let Some(token) = BOT_TOKEN.lock().unwrap().clone() else {
+        return Err(anyhow::anyhow!("telegram bot token not set"));
+    };
+    let url = Url::parse(&format!("https://api.telegram.org/bot{}/{}", token, method))?;
+    let headers = HashMap::from([("Content-Type".to_string(), content_type.to_string())]);
+    let res = http::send_request_await_response(http::Method::POST, url, Some(headers), 30, body)?;
+    let response: BotApiResponse<T> = serde_json::from_slice(res.body())?;
+    match response.result {
+        Some(result) if response.ok => Ok(result),
+        _ => Err(anyhow::anyhow!(
+            "telegram {} failed: {}",
+            method,
+            response.description.unwrap_or_default()
+        )),
+    }
+}
+
+/// Sends `audio`, Ogg Opus, as a voice note. Files can't go in a JSON body, so
+/// this one is multipart.
+pub fn send_bot_voice(id: i64, audio: Vec<u8>, caption: Option<&str>) -> anyhow::Result<TgMessage> {
+    let mut form = Form::new().text("chat_id", &id.to_string());
+    if let Some(caption) = caption {
+        form = form.text("caption", caption);
+    }
+    let (content_type, body) = form.file("voice", "reply.ogg", "audio/ogg", audio).finish();
+    bot_api_raw("sendVoice", &content_type, body)
+}
+
+/// Replaces the text of a message the bot sent, dropping its buttons.
+pub fn edit_bot_message(text: &str, id: i64, message_id: i32) -> anyhow::Result<()> {
+    let params = EditMessageTextParams::builder()
+        .chat_id(ChatId::Integer(id))
+        .message_id(message_id)
+        .text(format::truncate(text, format::MAX_MESSAGE_LEN))
+        .build();
+    // a Message, or `true` for inline messages
+    let _: serde_json::Value = bot_api("editMessageText", &params)?;
+    Ok(())
+}
+
+/// Stops the button's loading spinner, `text` shows as a short notification.
+pub fn answer_callback_query(query_id: &str, text: Option<&str>) -> anyhow::Result<()> {
+    let params = match text {
+        Some(text) => AnswerCallbackQueryParams::builder()
+            .callback_query_id(query_id)
+            .text(text)
+            .build(),
+        None => AnswerCallbackQueryParams::builder()
+            .callback_query_id(query_id)
+            .build(),
     };
-    Ok(text)
+    let _: bool = bot_api("answerCallbackQuery", &params)?;
+    Ok(())
+}
+
+/// The bot's @username without the @, which group messages mention it by.
+pub fn bot_username() -> anyhow::Result<String> {
+    if let Some(username) = BOT_USERNAME.lock().unwrap().clone() {
+        return Ok(username);
+    }
+    let me: frankenstein::User = bot_api("getMe", &serde_json::json!({}))?;
+    let Some(username) = me.username else {
+        return Err(anyhow::anyhow!("bot has no username"));
+    };
+    *BOT_USERNAME.lock().unwrap() = Some(username.clone());
+    Ok(username)
+}
+
+/// Registers the bot's commands so Telegram clients list them in the menu.
+pub fn set_my_commands(commands: &[(&str, &str)]) -> anyhow::Result<()> {
+    let commands = commands
+        .iter()
+        .map(|(command, description)| {
+            BotCommand::builder()
+                .command(*command)
+                .description(*description)
+                .build()
+        })
+        .collect();
+    let params = SetMyCommandsParams::builder().commands(commands).build();
+    let _: bool = bot_api("setMyCommands", &params)?;
+    Ok(())
 }
 
 pub fn get_file(file_id: &str) -> anyhow::Result<Vec<u8>> {
@@ -60,18 +230,24 @@ pub fn get_file(file_id: &str) -> anyhow::Result<Vec<u8>> {
     }
     Err(anyhow::anyhow!("Failed to get file"))
 }
-pub fn get_last_tg_msg(message: &Message) -> Option<TgMessage> {
-    let Ok(TgResponse::Update(tg_update)) = serde_json::from_slice(message.body()) else {
-        return None;
-    };
-    let update = tg_update.updates.last()?;
-    let msg = match &update.content {
-        UpdateContent::Message(msg) | UpdateContent::ChannelPost(msg) => msg,
-        _ => {
-            return None;
+/// First name, with the @username when there is one.
+pub fn display_name(user: &frankenstein::User) -> String {
+    match &user.username {
+        Some(username) => format!("{} (@{})", user.first_name, username),
+        None => user.first_name.clone(),
+    }
+}
+
+/// The updates in a message from the tg process, oldest first.
+pub fn get_tg_updates(message: &Message) -> anyhow::Result<Vec<Update>> {
+    match serde_json::from_slice(message.body())? {
+        TgResponse::Update(tg_update) => {
+            let mut updates = tg_update.updates;
+            updates.sort_by_key(|update| update.update_id);
+            Ok(updates)
         }
-    };
-    Some(msg.clone())
+        _ => Ok(vec![]),
+    }
 }
 
 pub fn subscribe() -> anyhow::Result<()> {
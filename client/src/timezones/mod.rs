@@ -0,0 +1,326 @@
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use regex::Regex;
+
+/// What we know about a guest's time zone, and how sure we are of it.
+#[derive(Debug, Clone, Copy)]
+pub enum GuestTimezone {
+    /// Stated by the guest, either in this message or a previous one.
+    Known(Tz),
+    /// Guessed from the guest's Telegram language_code, should be confirmed.
+    Inferred(Tz),
+    Unknown,
+}
+
+/// A time zone named in a message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mention {
+    /// The guest said that's where they are, "I'm in Tokyo" or "Asia/Tokyo".
+    Stated(Tz),
+    /// Only qualifies this message, "3pm EST" or "a call in Tokyo".
+    Named(Tz),
+}
+
+impl Mention {
+    pub fn tz(&self) -> Tz {
+        match self {
+            Self::Stated(tz) | Self::Named(tz) => *tz,
+        }
+    }
+}
+
+impl GuestTimezone {
+    pub fn tz(&self) -> Option<Tz> {
+        match self {
+            Self::Known(tz) | Self::Inferred(tz) => Some(*tz),
+            Self::Unknown => None,
+        }
+    }
+}
+
+// cities people are likely to mention, lowercase.
+const CITIES: &[(&str, &str)] = &[
+    ("new york", "America/New_York"),
+    ("nyc", "America/New_York"),
+    ("boston", "America/New_York"),
+    ("miami", "America/New_York"),
+    ("toronto", "America/Toronto"),
+    ("chicago", "America/Chicago"),
+    ("austin", "America/Chicago"),
+    ("denver", "America/Denver"),
+    ("los angeles", "America/Los_Angeles"),
+    ("san francisco", "America/Los_Angeles"),
+    ("seattle", "America/Los_Angeles"),
+    ("vancouver", "America/Vancouver"),
+    ("mexico city", "America/Mexico_City"),
+    ("sao paulo", "America/Sao_Paulo"),
+    ("são paulo", "America/Sao_Paulo"),
+    ("buenos aires", "America/Argentina/Buenos_Aires"),
+    ("london", "Europe/London"),
+    ("dublin", "Europe/Dublin"),
+    ("lisbon", "Europe/Lisbon"),
+    ("paris", "Europe/Paris"),
+    ("berlin", "Europe/Berlin"),
+    ("amsterdam", "Europe/Amsterdam"),
+    ("madrid", "Europe/Madrid"),
+    ("barcelona", "Europe/Madrid"),
+    ("rome", "Europe/Rome"),
+    ("zurich", "Europe/Zurich"),
+    ("vienna", "Europe/Vienna"),
+    ("prague", "Europe/Prague"),
+    ("warsaw", "Europe/Warsaw"),
+    ("stockholm", "Europe/Stockholm"),
+    ("helsinki", "Europe/Helsinki"),
+    ("athens", "Europe/Athens"),
+    ("kyiv", "Europe/Kyiv"),
+    ("kiev", "Europe/Kyiv"),
+    ("istanbul", "Europe/Istanbul"),
+    ("moscow", "Europe/Moscow"),
+    ("dubai", "Asia/Dubai"),
+    ("tel aviv", "Asia/Jerusalem"),
+    ("mumbai", "Asia/Kolkata"),
+    ("delhi", "Asia/Kolkata"),
+    ("bangalore", "Asia/Kolkata"),
+    ("bangkok", "Asia/Bangkok"),
+    ("singapore", "Asia/Singapore"),
+    ("hong kong", "Asia/Hong_Kong"),
+    ("shanghai", "Asia/Shanghai"),
+    ("beijing", "Asia/Shanghai"),
+    ("taipei", "Asia/Taipei"),
+    ("seoul", "Asia/Seoul"),
+    ("tokyo", "Asia/Tokyo"),
+    ("sydney", "Australia/Sydney"),
+    ("melbourne", "Australia/Melbourne"),
+    ("auckland", "Pacific/Auckland"),
+    ("lagos", "Africa/Lagos"),
+    ("nairobi", "Africa/Nairobi"),
+    ("cape town", "Africa/Johannesburg"),
+    ("johannesburg", "Africa/Johannesburg"),
+];
+
+// abbreviations that mostly mean one zone, matched in capitals only.
+const ABBREVIATIONS: &[(&str, &str)] = &[
+    ("EST", "America/New_York"),
+    ("EDT", "America/New_York"),
+    ("CST", "America/Chicago"),
+    ("CDT", "America/Chicago"),
+    ("MST", "America/Denver"),
+    ("MDT", "America/Denver"),
+    ("PST", "America/Los_Angeles"),
+    ("PDT", "America/Los_Angeles"),
+    ("BST", "Europe/London"),
+    ("CET", "Europe/Berlin"),
+    ("CEST", "Europe/Berlin"),
+    ("EET", "Europe/Athens"),
+    ("EEST", "Europe/Athens"),
+    ("JST", "Asia/Tokyo"),
+    ("KST", "Asia/Seoul"),
+    ("AEST", "Australia/Sydney"),
+    ("AEDT", "Australia/Sydney"),
+];
+
+// what comes before "in <city>" when the guest says where they are.
+const STATED_PREFIXES: &str = r"(?:i'm|im|i am|we're|we are|based|living|live|staying)";
+
+// region subtags are checked before languages, "pt-br" is a better hint than "pt".
+const REGIONS: &[(&str, &str)] = &[
+    ("gb", "Europe/London"),
+    ("ie", "Europe/Dublin"),
+    ("br", "America/Sao_Paulo"),
+    ("pt", "Europe/Lisbon"),
+    ("mx", "America/Mexico_City"),
+    ("ar", "America/Argentina/Buenos_Aires"),
+    ("in", "Asia/Kolkata"),
+    ("nz", "Pacific/Auckland"),
+    ("tw", "Asia/Taipei"),
+    ("hk", "Asia/Hong_Kong"),
+    ("sg", "Asia/Singapore"),
+];
+
+// only languages spoken mostly within a single time zone.
+const LANGUAGES: &[(&str, &str)] = &[
+    ("de", "Europe/Berlin"),
+    ("fr", "Europe/Paris"),
+    ("it", "Europe/Rome"),
+    ("nl", "Europe/Amsterdam"),
+    ("pl", "Europe/Warsaw"),
+    ("cs", "Europe/Prague"),
+    ("hu", "Europe/Budapest"),
+    ("ro", "Europe/Bucharest"),
+    ("el", "Europe/Athens"),
+    ("sv", "Europe/Stockholm"),
+    ("fi", "Europe/Helsinki"),
+    ("da", "Europe/Copenhagen"),
+    ("nb", "Europe/Oslo"),
+    ("uk", "Europe/Kyiv"),
+    ("tr", "Europe/Istanbul"),
+    ("he", "Asia/Jerusalem"),
+    ("fa", "Asia/Tehran"),
+    ("th", "Asia/Bangkok"),
+    ("vi", "Asia/Ho_Chi_Minh"),
+    ("id", "Asia/Jakarta"),
+    ("ko", "Asia/Seoul"),
+    ("ja", "Asia/Tokyo"),
+];
+
+pub fn parse_tz(timezone: &Option<String>) -> Tz {
+    timezone
+        .as_deref()
+        .unwrap_or("UTC")
+        .parse()
+        .unwrap_or(Tz::UTC)
+}
+
+/// Best effort guess from a Telegram `language_code` (IETF tag, e.g. "de" or "pt-br").
+pub fn from_language_code(code: &str) -> Option<Tz> {
+    let code = code.to_lowercase().replace('_', "-");
+    let mut parts = code.split('-');
+    let language = parts.next()?;
+
+    if let Some(region) = parts.next() {
+        if let Some((_, tz)) = REGIONS.iter().find(|(r, _)| *r == region) {
+            return tz.parse().ok();
+        }
+    }
+    LANGUAGES
+        .iter()
+        .find(|(l, _)| *l == language)
+        .and_then(|(_, tz)| tz.parse().ok())
+}
+
+/// Looks for a time zone the text names explicitly: an IANA name ("Europe/Berlin"),
+/// an abbreviation ("EST"), or a city in "in <city>" or "<city> time". A bare city
+/// isn't enough, "Sydney" and "Paris" are names too.
+pub fn find_in_text(text: &str) -> Option<Mention> {
+    let iana = Regex::new(r"\b[A-Z][a-z]+(?:/[A-Z][A-Za-z_]+)+\b").unwrap();
+    for candidate in iana.find_iter(text) {
+        if let Ok(tz) = candidate.as_str().parse::<Tz>() {
+            return Some(Mention::Stated(tz));
+        }
+    }
+
+    let lower = text.to_lowercase().replace('\u{2019}', "'");
+    for (city, tz) in CITIES {
+        let Ok(tz) = tz.parse::<Tz>() else {
+            continue;
+        };
+        let city = regex::escape(city);
+        let stated = Regex::new(&format!(r"\b{} in {}\b", STATED_PREFIXES, city));
+        if stated.is_ok_and(|re| re.is_match(&lower)) {
+            return Some(Mention::Stated(tz));
+        }
+        let named = Regex::new(&format!(r"\bin {city}\b|\b{city} time\b"));
+        if named.is_ok_and(|re| re.is_match(&lower)) {
+            return Some(Mention::Named(tz));
+        }
+    }
+
+    ABBREVIATIONS
+        .iter()
+        .find(|(abbreviation, _)| {
+            Regex::new(&format!(r"\b{}\b", abbreviation)).is_ok_and(|re| re.is_match(text))
+        })
+        .and_then(|(_, tz)| tz.parse().ok())
+        .map(Mention::Named)
+}
+
+/// A city or abbreviation on its own, as given to /timezone.
+pub fn by_name(name: &str) -> Option<Tz> {
+    let name = name.trim();
+    CITIES
+        .iter()
+        .find(|(city, _)| city.eq_ignore_ascii_case(name))
+        .or_else(|| {
+            ABBREVIATIONS
+                .iter()
+                .find(|(abbreviation, _)| abbreviation.eq_ignore_ascii_case(name))
+        })
+        .and_then(|(_, tz)| tz.parse().ok())
+}
+
+/// Formats a `YYYY-MM-DDTHH:MM:SSZ` time in the given zone, e.g. "Tue 04 Jun 15:00 CEST".
+pub fn format_local(utc_time: &str, tz: Tz) -> Option<String> {
+    let time = DateTime::parse_from_rfc3339(utc_time).ok()?;
+    Some(
+        time.with_timezone(&Utc)
+            .with_timezone(&tz)
+            .format("%a %d %b %H:%M %Z")
+            .to_string(),
+    )
+}
+
+/// One line per zone, guest first, used in booking confirmations. Without the
+/// guest's zone only the host's time is shown.
+pub fn both_local_times(
+    start: &str,
+    end: &str,
+    guest_tz: Option<Tz>,
+    owner_tz: Tz,
+) -> Option<String> {
+    let host = format!(
+        "Host time: {} - {}",
+        format_local(start, owner_tz)?,
+        format_local(end, owner_tz)?
+    );
+    let Some(guest_tz) = guest_tz else {
+        return Some(host);
+    };
+    let guest = format!(
+        "Your time: {} - {}",
+        format_local(start, guest_tz)?,
+        format_local(end, guest_tz)?
+    );
+    if guest_tz == owner_tz {
+        return Some(guest);
+    }
+    Some(format!("{}\n{}", guest, host))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_not_places() {
+        assert_eq!(find_in_text("Hi, I'm Sydney"), None);
+        assert_eq!(find_in_text("Paris and Austin would like to meet"), None);
+    }
+
+    #[test]
+    fn explicit_mentions() {
+        let tokyo = Tz::Asia__Tokyo;
+        assert_eq!(
+            find_in_text("I'm in Tokyo this month"),
+            Some(Mention::Stated(tokyo))
+        );
+        assert_eq!(
+            find_in_text("we are based in tokyo"),
+            Some(Mention::Stated(tokyo))
+        );
+        assert_eq!(
+            find_in_text("my zone is Asia/Tokyo"),
+            Some(Mention::Stated(tokyo))
+        );
+        assert_eq!(find_in_text("3pm tokyo time"), Some(Mention::Named(tokyo)));
+        assert_eq!(find_in_text("a call in Tokyo"), Some(Mention::Named(tokyo)));
+        assert_eq!(
+            find_in_text("Tuesday 3pm EST"),
+            Some(Mention::Named(Tz::America__New_York))
+        );
+        assert_eq!(find_in_text("the best time"), None);
+    }
+
+    #[test]
+    fn host_time_only_without_guest_zone() {
+        let times = both_local_times(
+            "2024-06-04T13:00:00Z",
+            "2024-06-04T14:00:00Z",
+            None,
+            Tz::Europe__Berlin,
+        );
+        assert_eq!(
+            times.as_deref(),
+            Some("Host time: Tue 04 Jun 15:00 CEST - Tue 04 Jun 16:00 CEST")
+        );
+    }
+}
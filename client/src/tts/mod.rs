@@ -0,0 +1,83 @@
+//! Voice replies: when voice replies are on and someone sent a voice message, the
+//! answer is read out by an OpenAI-compatible `/audio/speech` endpoint and sent
+//! back as a voice note, with the text as its caption.
+use crate::backend::Http;
+use kinode_process_lib::http;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, str::FromStr};
+use url::Url;
+
+pub const OPENAI_URL: &str = "https://api.openai.com/v1";
+// the endpoint's input limit, in characters
+const MAX_INPUT_LEN: usize = 4096;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TtsSettings {
+    pub enabled: bool,
+    // base url of the service, e.g. a local server at http://localhost:8880/v1
+    pub url: String,
+    pub model: String,
+    pub voice: String,
+}
+
+impl Default for TtsSettings {
+    fn default() -> Self {
+        TtsSettings {
+            enabled: false,
+            url: OPENAI_URL.to_string(),
+            model: "tts-1".to_string(),
+            voice: "alloy".to_string(),
+        }
+    }
+}
+
+impl TtsSettings {
+    fn is_openai(&self) -> bool {
+        self.url.trim_end_matches('/') == OPENAI_URL
+    }
+}
+
+/// `text` as Ogg Opus audio, what telegram plays as a voice note. The openai key
+/// only goes to openai, a local service is called without it.
+pub fn synthesize(
+    http: &impl Http,
+    settings: &TtsSettings,
+    openai_key: Option<&str>,
+    text: &str,
+) -> anyhow::Result<Vec<u8>> {
+    let url = Url::from_str(&format!(
+        "{}/audio/speech",
+        settings.url.trim_end_matches('/')
+    ))?;
+    let mut headers = HashMap::from([("Content-Type".to_string(), "application/json".to_string())]);
+    if settings.is_openai() {
+        let Some(key) = openai_key else {
+            return Err(anyhow::anyhow!(
+                "voice replies through openai need an openai key"
+            ));
+        };
+        headers.insert("Authorization".to_string(), format!("Bearer {}", key));
+    }
+    let input: String = text.chars().take(MAX_INPUT_LEN).collect();
+    let body = serde_json::json!({
+        "model": settings.model,
+        "voice": settings.voice,
+        "input": input,
+        "response_format": "opus",
+    });
+
+    let res = http.request(
+        http::Method::POST,
+        url,
+        headers,
+        60,
+        serde_json::to_vec(&body)?,
+    )?;
+    if !res.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "speech synthesis failed: {}",
+            String::from_utf8_lossy(res.body())
+        ));
+    }
+    Ok(res.body().clone())
+}
@@ -0,0 +1,127 @@
+//! `/undo`: every calendar change the bot makes for someone is stored with the
+//! operation that reverts it, and `/undo` applies the latest one.
+use crate::{
+    backend::Http,
+    gcal::{
+        helpers::{delete_event, update_event},
+        Event, SendUpdates,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// per telegram user, older changes can't be undone anymore
+pub const MAX_UNDO: usize = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mutation {
+    // rfc3339
+    pub at: String,
+    pub inverse: Inverse,
+}
+
+/// What reverts a change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Inverse {
+    // the bot created the event
+    Delete {
+        event_id: String,
+        summary: Option<String>,
+        // as for the change itself, older entries were made without notifying anyone
+        #[serde(default = "quietly")]
+        send_updates: SendUpdates,
+    },
+    // the bot edited the event, this is how it looked before
+    Restore {
+        snapshot: Box<Event>,
+        send_updates: SendUpdates,
+    },
+}
+
+fn quietly() -> SendUpdates {
+    SendUpdates::None
+}
+
+impl Inverse {
+    /// Reverting the creation of `event`, if google gave it an id.
+    pub fn created(event: &Event, send_updates: SendUpdates) -> Option<Self> {
+        Some(Inverse::Delete {
+            event_id: event.id.clone()?,
+            summary: event.summary.clone(),
+            send_updates,
+        })
+    }
+
+    /// Reverting an edit of `before`, the event as it was.
+    pub fn edited(before: &Event, send_updates: SendUpdates) -> Self {
+        Inverse::Restore {
+            snapshot: Box::new(before.clone()),
+            send_updates,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Inverse::Delete { summary, .. } => format!(
+                "removed \"{}\"",
+                summary.as_deref().unwrap_or("untitled event")
+            ),
+            Inverse::Restore { snapshot, .. } => format!(
+                "restored \"{}\" to how it was",
+                snapshot.summary.as_deref().unwrap_or("untitled event")
+            ),
+        }
+    }
+
+    pub fn apply(&self, http: &impl Http, token: &str) -> anyhow::Result<()> {
+        match self {
+            Inverse::Delete {
+                event_id,
+                send_updates,
+                ..
+            } => delete_event(http, token, event_id, *send_updates),
+            Inverse::Restore {
+                snapshot,
+                send_updates,
+            } => {
+                let Some(event_id) = &snapshot.id else {
+                    return Err(anyhow::anyhow!("snapshot has no event id"));
+                };
+                update_event(http, token, event_id, &editable(snapshot), *send_updates)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The fields of a snapshot the bot might have changed, so restoring it doesn't
+/// send back read-only ones like `etag` or `created`.
+fn editable(snapshot: &Event) -> Event {
+    Event {
+        summary: snapshot.summary.clone(),
+        description: snapshot.description.clone(),
+        location: snapshot.location.clone(),
+        start: snapshot.start.clone(),
+        end: snapshot.end.clone(),
+        attendees: snapshot.attendees.clone(),
+        ..Default::default()
+    }
+}
+
+pub fn push(log: &mut HashMap<u64, Vec<Mutation>>, user_id: u64, mutation: Mutation) {
+    let mutations = log.entry(user_id).or_default();
+    mutations.push(mutation);
+    if mutations.len() > MAX_UNDO {
+        mutations.remove(0);
+    }
+}
+
+/// Drops the changes that deleted `event_id` to revert its creation, once the
+/// event is gone some other way.
+pub fn forget_event(log: &mut HashMap<u64, Vec<Mutation>>, event_id: &str) {
+    for mutations in log.values_mut() {
+        mutations.retain(|mutation| {
+            !matches!(&mutation.inverse, Inverse::Delete { event_id: id, .. } if id == event_id)
+        });
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use kinode::process::standard::get_state;
 use kinode_process_lib::http::send_response;
@@ -11,6 +12,12 @@ use oauth2::{
 };
 use serde::{Deserialize, Serialize};
 
+mod providers;
+#[path = "../../shared/secrets.rs"]
+mod secrets;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use providers::{default_provider, ClientAuth, ProviderKind, DEFAULT_PROVIDER};
+
 const SUCCESS_HTML: &[u8] = include_bytes!("../../pkg/oauth-ui/index.html");
 
 wit_bindgen::generate!({
@@ -20,167 +27,517 @@ wit_bindgen::generate!({
 
 #[derive(Debug, Serialize, Deserialize)]
 struct State {
+    // provider name -> config, e.g. "google", "zoom"
+    providers: HashMap<String, OauthState>,
+    // provider name -> client -> token
+    tokens: HashMap<String, HashMap<Address, TokenMetadata>>,
+    // csrf state -> exchange in flight
+    exchanges: HashMap<String, PendingExchange>,
+    // last refresh timer id handed out, see TokenMetadata.refresh_timer
+    #[serde(default)]
+    timers: u64,
+}
+
+/// State from before providers were configurable, everything was google.
+#[derive(Debug, Deserialize)]
+struct LegacyState {
     inner: OauthState,
     tokens: HashMap<Address, TokenMetadata>,
     exchanges: HashMap<String, (Address, String)>,
 }
 
+impl From<LegacyState> for State {
+    fn from(legacy: LegacyState) -> Self {
+        State {
+            providers: HashMap::from([(DEFAULT_PROVIDER.to_string(), legacy.inner)]),
+            tokens: HashMap::from([(DEFAULT_PROVIDER.to_string(), legacy.tokens)]),
+            exchanges: legacy
+                .exchanges
+                .into_iter()
+                .map(|(csrf, (client, verifier))| {
+                    (
+                        csrf,
+                        PendingExchange {
+                            client,
+                            verifier,
+                            provider: DEFAULT_PROVIDER.to_string(),
+                            created_at: 0,
+                        },
+                    )
+                })
+                .collect(),
+            timers: 0,
+        }
+    }
+}
+
+impl State {
+    fn new(name: String, provider: OauthState) -> Self {
+        State {
+            providers: HashMap::from([(name, provider)]),
+            tokens: HashMap::new(),
+            exchanges: HashMap::new(),
+            timers: 0,
+        }
+    }
+
+    fn provider(&self, name: &str) -> anyhow::Result<&OauthState> {
+        self.providers
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("unknown provider: {}", name))
+    }
+
+    fn token(&self, provider: &str, client: &Address) -> Option<&TokenMetadata> {
+        self.tokens.get(provider)?.get(client)
+    }
+}
+
+fn save(state: &State) {
+    set_state(&serde_json::to_vec(state).unwrap());
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct OauthState {
+    // presets for endpoints and authorization params
+    #[serde(default)]
+    kind: ProviderKind,
     client_id: String,
+    #[serde(with = "secrets::string")]
     client_secret: String,
     auth_url: String,
     token_url: String,
+    #[serde(default)]
+    revoke_url: Option<String>,
     redirect_url: String,
+    // requested when a client doesn't name any, falls back to the kind's defaults
+    #[serde(default)]
+    default_scopes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingExchange {
+    client: Address,
+    verifier: String,
+    provider: String,
+    // unix seconds, exchanges older than EXCHANGE_TTL_SECS are dropped
+    #[serde(default)]
+    created_at: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct TokenMetadata {
+    // unix seconds. state from before this was absolute holds expires_in instead,
+    // which reads as long expired and gets refreshed on startup.
     token_expiry: u64,
     token_scope: Vec<String>,
+    #[serde(with = "secrets::string")]
     refresh_token: String,
+    // timers can't be cancelled, only the latest one armed for a token refreshes it.
+    #[serde(default)]
+    refresh_timer: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 enum OauthRequest {
+    // requests the full google calendar scope
     GenerateUrl,
+    // google, scopes are aliases from GOOGLE_SCOPES or full scope urls
+    GenerateUrlWithScopes {
+        scopes: Vec<String>,
+    },
+    // any configured provider, empty scopes means the provider's defaults
+    GenerateProviderUrl {
+        provider: String,
+        scopes: Vec<String>,
+    },
+    // sends a fresh token right away if one covering the scopes is stored,
+    // otherwise responds with a url like GenerateProviderUrl.
+    RequestToken {
+        provider: String,
+        scopes: Vec<String>,
+    },
     RefreshToken,
-    Exchange { code: String, state: String },
-    Token { token: String },
+    RefreshProviderToken {
+        provider: String,
+    },
+    // revokes the token with the provider and forgets it
+    Revoke,
+    RevokeProvider {
+        provider: String,
+    },
+    // the refresh token was rejected, the client has to go through login again
+    ReauthRequired {
+        provider: String,
+    },
+    // only accepted from our own node
+    AddProvider(Initialize),
+    ListProviders,
+    Exchange {
+        code: String,
+        state: String,
+    },
+    Token {
+        token: String,
+        // granted, as reported by the provider
+        #[serde(default)]
+        scopes: Vec<String>,
+        #[serde(default = "default_provider")]
+        provider: String,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 enum OauthResponse {
-    Url { url: String },
-    Error { error: String },
+    Url {
+        url: String,
+    },
+    Error {
+        error: String,
+    },
     // sent as a request for now
-    RefreshToken { token: String },
+    RefreshToken {
+        token: String,
+    },
+    Token {
+        token: String,
+        scopes: Vec<String>,
+        provider: String,
+    },
+    Providers {
+        providers: Vec<ProviderInfo>,
+    },
+    Ok,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ProviderInfo {
+    name: String,
+    kind: ProviderKind,
+    default_scopes: Vec<String>,
 }
 
+/// Provider config, either sent once to set up the process or later through
+/// `AddProvider`. Endpoints can be left out for known kinds.
 #[derive(Debug, Serialize, Deserialize)]
 struct Initialize {
+    // defaults to the kind's name, e.g. "google"
+    #[serde(default)]
+    provider: Option<String>,
+    #[serde(default)]
+    kind: ProviderKind,
     client_id: String,
     client_secret: String,
-    auth_url: String,
-    token_url: String,
+    #[serde(default)]
+    auth_url: Option<String>,
+    #[serde(default)]
+    token_url: Option<String>,
+    #[serde(default)]
+    revoke_url: Option<String>,
     redirect_url: String,
+    #[serde(default)]
+    default_scopes: Vec<String>,
+}
+
+impl Initialize {
+    fn into_provider(self) -> anyhow::Result<(String, OauthState)> {
+        let endpoints = self.kind.endpoints();
+        let auth_url = self
+            .auth_url
+            .or_else(|| endpoints.as_ref().map(|e| e.auth_url.to_string()))
+            .ok_or_else(|| anyhow::anyhow!("auth_url required for {:?}", self.kind))?;
+        let token_url = self
+            .token_url
+            .or_else(|| endpoints.as_ref().map(|e| e.token_url.to_string()))
+            .ok_or_else(|| anyhow::anyhow!("token_url required for {:?}", self.kind))?;
+        let revoke_url = self
+            .revoke_url
+            .or_else(|| endpoints.and_then(|e| e.revoke_url.map(str::to_string)));
+
+        let name = self
+            .provider
+            .unwrap_or_else(|| self.kind.name().to_string());
+        let provider = OauthState {
+            kind: self.kind,
+            client_id: self.client_id,
+            client_secret: self.client_secret,
+            auth_url,
+            token_url,
+            revoke_url,
+            redirect_url: self.redirect_url,
+            default_scopes: self.default_scopes,
+        };
+        // fail early on bad urls rather than on the first client request.
+        create_oauth_client(&provider)?;
+        Ok((name, provider))
+    }
+}
+
+/// Context of our timers, to tell them apart when they fire.
+#[derive(Debug, Serialize, Deserialize)]
+enum TimerContext {
+    Refresh(Expires),
+    SweepExchanges,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Expires {
     client: Address,
+    #[serde(default = "default_provider")]
+    provider: String,
+    #[serde(default)]
+    timer: u64,
+    // failed refreshes so far, for backoff
+    #[serde(default)]
+    attempt: u32,
+}
+
+// refresh this long before the access token expires
+const REFRESH_MARGIN_SECS: u64 = 60;
+// retries after a failed refresh back off from RETRY_BASE_MS, doubling up to RETRY_MAX_MS.
+const MAX_REFRESH_ATTEMPTS: u32 = 10;
+const RETRY_BASE_MS: u64 = 5_000;
+const RETRY_MAX_MS: u64 = 30 * 60 * 1000;
+
+// a login has to finish within this long
+const EXCHANGE_TTL_SECS: u64 = 10 * 60;
+const SWEEP_INTERVAL_MS: u64 = 5 * 60 * 1000;
+// logins in flight per client, the oldest is dropped beyond this
+const MAX_EXCHANGES_PER_CLIENT: usize = 5;
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn respond(response: &OauthResponse) -> anyhow::Result<()> {
+    Response::new().body(serde_json::to_vec(response)?).send()?;
+    Ok(())
+}
+
+/// Expands the requested scopes, or the provider's defaults when there are none.
+fn requested_scopes(provider: &OauthState, scopes: &[String]) -> anyhow::Result<Vec<String>> {
+    let scopes = if !scopes.is_empty() {
+        scopes.to_vec()
+    } else if !provider.default_scopes.is_empty() {
+        provider.default_scopes.clone()
+    } else {
+        provider.kind.default_scopes()
+    };
+    scopes
+        .iter()
+        .map(|scope| provider.kind.expand_scope(scope))
+        .collect()
 }
 
 fn generate_url(
     source: &Address,
-    client: &mut BasicClient,
+    provider_name: &str,
+    scopes: &[String],
     state: &mut State,
 ) -> anyhow::Result<()> {
+    let result = state.provider(provider_name).and_then(|provider| {
+        Ok((
+            requested_scopes(provider, scopes)?,
+            create_oauth_client(provider)?,
+            provider.kind,
+        ))
+    });
+    let (scopes, client, kind) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            let _ = respond(&OauthResponse::Error {
+                error: e.to_string(),
+            });
+            return Err(e);
+        }
+    };
+
     let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
 
-    let (auth_url, csrf_token) = client
+    let mut request = client
         .authorize_url(CsrfToken::new_random)
-        .add_scope(Scope::new(
-            "https://www.googleapis.com/auth/calendar".to_string(),
-        ))
-        .add_extra_param("access_type", "offline")
-        .add_extra_param("prompt", "consent")
-        .set_pkce_challenge(pkce_challenge)
-        .url();
+        .add_scopes(scopes.into_iter().map(Scope::new))
+        .add_scopes(
+            kind.offline_scopes()
+                .iter()
+                .map(|s| Scope::new(s.to_string())),
+        )
+        .set_pkce_challenge(pkce_challenge);
 
-    // NOTE here, we're adding the extra_param prompt:consent every time, which
-    // leads to every time user is redirected, needing consent.
-    // could be improved, and technically one shouldn't need this after initial consent,
-    // but in our usecase, ideally this should only happen once/not very often, as the UI is telegram.
+    // consent is only needed to get a refresh token, google only hands one out
+    // on the consent screen. once we have one, asking for more scopes doesn't need it.
+    let needs_consent = state.token(provider_name, source).is_none();
+    for (key, value) in kind.auth_params(needs_consent) {
+        request = request.add_extra_param(key, value);
+    }
+    let (auth_url, csrf_token) = request.url();
 
     println!("Browse to: {}", auth_url);
 
+    // remote nodes can ask for urls, don't let them pile up exchanges.
+    let mut pending: Vec<(String, u64)> = state
+        .exchanges
+        .iter()
+        .filter(|(_, exchange)| exchange.client == *source)
+        .map(|(csrf, exchange)| (csrf.clone(), exchange.created_at))
+        .collect();
+    if pending.len() >= MAX_EXCHANGES_PER_CLIENT {
+        pending.sort_by_key(|(_, created_at)| *created_at);
+        for (csrf, _) in pending
+            .iter()
+            .take(pending.len() + 1 - MAX_EXCHANGES_PER_CLIENT)
+        {
+            state.exchanges.remove(csrf);
+        }
+    }
+
     state.exchanges.insert(
         csrf_token.secret().clone(),
-        (source.clone(), pkce_verifier.secret().clone()),
+        PendingExchange {
+            client: source.clone(),
+            verifier: pkce_verifier.secret().clone(),
+            provider: provider_name.to_string(),
+            created_at: now_secs(),
+        },
     );
+    // a restart mid-login shouldn't break the redirect.
+    save(state);
 
-    let _ = Response::new()
-        .body(
-            serde_json::to_vec(&OauthResponse::Url {
-                url: auth_url.to_string(),
-            })
-            .unwrap(),
-        )
-        .send();
+    respond(&OauthResponse::Url {
+        url: auth_url.to_string(),
+    })
+}
 
-    Ok(())
+/// Outgoing HTTP to the provider, behind a trait so the token logic doesn't
+/// depend on a running node. `Kinode` is the real implementation.
+trait Http {
+    /// POSTs an `application/x-www-form-urlencoded` body, with `authorization` as
+    /// the Authorization header if given, returning the response body.
+    /// Non-2xx statuses are errors carrying the body.
+    fn post_form(
+        &self,
+        url: &str,
+        body: String,
+        authorization: Option<String>,
+        timeout: u64,
+    ) -> anyhow::Result<Vec<u8>>;
 }
 
-fn refresh_access_token(
-    source: &Address,
-    refresh_token: &str,
-    state: &mut State,
-) -> anyhow::Result<()> {
-    let mut headers = HashMap::new();
-    headers.insert(
-        "Content-Type".to_string(),
-        "application/x-www-form-urlencoded".to_string(),
-    );
+struct Kinode;
 
-    let body = url::form_urlencoded::Serializer::new(String::new())
-        .append_pair("grant_type", "refresh_token")
-        .append_pair("client_id", &state.inner.client_id)
-        .append_pair("client_secret", &state.inner.client_secret)
-        .append_pair("refresh_token", refresh_token)
-        .finish();
+/// Non-2xx response from the provider.
+#[derive(Debug)]
+struct HttpError {
+    status: u16,
+    url: String,
+    body: String,
+}
 
-    let resp = http::send_request_await_response(
-        http::Method::POST,
-        state.inner.token_url.parse().unwrap(),
-        Some(headers),
-        5,
-        body.into_bytes(),
-    )?;
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} from {}: {}", self.status, self.url, self.body)
+    }
+}
 
-    let resp_json_body: serde_json::Value = serde_json::from_slice(&resp.body())?;
-
-    let new_access_token = resp_json_body
-        .get("access_token")
-        .ok_or_else(|| anyhow::anyhow!("Access token not found in response"))?
-        .as_str()
-        .ok_or_else(|| anyhow::anyhow!("Invalid access token format"))?
-        .to_string();
-
-    let new_expires_in = resp_json_body
-        .get("expires_in")
-        .ok_or_else(|| anyhow::anyhow!("Expires in not found in response"))?
-        .as_u64()
-        .ok_or_else(|| anyhow::anyhow!("Invalid expires in format"))?;
-
-    state.tokens.insert(
-        source.clone(),
-        TokenMetadata {
-            token_expiry: new_expires_in,
-            token_scope: vec![],
-            refresh_token: refresh_token.to_string(),
-        },
-    );
+impl std::error::Error for HttpError {}
 
-    let expires_ms = (new_expires_in - 30) * 1000;
-    let context = serde_json::to_vec(&Expires {
-        client: source.clone(),
-    })?;
-    timer::set_timer(expires_ms, Some(context));
+/// The refresh token was revoked, expired or otherwise rejected, retrying won't help.
+fn is_invalid_grant(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<HttpError>()
+        .and_then(|e| serde_json::from_str::<serde_json::Value>(&e.body).ok())
+        .is_some_and(|body| body.get("error").and_then(|e| e.as_str()) == Some("invalid_grant"))
+}
 
-    let _ = Request::new()
-        .target(source)
-        .body(
-            serde_json::to_vec(&OauthRequest::Token {
-                token: new_access_token,
-            })
-            .unwrap(),
+impl Http for Kinode {
+    fn post_form(
+        &self,
+        url: &str,
+        body: String,
+        authorization: Option<String>,
+        timeout: u64,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "Content-Type".to_string(),
+            "application/x-www-form-urlencoded".to_string(),
+        );
+        if let Some(authorization) = authorization {
+            headers.insert("Authorization".to_string(), authorization);
+        }
+
+        let resp = http::send_request_await_response(
+            http::Method::POST,
+            url.parse()?,
+            Some(headers),
+            timeout,
+            body.into_bytes(),
+        )?;
+        if !resp.status().is_success() {
+            return Err(HttpError {
+                status: resp.status().as_u16(),
+                url: url.to_string(),
+                body: String::from_utf8_lossy(resp.body()).to_string(),
+            }
+            .into());
+        }
+        Ok(resp.body().clone())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+    // only present when the user went through the consent screen
+    refresh_token: Option<String>,
+    // space separated, the scopes actually granted
+    scope: Option<String>,
+}
+
+fn parse_token_response(body: &[u8]) -> anyhow::Result<TokenResponse> {
+    serde_json::from_slice::<TokenResponse>(body).map_err(|e| {
+        anyhow::anyhow!(
+            "Invalid token response ({}): {}",
+            e,
+            String::from_utf8_lossy(body)
         )
-        .send();
+    })
+}
 
-    // todo, refactor into a better
+/// The Authorization header for providers that take the client credentials
+/// there rather than in the form.
+fn basic_auth(inner: &OauthState) -> Option<String> {
+    (inner.kind.client_auth() == ClientAuth::Basic).then(|| {
+        let credentials = format!("{}:{}", inner.client_id, inner.client_secret);
+        format!("Basic {}", STANDARD.encode(credentials))
+    })
+}
 
-    Ok(())
+/// A form for the token endpoint, holding the client credentials unless they
+/// go in `basic_auth`.
+fn token_form(inner: &OauthState) -> url::form_urlencoded::Serializer<'static, String> {
+    let mut form = url::form_urlencoded::Serializer::new(String::new());
+    if inner.kind.client_auth() == ClientAuth::Body {
+        form.append_pair("client_id", &inner.client_id)
+            .append_pair("client_secret", &inner.client_secret);
+    }
+    form
+}
+
+fn refresh_access_token(
+    http: &impl Http,
+    refresh_token: &str,
+    inner: &OauthState,
+) -> anyhow::Result<TokenResponse> {
+    let body = token_form(inner)
+        .append_pair("grant_type", "refresh_token")
+        .append_pair("refresh_token", refresh_token)
+        .finish();
+
+    let resp = http.post_form(&inner.token_url, body, basic_auth(inner), 5)?;
+    parse_token_response(&resp)
 }
 
 // take other things in too. it's source + url? only? or should we separate the code...
@@ -194,100 +551,272 @@ fn refresh_access_token(
 // all client UI needs is generate_url() for auth. (this can be done again and again I think)?
 //
 fn exchange_code(
-    code: &String,
-    source: &Address,
+    http: &impl Http,
+    code: &str,
     verifier: &str,
-    state: &mut State,
-) -> anyhow::Result<()> {
-    let mut headers = HashMap::new();
-    headers.insert(
-        "Content-Type".to_string(),
-        "application/x-www-form-urlencoded".to_string(),
-    );
-
-    let body = url::form_urlencoded::Serializer::new(String::new())
+    inner: &OauthState,
+) -> anyhow::Result<TokenResponse> {
+    let body = token_form(inner)
         .append_pair("grant_type", "authorization_code")
-        .append_pair("client_id", &state.inner.client_id)
-        .append_pair("client_secret", &state.inner.client_secret)
-        .append_pair("code", &code)
-        .append_pair("redirect_uri", &state.inner.redirect_url)
-        .append_pair("code_verifier", &verifier)
+        .append_pair("code", code)
+        .append_pair("redirect_uri", &inner.redirect_url)
+        .append_pair("code_verifier", verifier)
         .finish();
 
-    let resp = http::send_request_await_response(
-        http::Method::POST,
-        state.inner.token_url.parse().unwrap(),
-        Some(headers),
-        5,
-        body.into_bytes(),
-    )?;
+    let resp = http.post_form(&inner.token_url, body, basic_auth(inner), 5)?;
+    parse_token_response(&resp)
+}
 
-    let resp_json_body: serde_json::Value = serde_json::from_slice(&resp.body())?;
-
-    let token = resp_json_body
-        .get("access_token")
-        .ok_or_else(|| anyhow::anyhow!("Access token not found in response"))?
-        .as_str()
-        .ok_or_else(|| anyhow::anyhow!("Invalid access token format"))?
-        .to_string();
-
-    let refresh_token = resp_json_body
-        .get("refresh_token")
-        .ok_or_else(|| anyhow::anyhow!("Refresh token not found in response"))?
-        .as_str()
-        .ok_or_else(|| anyhow::anyhow!("Invalid refresh token format"))?
-        .to_string();
-
-    let expires_in = resp_json_body
-        .get("expires_in")
-        .ok_or_else(|| anyhow::anyhow!("Expires in not found in response"))?
-        .as_u64()
-        .ok_or_else(|| anyhow::anyhow!("Invalid expires in format"))?;
-
-    state.tokens.insert(
-        source.clone(),
-        TokenMetadata {
-            token_expiry: expires_in,
-            token_scope: vec![],
-            refresh_token: refresh_token,
-        },
-    );
+/// Stores the token metadata, arms the refresh timer and hands the access token to the client.
+/// Returns the access token.
+fn store_token(
+    source: &Address,
+    provider: &str,
+    token: TokenResponse,
+    refresh_token: String,
+    state: &mut State,
+) -> anyhow::Result<String> {
+    // refresh responses may leave out the scope, keep what we knew then.
+    let token_scope = match &token.scope {
+        Some(scope) => scope.split_whitespace().map(str::to_string).collect(),
+        None => state
+            .token(provider, source)
+            .map(|metadata| metadata.token_scope.clone())
+            .unwrap_or_default(),
+    };
 
-    // automatic refreshing, in 30s before expiry, we request a new access_token,
-    // and send it back to the client?
-    let expires_ms = (expires_in - 30) * 1000;
-    let context = serde_json::to_vec(&Expires {
-        client: source.clone(),
-    })?;
+    state
+        .tokens
+        .entry(provider.to_string())
+        .or_default()
+        .insert(
+            source.clone(),
+            TokenMetadata {
+                token_expiry: now_secs() + token.expires_in,
+                token_scope: token_scope.clone(),
+                refresh_token,
+                refresh_timer: 0,
+            },
+        );
 
-    timer::set_timer(expires_ms, Some(context));
+    // automatic refreshing, a bit before expiry we request a new access_token,
+    // and send it back to the client.
+    let delay_ms = token.expires_in.saturating_sub(REFRESH_MARGIN_SECS) * 1000;
+    arm_refresh(source, provider, delay_ms, 0, state)?;
+    save(state);
 
     let _ = Request::new()
         .target(source)
         .body(
             serde_json::to_vec(&OauthRequest::Token {
-                token: token.to_string(),
+                token: token.access_token.clone(),
+                scopes: token_scope,
+                provider: provider.to_string(),
             })
             .unwrap(),
         )
         .send();
 
-    Ok(())
+    Ok(token.access_token)
 }
 
-fn handle_message(
-    _our: &Address,
+/// Arms a refresh timer for the client's token, superseding any armed before it.
+fn arm_refresh(
+    client: &Address,
+    provider: &str,
+    delay_ms: u64,
+    attempt: u32,
     state: &mut State,
-    client: &mut BasicClient,
 ) -> anyhow::Result<()> {
+    let Some(metadata) = state
+        .tokens
+        .get_mut(provider)
+        .and_then(|tokens| tokens.get_mut(client))
+    else {
+        return Ok(());
+    };
+    state.timers += 1;
+    metadata.refresh_timer = state.timers;
+
+    let context = serde_json::to_vec(&TimerContext::Refresh(Expires {
+        client: client.clone(),
+        provider: provider.to_string(),
+        timer: state.timers,
+        attempt,
+    }))?;
+    timer::set_timer(delay_ms, Some(context));
+    Ok(())
+}
+
+/// Timers don't survive a restart, so arm one for every stored token from its expiry.
+fn rearm_refreshes(state: &mut State) -> anyhow::Result<()> {
+    let now = now_secs();
+    let pending: Vec<(String, Address, u64)> = state
+        .tokens
+        .iter()
+        .flat_map(|(provider, tokens)| {
+            tokens.iter().map(|(client, metadata)| {
+                let refresh_at = metadata.token_expiry.saturating_sub(REFRESH_MARGIN_SECS);
+                (
+                    provider.clone(),
+                    client.clone(),
+                    refresh_at.saturating_sub(now),
+                )
+            })
+        })
+        .collect();
+
+    for (provider, client, delay_secs) in pending {
+        arm_refresh(&client, &provider, delay_secs * 1000, 0, state)?;
+    }
+    save(state);
+    Ok(())
+}
+
+/// Returns the new access token, or None if the client has no token stored.
+fn refresh_token(
+    source: &Address,
+    provider: &str,
+    state: &mut State,
+) -> anyhow::Result<Option<String>> {
+    let Some(token_metadata) = state.token(provider, source) else {
+        return Ok(None);
+    };
+    let refresh_token = token_metadata.refresh_token.clone();
+    let token = refresh_access_token(&Kinode, &refresh_token, state.provider(provider)?)?;
+    Ok(Some(store_token(
+        source,
+        provider,
+        token,
+        refresh_token,
+        state,
+    )?))
+}
+
+/// Refresh asked for by the client, e.g. after google rejected its access token.
+/// Responds with the new token.
+fn refresh_on_demand(source: &Address, provider: &str, state: &mut State) -> anyhow::Result<()> {
+    match refresh_token(source, provider, state) {
+        Ok(Some(token)) => respond(&OauthResponse::Token {
+            token,
+            scopes: state
+                .token(provider, source)
+                .map(|metadata| metadata.token_scope.clone())
+                .unwrap_or_default(),
+            provider: provider.to_string(),
+        }),
+        Ok(None) => respond(&OauthResponse::Error {
+            error: format!("no {} token stored, connect first", provider),
+        }),
+        Err(e) => {
+            if is_invalid_grant(&e) {
+                reauth_required(source, provider, state);
+            }
+            respond(&OauthResponse::Error {
+                error: e.to_string(),
+            })?;
+            Err(e)
+        }
+    }
+}
+
+/// Drops a token the provider no longer accepts and tells the client to log in again.
+fn reauth_required(client: &Address, provider: &str, state: &mut State) {
+    if let Some(tokens) = state.tokens.get_mut(provider) {
+        tokens.remove(client);
+    }
+    save(state);
+
+    let _ = Request::new()
+        .target(client)
+        .body(
+            serde_json::to_vec(&OauthRequest::ReauthRequired {
+                provider: provider.to_string(),
+            })
+            .unwrap(),
+        )
+        .send();
+}
+
+/// A token whose refresh token couldn't be decrypted can't be refreshed, its client
+/// is asked to log in again.
+fn drop_lost_tokens(state: &mut State) {
+    let lost: Vec<(String, Address)> = state
+        .tokens
+        .iter()
+        .flat_map(|(provider, tokens)| {
+            tokens
+                .iter()
+                .filter(|(_, metadata)| metadata.refresh_token.is_empty())
+                .map(move |(client, _)| (provider.clone(), client.clone()))
+        })
+        .collect();
+    for (provider, client) in lost {
+        reauth_required(&client, &provider, state);
+    }
+}
+
+/// Revokes `token` with the provider, if it has a revocation endpoint.
+fn revoke_token(http: &impl Http, token: &str, inner: &OauthState) -> anyhow::Result<()> {
+    let Some(url) = &inner.revoke_url else {
+        return Ok(());
+    };
+    let body = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("token", token)
+        .finish();
+    http.post_form(url, body, basic_auth(inner), 5).map(|_| ())
+}
+
+/// Forgets the client's token and any logins in flight, then revokes the refresh
+/// token with the provider if it has a revocation endpoint.
+fn revoke(source: &Address, provider: &str, state: &mut State) -> anyhow::Result<()> {
+    let metadata = state
+        .tokens
+        .get_mut(provider)
+        .and_then(|tokens| tokens.remove(source));
+    state
+        .exchanges
+        .retain(|_, exchange| !(exchange.client == *source && exchange.provider == provider));
+    save(state);
+
+    let inner = state.providers.get(provider);
+    let result = match (metadata, inner) {
+        (Some(metadata), Some(inner)) => revoke_token(&Kinode, &metadata.refresh_token, inner),
+        _ => Ok(()),
+    };
+
+    match result {
+        Ok(()) => respond(&OauthResponse::Ok),
+        Err(e) => {
+            respond(&OauthResponse::Error {
+                error: format!("token removed, but revoking it failed: {}", e),
+            })?;
+            Err(e)
+        }
+    }
+}
+
+/// Whether the stored token was granted every requested scope.
+fn covers(metadata: &TokenMetadata, provider: &OauthState, scopes: &[String]) -> bool {
+    let Ok(scopes) = requested_scopes(provider, scopes) else {
+        return false;
+    };
+    scopes
+        .iter()
+        .all(|scope| metadata.token_scope.contains(scope))
+}
+
+fn handle_message(our: &Address, state: &mut State) -> anyhow::Result<()> {
     let message = await_message()?;
 
     if !message.is_request() {
         // slightly special case, put elsewhere?
         if message.source().process == "timer:distro:sys" {
             if let Some(ctx) = message.context() {
-                let expires: Expires = serde_json::from_slice(ctx)?;
-                handle_timer(&expires, state)?;
+                match serde_json::from_slice(ctx)? {
+                    TimerContext::Refresh(expires) => handle_timer(&expires, state)?,
+                    TimerContext::SweepExchanges => sweep_exchanges(state)?,
+                }
             }
             return Ok(());
         }
@@ -319,15 +848,36 @@ fn handle_message(
             .get("state")
             .ok_or_else(|| anyhow::anyhow!("no state in query params"))?;
 
-        if let Some((addr, verifier)) = state.exchanges.get_mut(state_str).cloned() {
-            exchange_code(code, &addr, &verifier, state)?;
-        } else {
-            send_response(http::StatusCode::UNAUTHORIZED, None, vec![]);
+        // single use, a replayed redirect finds nothing.
+        let Some(exchange) = state.exchanges.remove(state_str) else {
             return Err(anyhow::anyhow!(
-                "unexpected state, no auth for u : {:?}",
-                state
+                "unexpected state, no auth for u: {}",
+                state_str
             ));
+        };
+        save(state);
+        if exchange.created_at + EXCHANGE_TTL_SECS < now_secs() {
+            return Err(anyhow::anyhow!("login for {} expired", exchange.client));
         }
+        let PendingExchange {
+            client,
+            verifier,
+            provider,
+            ..
+        } = &exchange;
+        let token = exchange_code(&Kinode, code, verifier, state.provider(provider)?)?;
+        // without a consent prompt google doesn't send a new refresh token.
+        let refresh_token = token
+            .refresh_token
+            .clone()
+            .or_else(|| {
+                state
+                    .token(provider, client)
+                    .map(|metadata| metadata.refresh_token.clone())
+            })
+            .ok_or_else(|| anyhow::anyhow!("Refresh token not found in response"))?;
+        let (client, provider) = (client.clone(), provider.clone());
+        store_token(&client, &provider, token, refresh_token, state)?;
         return Ok(());
     }
 
@@ -335,14 +885,73 @@ fn handle_message(
 
     match req {
         OauthRequest::GenerateUrl => {
-            generate_url(message.source(), client, state)?;
+            generate_url(message.source(), DEFAULT_PROVIDER, &[], state)?;
+        }
+        OauthRequest::GenerateUrlWithScopes { scopes } => {
+            generate_url(message.source(), DEFAULT_PROVIDER, &scopes, state)?;
+        }
+        OauthRequest::GenerateProviderUrl { provider, scopes } => {
+            generate_url(message.source(), &provider, &scopes, state)?;
+        }
+        OauthRequest::RequestToken { provider, scopes } => {
+            let covered = match (
+                state.providers.get(&provider),
+                state.token(&provider, message.source()),
+            ) {
+                (Some(config), Some(metadata)) => covers(metadata, config, &scopes),
+                _ => false,
+            };
+            if !covered {
+                return generate_url(message.source(), &provider, &scopes, state);
+            }
+            refresh_on_demand(message.source(), &provider, state)?;
         }
         OauthRequest::RefreshToken => {
-            if let Some(token_metadata) = state.tokens.get(&message.source()) {
-                let refresh_token = token_metadata.refresh_token.clone();
-                refresh_access_token(&message.source(), &refresh_token, state)?;
+            refresh_on_demand(message.source(), DEFAULT_PROVIDER, state)?;
+        }
+        OauthRequest::RefreshProviderToken { provider } => {
+            refresh_on_demand(message.source(), &provider, state)?;
+        }
+        OauthRequest::Revoke => {
+            revoke(message.source(), DEFAULT_PROVIDER, state)?;
+        }
+        OauthRequest::RevokeProvider { provider } => {
+            revoke(message.source(), &provider, state)?;
+        }
+        OauthRequest::AddProvider(init) => {
+            if message.source().node != our.node {
+                respond(&OauthResponse::Error {
+                    error: "providers can only be added by this node".to_string(),
+                })?;
+                return Err(anyhow::anyhow!(
+                    "AddProvider from foreign node: {}",
+                    message.source()
+                ));
+            }
+            match init.into_provider() {
+                Ok((name, provider)) => {
+                    println!("added provider {}", name);
+                    state.providers.insert(name, provider);
+                    save(state);
+                    respond(&OauthResponse::Ok)?;
+                }
+                Err(e) => respond(&OauthResponse::Error {
+                    error: e.to_string(),
+                })?,
             }
         }
+        OauthRequest::ListProviders => {
+            let providers = state
+                .providers
+                .iter()
+                .map(|(name, provider)| ProviderInfo {
+                    name: name.clone(),
+                    kind: provider.kind,
+                    default_scopes: provider.default_scopes.clone(),
+                })
+                .collect();
+            respond(&OauthResponse::Providers { providers })?;
+        }
         OauthRequest::Exchange { .. } => {
             // reason for this is, the http_redirect url needs to be defined at the start.
             // so you can't really redirect to your own kinode that would do this.
@@ -355,76 +964,120 @@ fn handle_message(
     Ok(())
 }
 
-fn handle_timer(expires: &Expires, state: &mut State) -> anyhow::Result<()> {
-    if let Some(token_metadata) = state.tokens.get(&expires.client) {
-        let refresh_token = token_metadata.refresh_token.clone();
-        refresh_access_token(&expires.client, &refresh_token, state)?;
+/// Drops logins that were never finished, then re-arms itself.
+fn sweep_exchanges(state: &mut State) -> anyhow::Result<()> {
+    let now = now_secs();
+    let before = state.exchanges.len();
+    state
+        .exchanges
+        .retain(|_, exchange| exchange.created_at + EXCHANGE_TTL_SECS >= now);
+    if state.exchanges.len() != before {
+        save(state);
     }
+    arm_sweep()
+}
+
+fn arm_sweep() -> anyhow::Result<()> {
+    timer::set_timer(
+        SWEEP_INTERVAL_MS,
+        Some(serde_json::to_vec(&TimerContext::SweepExchanges)?),
+    );
     Ok(())
 }
 
+fn handle_timer(expires: &Expires, state: &mut State) -> anyhow::Result<()> {
+    // revoked, or refreshed since and a newer timer is armed.
+    match state.token(&expires.provider, &expires.client) {
+        Some(metadata) if metadata.refresh_timer == expires.timer => {}
+        _ => return Ok(()),
+    }
+
+    let Err(e) = refresh_token(&expires.client, &expires.provider, state) else {
+        return Ok(());
+    };
+    if is_invalid_grant(&e) {
+        reauth_required(&expires.client, &expires.provider, state);
+        return Err(e.context("refresh token rejected, client asked to log in again"));
+    }
+    if expires.attempt + 1 >= MAX_REFRESH_ATTEMPTS {
+        // the client can still refresh on demand, and a restart re-arms the timer.
+        return Err(e.context("giving up refreshing"));
+    }
+
+    let delay_ms = RETRY_BASE_MS
+        .saturating_mul(1 << expires.attempt)
+        .min(RETRY_MAX_MS);
+    arm_refresh(
+        &expires.client,
+        &expires.provider,
+        delay_ms,
+        expires.attempt + 1,
+        state,
+    )?;
+    save(state);
+    Err(e.context(format!("refresh failed, retrying in {}s", delay_ms / 1000)))
+}
+
 fn initialize() -> State {
     // try to get saved state first, then wait for Initialize message either from
     // http or command line.
-    match get_state() {
-        Some(state) => {
-            let state: State = serde_json::from_slice(&state).unwrap();
-            return state;
+    if let Some(bytes) = get_state() {
+        match serde_json::from_slice::<State>(&bytes) {
+            Ok(state) => {
+                if secrets::take_failures() > 0 {
+                    // clients with a lost refresh token are asked to log in again by `drop_lost_tokens`.
+                    println!("some saved secrets couldn't be decrypted and were cleared, set the client secrets again");
+                    save(&state);
+                }
+                return state;
+            }
+            Err(e) => match serde_json::from_slice::<LegacyState>(&bytes) {
+                Ok(legacy) => {
+                    let state = State::from(legacy);
+                    save(&state);
+                    return state;
+                }
+                Err(_) => println!("can't read saved state, starting over: {:?}", e),
+            },
         }
-        None => {}
     }
 
     loop {
         if let Ok(message) = await_message() {
-            if message.source().process == "http_server:distro:sys" {
+            let init = if message.source().process == "http_server:distro:sys" {
                 let msg: http::HttpServerRequest = serde_json::from_slice(message.body()).unwrap();
 
-                if let http::HttpServerRequest::Http(_req) = msg {
-                    let body = get_blob().unwrap();
-                    let init = serde_json::from_slice::<Initialize>(&body.bytes).unwrap();
-                    let state = State {
-                        inner: OauthState {
-                            client_id: init.client_id,
-                            client_secret: init.client_secret,
-                            auth_url: init.auth_url,
-                            token_url: init.token_url,
-                            redirect_url: init.redirect_url,
-                        },
-                        tokens: HashMap::new(),
-                        exchanges: HashMap::new(),
-                    };
-                    set_state(&serde_json::to_vec(&state).unwrap());
-                    return state;
-                }
-            }
-            if let Ok(init) = serde_json::from_slice::<Initialize>(message.body()) {
-                let state = State {
-                    inner: OauthState {
-                        client_id: init.client_id,
-                        client_secret: init.client_secret,
-                        auth_url: init.auth_url,
-                        token_url: init.token_url,
-                        redirect_url: init.redirect_url,
-                    },
-                    tokens: HashMap::new(),
-                    exchanges: HashMap::new(),
+                let http::HttpServerRequest::Http(_req) = msg else {
+                    continue;
                 };
-                set_state(&serde_json::to_vec(&state).unwrap());
+                let body = get_blob().unwrap();
+                serde_json::from_slice::<Initialize>(&body.bytes).unwrap()
+            } else if let Ok(init) = serde_json::from_slice::<Initialize>(message.body()) {
+                init
+            } else {
+                continue;
+            };
 
-                return state;
+            match init.into_provider() {
+                Ok((name, provider)) => {
+                    let state = State::new(name, provider);
+                    save(&state);
+                    return state;
+                }
+                Err(e) => println!("invalid provider config: {:?}", e),
             }
         }
     }
 }
 
-fn create_oauth_client(state: &State) -> Result<BasicClient, url::ParseError> {
+fn create_oauth_client(provider: &OauthState) -> Result<BasicClient, url::ParseError> {
     let client = BasicClient::new(
-        ClientId::new(state.inner.client_id.clone()),
-        Some(ClientSecret::new(state.inner.client_secret.clone())),
-        AuthUrl::new(state.inner.auth_url.clone())?,
-        Some(TokenUrl::new(state.inner.token_url.clone())?),
+        ClientId::new(provider.client_id.clone()),
+        Some(ClientSecret::new(provider.client_secret.clone())),
+        AuthUrl::new(provider.auth_url.clone())?,
+        Some(TokenUrl::new(provider.token_url.clone())?),
     );
-    let client = client.set_redirect_uri(RedirectUrl::new(state.inner.redirect_url.clone())?);
+    let client = client.set_redirect_uri(RedirectUrl::new(provider.redirect_url.clone())?);
     Ok(client)
 }
 
@@ -448,11 +1101,19 @@ fn init(our: Address) {
     // todo: better webpage serving too...
     http::bind_http_path("/auth", false, false).unwrap();
 
+    // before initialize, saved secrets are encrypted with it.
+    secrets::init(&our).unwrap();
     let mut state = initialize();
-    let mut client = create_oauth_client(&state).unwrap();
+    drop_lost_tokens(&mut state);
+    if let Err(e) = rearm_refreshes(&mut state) {
+        println!("error re-arming refreshes: {:?}", e);
+    }
+    if let Err(e) = sweep_exchanges(&mut state) {
+        println!("error sweeping exchanges: {:?}", e);
+    }
 
     loop {
-        match handle_message(&our, &mut state, &mut client) {
+        match handle_message(&our, &mut state) {
             Ok(()) => {}
             Err(e) => {
                 println!("error: {:?}", e);
@@ -460,3 +1121,193 @@ fn init(our: Address) {
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// A token endpoint giving one canned answer, recording what was posted to it.
+    struct FakeProvider {
+        // status and body of a failure, or the body of a success
+        answer: Result<&'static str, (u16, &'static str)>,
+        // url, form and Authorization header of each post
+        posts: RefCell<Vec<Post>>,
+    }
+
+    type Post = (String, HashMap<String, String>, Option<String>);
+
+    impl FakeProvider {
+        fn answering(answer: Result<&'static str, (u16, &'static str)>) -> Self {
+            FakeProvider {
+                answer,
+                posts: RefCell::new(vec![]),
+            }
+        }
+
+        fn posted(&self) -> Post {
+            let posts = self.posts.borrow();
+            assert_eq!(posts.len(), 1, "expected a single post");
+            posts[0].clone()
+        }
+    }
+
+    impl Http for FakeProvider {
+        fn post_form(
+            &self,
+            url: &str,
+            body: String,
+            authorization: Option<String>,
+            _timeout: u64,
+        ) -> anyhow::Result<Vec<u8>> {
+            let form = url::form_urlencoded::parse(body.as_bytes())
+                .into_owned()
+                .collect();
+            self.posts
+                .borrow_mut()
+                .push((url.to_string(), form, authorization));
+            match self.answer {
+                Ok(body) => Ok(body.as_bytes().to_vec()),
+                Err((status, body)) => Err(HttpError {
+                    status,
+                    url: url.to_string(),
+                    body: body.to_string(),
+                }
+                .into()),
+            }
+        }
+    }
+
+    fn provider() -> OauthState {
+        OauthState {
+            kind: ProviderKind::Google,
+            client_id: "client-id".to_string(),
+            client_secret: "client secret&".to_string(),
+            auth_url: "https://accounts.google.com/o/oauth2/v2/auth".to_string(),
+            token_url: "https://oauth2.googleapis.com/token".to_string(),
+            revoke_url: None,
+            redirect_url: "https://node.example/oauth:kcal:appattacc.os/auth".to_string(),
+            default_scopes: vec![],
+        }
+    }
+
+    const GRANTED: &str = r#"{"access_token":"access","expires_in":3599,"refresh_token":"refresh","scope":"openid https://www.googleapis.com/auth/calendar","token_type":"Bearer"}"#;
+
+    #[test]
+    fn exchange_posts_the_code_and_verifier() {
+        let http = FakeProvider::answering(Ok(GRANTED));
+        let token = exchange_code(&http, "the/code", "verifier", &provider()).unwrap();
+
+        let (url, form, authorization) = http.posted();
+        assert_eq!(url, "https://oauth2.googleapis.com/token");
+        assert_eq!(authorization, None);
+        assert_eq!(form["grant_type"], "authorization_code");
+        assert_eq!(form["client_id"], "client-id");
+        assert_eq!(form["client_secret"], "client secret&");
+        assert_eq!(form["code"], "the/code");
+        assert_eq!(form["code_verifier"], "verifier");
+        assert_eq!(
+            form["redirect_uri"],
+            "https://node.example/oauth:kcal:appattacc.os/auth"
+        );
+
+        assert_eq!(token.access_token, "access");
+        assert_eq!(token.expires_in, 3599);
+        assert_eq!(token.refresh_token.as_deref(), Some("refresh"));
+        assert_eq!(
+            token.scope.as_deref(),
+            Some("openid https://www.googleapis.com/auth/calendar")
+        );
+    }
+
+    #[test]
+    fn refresh_posts_the_refresh_token() {
+        // google leaves out the refresh token, and sometimes the scope, on refresh.
+        let http = FakeProvider::answering(Ok(r#"{"access_token":"fresh","expires_in":3599}"#));
+        let token = refresh_access_token(&http, "refresh", &provider()).unwrap();
+
+        let (url, form, authorization) = http.posted();
+        assert_eq!(url, "https://oauth2.googleapis.com/token");
+        assert_eq!(authorization, None);
+        assert_eq!(form["grant_type"], "refresh_token");
+        assert_eq!(form["refresh_token"], "refresh");
+        assert_eq!(form["client_id"], "client-id");
+        assert_eq!(form["client_secret"], "client secret&");
+        assert!(!form.contains_key("code"));
+
+        assert_eq!(token.access_token, "fresh");
+        assert_eq!(token.refresh_token, None);
+        assert_eq!(token.scope, None);
+    }
+
+    fn zoom() -> OauthState {
+        OauthState {
+            kind: ProviderKind::Zoom,
+            auth_url: "https://zoom.us/oauth/authorize".to_string(),
+            token_url: "https://zoom.us/oauth/token".to_string(),
+            revoke_url: Some("https://zoom.us/oauth/revoke".to_string()),
+            ..provider()
+        }
+    }
+
+    #[test]
+    fn zoom_gets_the_client_credentials_as_basic_auth() {
+        // base64 of "client-id:client secret&"
+        let basic = Some("Basic Y2xpZW50LWlkOmNsaWVudCBzZWNyZXQm".to_string());
+
+        let http = FakeProvider::answering(Ok(GRANTED));
+        exchange_code(&http, "code", "verifier", &zoom()).unwrap();
+        let (url, form, authorization) = http.posted();
+        assert_eq!(url, "https://zoom.us/oauth/token");
+        assert_eq!(authorization, basic);
+        assert_eq!(form["code"], "code");
+        assert!(!form.contains_key("client_id") && !form.contains_key("client_secret"));
+
+        let http = FakeProvider::answering(Ok(GRANTED));
+        refresh_access_token(&http, "refresh", &zoom()).unwrap();
+        let (_, form, authorization) = http.posted();
+        assert_eq!(authorization, basic);
+        assert_eq!(form["refresh_token"], "refresh");
+        assert!(!form.contains_key("client_secret"));
+
+        let http = FakeProvider::answering(Ok("{}"));
+        revoke_token(&http, "refresh", &zoom()).unwrap();
+        let (url, form, authorization) = http.posted();
+        assert_eq!(url, "https://zoom.us/oauth/revoke");
+        assert_eq!(authorization, basic);
+        assert_eq!(form["token"], "refresh");
+    }
+
+    #[test]
+    fn revoked_refresh_tokens_are_invalid_grants() {
+        let http = FakeProvider::answering(Err((
+            400,
+            r#"{"error":"invalid_grant","error_description":"Token has been expired or revoked."}"#,
+        )));
+        let e = refresh_access_token(&http, "refresh", &provider()).unwrap_err();
+        assert!(is_invalid_grant(&e));
+    }
+
+    #[test]
+    fn other_failures_are_not_invalid_grants() {
+        for answer in [
+            Err((500, "backend error")),
+            Err((400, r#"{"error":"invalid_client"}"#)),
+        ] {
+            let http = FakeProvider::answering(answer);
+            let e = refresh_access_token(&http, "refresh", &provider()).unwrap_err();
+            assert!(!is_invalid_grant(&e), "{}", e);
+        }
+        assert!(!is_invalid_grant(&anyhow::anyhow!("invalid_grant")));
+    }
+
+    #[test]
+    fn malformed_responses_are_errors() {
+        for body in ["<html>oops</html>", r#"{"expires_in":3599}"#, ""] {
+            let http = FakeProvider::answering(Ok(body));
+            let e = exchange_code(&http, "code", "verifier", &provider()).unwrap_err();
+            assert!(e.to_string().starts_with("Invalid token response"), "{}", e);
+            assert!(!is_invalid_grant(&e));
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use serde::{Deserialize, Serialize};
+
+/// Provider used by requests that don't name one, kcal's own google calendar flow.
+pub const DEFAULT_PROVIDER: &str = "google";
+
+pub fn default_provider() -> String {
+    DEFAULT_PROVIDER.to_string()
+}
+
+const GOOGLE_SCOPE_PREFIX: &str = "https://www.googleapis.com/auth/";
+
+// short names clients can use instead of full google scope urls.
+const GOOGLE_SCOPES: &[&str] = &[
+    "calendar",
+    "calendar.readonly",
+    "calendar.events",
+    "calendar.events.readonly",
+    "calendar.freebusy",
+    "contacts.readonly",
+];
+
+/// Presets for known providers, the differences in endpoints and authorization
+/// parameters live here. `Oidc` is any other standard provider, configured with
+/// explicit endpoints.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    #[default]
+    Google,
+    Microsoft,
+    Zoom,
+    Oidc,
+}
+
+/// How the client id and secret reach the token and revoke endpoints.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClientAuth {
+    // `client_id` and `client_secret` form fields
+    Body,
+    // `Authorization: Basic base64(client_id:client_secret)`
+    Basic,
+}
+
+pub struct Endpoints {
+    pub auth_url: &'static str,
+    pub token_url: &'static str,
+    pub revoke_url: Option<&'static str>,
+}
+
+impl ProviderKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Google => "google",
+            Self::Microsoft => "microsoft",
+            Self::Zoom => "zoom",
+            Self::Oidc => "oidc",
+        }
+    }
+
+    pub fn endpoints(&self) -> Option<Endpoints> {
+        match self {
+            Self::Google => Some(Endpoints {
+                auth_url: "https://accounts.google.com/o/oauth2/v2/auth",
+                token_url: "https://oauth2.googleapis.com/token",
+                revoke_url: Some("https://oauth2.googleapis.com/revoke"),
+            }),
+            Self::Microsoft => Some(Endpoints {
+                auth_url: "https://login.microsoftonline.com/common/oauth2/v2.0/authorize",
+                token_url: "https://login.microsoftonline.com/common/oauth2/v2.0/token",
+                revoke_url: None,
+            }),
+            Self::Zoom => Some(Endpoints {
+                auth_url: "https://zoom.us/oauth/authorize",
+                token_url: "https://zoom.us/oauth/token",
+                revoke_url: Some("https://zoom.us/oauth/revoke"),
+            }),
+            Self::Oidc => None,
+        }
+    }
+
+    pub fn client_auth(&self) -> ClientAuth {
+        match self {
+            // zoom rejects credentials in the body
+            Self::Zoom => ClientAuth::Basic,
+            Self::Google | Self::Microsoft | Self::Oidc => ClientAuth::Body,
+        }
+    }
+
+    /// Used when neither the request nor the provider config name any scopes.
+    pub fn default_scopes(&self) -> Vec<String> {
+        let scopes: &[&str] = match self {
+            Self::Google => &["calendar"],
+            Self::Microsoft => &["https://graph.microsoft.com/Calendars.ReadWrite"],
+            // zoom scopes are configured on the app, not requested.
+            Self::Zoom => &[],
+            Self::Oidc => &["openid"],
+        };
+        scopes.iter().map(|s| s.to_string()).collect()
+    }
+
+    pub fn expand_scope(&self, scope: &str) -> anyhow::Result<String> {
+        match self {
+            Self::Google if GOOGLE_SCOPES.contains(&scope) => {
+                Ok(format!("{}{}", GOOGLE_SCOPE_PREFIX, scope))
+            }
+            Self::Google
+                if !scope.starts_with("https://")
+                    && !matches!(scope, "openid" | "email" | "profile") =>
+            {
+                Err(anyhow::anyhow!("unknown scope: {}", scope))
+            }
+            _ => Ok(scope.to_string()),
+        }
+    }
+
+    /// Scopes that have to be requested for a refresh token to be handed out at all.
+    pub fn offline_scopes(&self) -> &'static [&'static str] {
+        match self {
+            Self::Microsoft | Self::Oidc => &["offline_access"],
+            Self::Google | Self::Zoom => &[],
+        }
+    }
+
+    /// Extra authorization url parameters. Consent is only forced when we don't
+    /// have a refresh token yet, that's the only time it's needed to get one.
+    pub fn auth_params(&self, needs_consent: bool) -> Vec<(&'static str, &'static str)> {
+        let mut params = match self {
+            // incremental auth, previously granted scopes stay granted.
+            Self::Google => vec![
+                ("access_type", "offline"),
+                ("include_granted_scopes", "true"),
+            ],
+            Self::Microsoft | Self::Zoom | Self::Oidc => vec![],
+        };
+        if needs_consent && *self != Self::Zoom {
+            params.push(("prompt", "consent"));
+        }
+        params
+    }
+}
@@ -0,0 +1,193 @@
+//! Credentials at rest, shared by the client and oauth processes, which both
+//! include this file as their `secrets` module. Values are encrypted with
+//! ChaCha20-Poly1305 under a key kept in the calling process's vfs drive, so
+//! `set_state` never sees them in plaintext.
+//!
+//! Fields opt in with `#[serde(with = "secrets::option")]` or
+//! `#[serde(with = "secrets::string")]`. Values saved before encryption existed
+//! are read as plaintext and encrypted on the next save. Values that can't be
+//! decrypted, e.g. after the key was lost, read as empty so the rest of the
+//! state still loads, and are counted for `take_failures`.
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use kinode_process_lib::{println, vfs, Address};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    OnceLock,
+};
+
+const PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 12;
+
+static CIPHER: OnceLock<ChaCha20Poly1305> = OnceLock::new();
+// values read as empty since the last `take_failures`
+static FAILURES: AtomicUsize = AtomicUsize::new(0);
+
+/// Loads the node-local key, creating it on first run. Has to run before state is loaded.
+pub fn init(our: &Address) -> anyhow::Result<()> {
+    let drive = vfs::create_drive(our.package_id(), "secrets", None)?;
+    let file = vfs::open_file(&format!("{}/{}.key", drive, our.process()), true, None)?;
+    let mut key = file.read()?;
+    if key.is_empty() {
+        key = ChaCha20Poly1305::generate_key(&mut OsRng).to_vec();
+        file.write(&key)?;
+    }
+    if key.len() != 32 {
+        return Err(anyhow::anyhow!("corrupt secrets key in {}", file.path));
+    }
+    let _ = CIPHER.set(ChaCha20Poly1305::new(Key::from_slice(&key)));
+    Ok(())
+}
+
+fn cipher() -> anyhow::Result<&'static ChaCha20Poly1305> {
+    CIPHER
+        .get()
+        .ok_or_else(|| anyhow::anyhow!("secrets key not loaded"))
+}
+
+pub fn encrypt(plaintext: &str) -> anyhow::Result<String> {
+    seal(cipher()?, plaintext)
+}
+
+fn seal(cipher: &ChaCha20Poly1305, plaintext: &str) -> anyhow::Result<String> {
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext.as_bytes())
+        .map_err(|_| anyhow::anyhow!("failed to encrypt secret"))?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    Ok(format!("{}{}", PREFIX, STANDARD.encode(sealed)))
+}
+
+pub fn decrypt(value: &str) -> anyhow::Result<String> {
+    let Some(encoded) = value.strip_prefix(PREFIX) else {
+        // saved before encryption
+        return Ok(value.to_string());
+    };
+    let sealed = STANDARD.decode(encoded)?;
+    if sealed.len() < NONCE_LEN {
+        return Err(anyhow::anyhow!("truncated secret"));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let plaintext = cipher()?
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow::anyhow!("failed to decrypt secret, wrong key?"))?;
+    Ok(String::from_utf8(plaintext)?)
+}
+
+/// How many saved values couldn't be decrypted since last asked.
+pub fn take_failures() -> usize {
+    FAILURES.swap(0, Ordering::Relaxed)
+}
+
+/// `value` decrypted, or `None` if that fails.
+fn decrypt_or_forget(value: &str) -> Option<String> {
+    match decrypt(value) {
+        Ok(plaintext) => Some(plaintext),
+        Err(e) => {
+            println!("secrets: dropping a saved value: {:?}", e);
+            FAILURES.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+}
+
+/// `#[serde(with = "secrets::option")]` for `Option<String>` fields.
+// each process only has one kind of secret field
+#[allow(dead_code)]
+pub mod option {
+    use serde::{ser::Error as _, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<String>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => {
+                let sealed = super::encrypt(value).map_err(S::Error::custom)?;
+                serializer.serialize_some(&sealed)
+            }
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<String>, D::Error> {
+        Ok(Option::<String>::deserialize(deserializer)?
+            .and_then(|value| super::decrypt_or_forget(&value)))
+    }
+}
+
+/// `#[serde(with = "secrets::string")]` for `String` fields.
+#[allow(dead_code)]
+pub mod string {
+    use serde::{ser::Error as _, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &str, serializer: S) -> Result<S::Ok, S::Error> {
+        let sealed = super::encrypt(value).map_err(S::Error::custom)?;
+        serializer.serialize_str(&sealed)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Ok(super::decrypt_or_forget(&value).unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Saved {
+        #[serde(default, with = "option")]
+        token: Option<String>,
+        #[serde(with = "string")]
+        secret: String,
+        user_id: u64,
+    }
+
+    fn key(byte: u8) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&[byte; 32]))
+    }
+
+    #[test]
+    fn a_wrong_key_only_loses_the_secrets() {
+        let _ = CIPHER.set(key(1));
+        let saved = Saved {
+            token: Some("token".to_string()),
+            secret: "secret".to_string(),
+            user_id: 7,
+        };
+        let json = serde_json::to_string(&saved).unwrap();
+        assert!(
+            !json.contains(":\"token\"") && !json.contains(":\"secret\""),
+            "{}",
+            json
+        );
+        assert_eq!(serde_json::from_str::<Saved>(&json).unwrap(), saved);
+
+        // sealed under a key that has since been lost
+        let lost = serde_json::json!({
+            "token": seal(&key(2), "token").unwrap(),
+            "secret": seal(&key(2), "secret").unwrap(),
+            "user_id": 7,
+        });
+        take_failures();
+        let read: Saved = serde_json::from_value(lost).unwrap();
+        assert_eq!(read.token, None);
+        assert_eq!(read.secret, "");
+        assert_eq!(read.user_id, 7);
+        assert_eq!(take_failures(), 2);
+
+        // saved before encryption
+        let plain = serde_json::json!({ "token": "token", "secret": "secret", "user_id": 7 });
+        assert_eq!(serde_json::from_value::<Saved>(plain).unwrap(), saved);
+        assert_eq!(take_failures(), 0);
+    }
+}